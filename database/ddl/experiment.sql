@@ -0,0 +1,34 @@
+DROP TABLE IF EXISTS demo.experiment;
+DROP TABLE IF EXISTS demo.experiment_stage;
+DROP TABLE IF EXISTS demo.measurement;
+
+CREATE TABLE demo.experiment (
+    experiment_id TEXT,
+    researcher TEXT NOT NULL,
+    sensors TEXT[] NOT NULL,
+    lower_threshold REAL NOT NULL,
+    upper_threshold REAL NOT NULL,
+    interrupted BOOLEAN,
+    PRIMARY KEY(experiment_id)
+);
+
+CREATE TABLE demo.experiment_stage (
+    experiment_id TEXT,
+    stage TEXT,
+    timestamp DOUBLE PRECISION NOT NULL,
+    PRIMARY KEY(experiment_id, stage)
+);
+
+CREATE TABLE demo.measurement (
+    experiment_id TEXT,
+    measurement_id TEXT,
+    stage TEXT NOT NULL,
+    timestamp DOUBLE PRECISION NOT NULL,
+    temperature REAL NOT NULL,
+    notification_type TEXT,
+    PRIMARY KEY(experiment_id, measurement_id)
+);
+
+GRANT SELECT
+ON demo.experiment, demo.experiment_stage, demo.measurement
+TO grafanareader;
@@ -11,10 +11,41 @@ CREATE TABLE demo.notification (
 CREATE TABLE demo.notification_ground_truth (
     experiment_id TEXT,
     measurement_id TEXT, 
+    notification_type TEXT NOT NULL,
+    measurement_timestamp DOUBLE PRECISION NOT NULL,
+    researcher TEXT NOT NULL,
+    topic TEXT NOT NULL,
     insert_timestamp TIMESTAMP DEFAULT now(),
     PRIMARY KEY(experiment_id, measurement_id)
 );
 
+CREATE TABLE demo.experiment (
+    experiment_id TEXT,
+    researcher TEXT NOT NULL,
+    sensors TEXT[] NOT NULL,
+    lower_threshold REAL NOT NULL,
+    upper_threshold REAL NOT NULL,
+    interrupted BOOLEAN,
+    PRIMARY KEY(experiment_id)
+);
+
+CREATE TABLE demo.experiment_stage (
+    experiment_id TEXT,
+    stage TEXT,
+    timestamp DOUBLE PRECISION NOT NULL,
+    PRIMARY KEY(experiment_id, stage)
+);
+
+CREATE TABLE demo.measurement (
+    experiment_id TEXT,
+    measurement_id TEXT,
+    stage TEXT NOT NULL,
+    timestamp DOUBLE PRECISION NOT NULL,
+    temperature REAL NOT NULL,
+    notification_type TEXT,
+    PRIMARY KEY(experiment_id, measurement_id)
+);
+
 CREATE USER grafanareader WITH PASSWORD '***';
 
 GRANT USAGE ON SCHEMA demo TO grafanareader;
@@ -3,6 +3,10 @@ DROP TABLE demo.notification_ground_truth;
 CREATE TABLE demo.notification_ground_truth (
     experiment_id TEXT,
     measurement_id TEXT, 
+    notification_type TEXT NOT NULL,
+    measurement_timestamp DOUBLE PRECISION NOT NULL,
+    researcher TEXT NOT NULL,
+    topic TEXT NOT NULL,
     insert_timestamp TIMESTAMP DEFAULT now(),
     PRIMARY KEY(experiment_id, measurement_id)
 );
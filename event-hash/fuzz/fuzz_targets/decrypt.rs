@@ -0,0 +1,15 @@
+#![no_main]
+
+use event_hash::{AssociatedData, HashData, Keyring};
+use libfuzzer_sys::fuzz_target;
+
+const ASSOCIATED_DATA: AssociatedData = AssociatedData {
+    topic: "experiment",
+    experiment_id: "5678",
+    schema_version: 2,
+};
+
+fuzz_target!(|hash_data: &str| {
+    let keyring = Keyring::single("QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh").unwrap();
+    let _ = HashData::decrypt(&keyring, Some("group0"), ASSOCIATED_DATA, hash_data);
+});
@@ -0,0 +1,23 @@
+#![no_main]
+
+use event_hash::{generate_keys, AssociatedData, HashData, Opener};
+use libfuzzer_sys::fuzz_target;
+use std::sync::OnceLock;
+
+const ASSOCIATED_DATA: AssociatedData = AssociatedData {
+    topic: "experiment",
+    experiment_id: "5678",
+    schema_version: 2,
+};
+
+fn opener() -> &'static Opener {
+    static OPENER: OnceLock<Opener> = OnceLock::new();
+    OPENER.get_or_init(|| {
+        let (_, opener) = generate_keys("producer").unwrap();
+        Opener::from_json(&opener).unwrap()
+    })
+}
+
+fuzz_target!(|hash_data: &str| {
+    let _ = HashData::open(opener(), Some("group0"), ASSOCIATED_DATA, hash_data);
+});
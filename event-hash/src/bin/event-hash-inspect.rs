@@ -0,0 +1,474 @@
+use clap::{command, value_parser, Arg, ArgAction, ArgMatches, Command};
+use event_hash::{
+    generate_keys, generate_producer_keys, is_sealed, key_id, AssociatedData, DecryptError,
+    HashData, Keyring, Opener,
+};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    process::ExitCode,
+};
+
+/// Line of NDJSON carrying a hash: a `/notify` body, or a `sensor_temperature_measured` event
+/// of a topic dump. Fields other than the hash and its context are ignored.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+struct Entry {
+    #[serde(alias = "measurement_hash")]
+    cipher_data: Option<String>,
+    #[serde(alias = "experiment")]
+    experiment_id: Option<String>,
+    topic: Option<String>,
+    schema_version: Option<u32>,
+}
+
+impl Entry {
+    /// Reads a line of NDJSON, or a bare hash. `None` for blank lines and events without a hash.
+    fn parse(line: &str) -> Result<Option<Self>, serde_json::Error> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(None);
+        }
+        let entry = if line.starts_with('{') {
+            serde_json::from_str(line)?
+        } else {
+            Entry {
+                cipher_data: Some(line.into()),
+                ..Default::default()
+            }
+        };
+        Ok(entry.cipher_data.is_some().then_some(entry))
+    }
+}
+
+/// Decrypts hashes in the context given on the command line, unless their entry carries its own.
+struct Inspector {
+    keyring: Keyring,
+    opener: Option<Opener>,
+    group: Option<String>,
+    topic: String,
+    experiment_id: Option<String>,
+    schema_version: u32,
+}
+
+impl Inspector {
+    fn from_args(args: &mut ArgMatches) -> Self {
+        let keyring = match (
+            args.remove_one::<String>("keyring"),
+            args.remove_one::<String>("secret-key"),
+        ) {
+            (Some(path), _) => Keyring::from_file(&path),
+            (None, Some(secret_key)) => Keyring::single(&secret_key),
+            (None, None) => Ok(Keyring::default()),
+        }
+        .unwrap_or_else(|e| panic!("Invalid keyring: {}", e));
+        let opener = args.remove_one::<String>("sealing-keys").map(|path| {
+            Opener::from_file(&path).unwrap_or_else(|e| panic!("Invalid sealing keys: {}", e))
+        });
+        Self {
+            keyring,
+            opener,
+            group: args.remove_one::<String>("group"),
+            topic: args.remove_one::<String>("topic").expect("Defaulted"),
+            experiment_id: args.remove_one::<String>("experiment-id"),
+            schema_version: args.remove_one::<u32>("schema-version").expect("Defaulted"),
+        }
+    }
+
+    fn decrypt(&self, entry: &Entry) -> Result<HashData, DecryptError> {
+        let associated_data = AssociatedData {
+            topic: entry.topic.as_deref().unwrap_or(&self.topic),
+            experiment_id: entry
+                .experiment_id
+                .as_deref()
+                .or(self.experiment_id.as_deref())
+                .unwrap_or_default(),
+            schema_version: entry.schema_version.unwrap_or(self.schema_version),
+        };
+        let cipher_data = entry.cipher_data.as_deref().unwrap_or_default();
+        match &self.opener {
+            Some(opener) if is_sealed(cipher_data) => {
+                HashData::open(opener, self.group.as_deref(), associated_data, cipher_data)
+            }
+            _ => HashData::decrypt(
+                &self.keyring,
+                self.group.as_deref(),
+                associated_data,
+                cipher_data,
+            ),
+        }
+    }
+}
+
+fn input(path: Option<&str>) -> Box<dyn BufRead> {
+    match path {
+        Some("-") | None => Box::new(io::stdin().lock()),
+        Some(path) => Box::new(BufReader::new(
+            File::open(path).unwrap_or_else(|e| panic!("Unable to open {}: {}", path, e)),
+        )),
+    }
+}
+
+fn entries(lines: impl Iterator<Item = String>) -> impl Iterator<Item = (usize, Entry)> {
+    lines
+        .enumerate()
+        .filter_map(|(idx, line)| match Entry::parse(&line) {
+            Ok(entry) => entry.map(|entry| (idx + 1, entry)),
+            Err(e) => {
+                eprintln!("line {}: not a hash nor a JSON object: {}", idx + 1, e);
+                None
+            }
+        })
+}
+
+/// Prints every field of the hashes, or why they do not decrypt. Fails if any does not.
+fn decrypt(inspector: &Inspector, args: &ArgMatches) -> ExitCode {
+    let lines: Box<dyn Iterator<Item = String>> = match args.get_many::<String>("hash") {
+        Some(hashes) => Box::new(hashes.cloned()),
+        None => Box::new(input(None).lines().map_while(Result::ok)),
+    };
+    let mut failed = false;
+    for (_, entry) in entries(lines) {
+        let cipher_data = entry.cipher_data.as_deref().unwrap_or_default();
+        println!("{}", cipher_data);
+        if let Ok(key_id) = key_id(cipher_data) {
+            println!("  key_id: {}", key_id);
+        }
+        match inspector.decrypt(&entry) {
+            Ok(hash_data) => {
+                println!("  notification_type: {:?}", hash_data.notification_type);
+                println!("  researcher: {:?}", hash_data.researcher);
+                println!("  experiment_id: {}", hash_data.experiment_id);
+                println!("  measurement_id: {}", hash_data.measurement_id);
+                println!("  timestamp: {}", hash_data.timestamp);
+                println!("  sequence: {:?}", hash_data.sequence);
+            }
+            Err(e) => {
+                failed = true;
+                println!("  error: {}", e);
+            }
+        }
+    }
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Measurements of a dump that expected a notification, per experiment in timestamp order.
+fn expected_notifications(
+    inspector: &Inspector,
+    entries: impl Iterator<Item = (usize, Entry)>,
+) -> (BTreeMap<String, Vec<HashData>>, usize) {
+    let mut experiments: BTreeMap<String, Vec<HashData>> = BTreeMap::new();
+    let mut failed = 0;
+    for (line, entry) in entries {
+        match inspector.decrypt(&entry) {
+            Ok(hash_data) if hash_data.notification_type.is_some() => experiments
+                .entry(hash_data.experiment_id.clone())
+                .or_default()
+                .push(hash_data),
+            Ok(_) => {}
+            Err(e) => {
+                failed += 1;
+                eprintln!("line {}: {}", line, e);
+            }
+        }
+    }
+    for measurements in experiments.values_mut() {
+        measurements.sort_by(|a, b| a.timestamp.total_cmp(&b.timestamp));
+    }
+    (experiments, failed)
+}
+
+fn scan(inspector: &Inspector, args: &ArgMatches) -> ExitCode {
+    let lines = input(args.get_one::<String>("dump").map(String::as_str))
+        .lines()
+        .map_while(Result::ok);
+    let (experiments, failed) = expected_notifications(inspector, entries(lines));
+    for (experiment_id, measurements) in &experiments {
+        println!("experiment {}", experiment_id);
+        for hash_data in measurements {
+            // Hashes from before sequence numbers are listed without
+            let sequence = hash_data
+                .sequence
+                .map_or("-".into(), |sequence| sequence.to_string());
+            println!(
+                "  {} {} {} {:?}",
+                sequence,
+                hash_data.timestamp,
+                hash_data.measurement_id,
+                hash_data
+                    .notification_type
+                    .as_ref()
+                    .expect("Expected a notification")
+            );
+        }
+    }
+    if failed > 0 {
+        eprintln!("{} hashes did not decrypt", failed);
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Writes new key files of the sealed mode, never overwriting an existing sealer. An existing
+/// opener is rewritten to trust the new producer too.
+fn keygen(args: &ArgMatches) -> ExitCode {
+    let signer_id = args.get_one::<String>("signer-id").expect("Required");
+    let sealer_path = args.get_one::<String>("sealer").expect("Required");
+    let opener_path = args.get_one::<String>("opener").expect("Required");
+    let existing = match fs::read_to_string(opener_path) {
+        Ok(opener) => Some(opener),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => {
+            eprintln!("Unable to read {}: {}", opener_path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let keys = match &existing {
+        Some(opener) => generate_producer_keys(opener, signer_id),
+        None => generate_keys(signer_id),
+    };
+    let (sealer, opener) = match keys {
+        Ok(keys) => keys,
+        Err(e) => {
+            eprintln!("Unable to generate the keys of {}: {}", signer_id, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    // Claims the sealer path first, then writes the opener: a sealer is only ever written once
+    // its producer can be opened, the sealer left empty is removed otherwise.
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    let mut sealer_file = match options.open(sealer_path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Unable to write {}: {}", sealer_path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if existing.is_some() {
+        options.create_new(false).truncate(true);
+    }
+    let written = options
+        .open(opener_path)
+        .and_then(|mut file| writeln!(file, "{}", opener));
+    if let Err(e) = written {
+        eprintln!("Unable to write {}: {}", opener_path, e);
+        drop(sealer_file);
+        if let Err(e) = fs::remove_file(sealer_path) {
+            eprintln!("Unable to remove {}: {}", sealer_path, e);
+        }
+        return ExitCode::FAILURE;
+    }
+    if let Err(e) = writeln!(sealer_file, "{}", sealer) {
+        eprintln!("Unable to write {}: {}", sealer_path, e);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+fn main() -> ExitCode {
+    let mut matches = command!() // requires `cargo` feature
+        .about("Decrypts measurement hashes, to settle notification disputes")
+        .next_line_help(true)
+        .subcommand_required(true)
+        .arg(Arg::new("secret-key")
+            .required(false)
+            .long("secret-key")
+            .action(ArgAction::Set)
+            .help("<key> is the 32 character string passed to the notifications-service")
+        )
+        .arg(Arg::new("keyring")
+            .required(false)
+            .long("keyring")
+            .action(ArgAction::Set)
+            .conflicts_with("secret-key")
+            .help("<keyring> is the JSON file of keys passed to the notifications-service, instead of `--secret-key`")
+        )
+        .arg(Arg::new("sealing-keys")
+            .required(false)
+            .long("sealing-keys")
+            .action(ArgAction::Set)
+            .help("<sealing-keys> is the JSON file of keys passed to the notifications-service opening sealed hashes")
+        )
+        .arg(Arg::new("group")
+            .required(false)
+            .long("group")
+            .action(ArgAction::Set)
+            .help("Group the hashes are expected to be issued for, any group if left out")
+        )
+        .arg(Arg::new("topic")
+            .required(false)
+            .long("topic")
+            .action(ArgAction::Set)
+            .default_value("experiment")
+            .help("Topic the hashes were sent to, unless their entry has a `topic`")
+        )
+        .arg(Arg::new("experiment-id")
+            .required(false)
+            .long("experiment-id")
+            .action(ArgAction::Set)
+            .help("Experiment the hashes were issued for, unless their entry has an `experiment_id` or `experiment`")
+        )
+        .arg(Arg::new("schema-version")
+            .required(false)
+            .long("schema-version")
+            .action(ArgAction::Set)
+            .default_value("1")
+            .value_parser(value_parser!(u32))
+            .help("Schema version of the events carrying the hashes, unless their entry has a `schema_version`")
+        )
+        .subcommand(
+            Command::new("decrypt")
+                .about("Prints the fields of each hash, or why it does not decrypt")
+                .arg(Arg::new("hash")
+                    .action(ArgAction::Append)
+                    .help("`measurement_hash` or `cipher_data` to decrypt, read from stdin if left out: one hash or NDJSON entry per line")
+                ),
+        )
+        .subcommand(
+            Command::new("scan")
+                .about("Lists the measurements of a topic dump that expected a notification, per experiment")
+                .arg(Arg::new("dump")
+                    .action(ArgAction::Set)
+                    .help("NDJSON file of the events of a topic, read from stdin if left out or `-`")
+                ),
+        )
+        .subcommand(
+            Command::new("keygen")
+                .about("Generates the keys of a producer sealing hashes to the notifications-service")
+                .arg(Arg::new("signer-id")
+                    .required(true)
+                    .long("signer-id")
+                    .action(ArgAction::Set)
+                    .help("ID of the producer, named by the hashes it seals")
+                )
+                .arg(Arg::new("sealer")
+                    .required(true)
+                    .long("sealer")
+                    .action(ArgAction::Set)
+                    .help("File to write the `--sealing-keys` of the experiment-producer to")
+                )
+                .arg(Arg::new("opener")
+                    .required(true)
+                    .long("opener")
+                    .action(ArgAction::Set)
+                    .help("File to write the `--sealing-keys` of the notifications-service to. An existing one keeps its opening key and trusts the new producer along with the others")
+                ),
+        )
+        .get_matches();
+
+    let inspector = Inspector::from_args(&mut matches);
+    match matches.subcommand() {
+        Some(("decrypt", args)) => decrypt(&inspector, args),
+        Some(("scan", args)) => scan(&inspector, args),
+        Some(("keygen", args)) => keygen(args),
+        _ => unreachable!("Subcommand required"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use event_hash::NotificationType;
+
+    const SECRET_KEY: &str = "QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh";
+
+    fn inspector() -> Inspector {
+        Inspector {
+            keyring: Keyring::single(SECRET_KEY).unwrap(),
+            opener: None,
+            group: None,
+            topic: "experiment".into(),
+            experiment_id: None,
+            schema_version: 1,
+        }
+    }
+
+    fn hash(experiment_id: &str, measurement_id: &str, timestamp: f64) -> String {
+        let keyring = Keyring::single(SECRET_KEY).unwrap();
+        let hash_data = HashData {
+            notification_type: (measurement_id != "quiet").then_some(NotificationType::OutOfRange),
+            researcher: None,
+            experiment_id: experiment_id.into(),
+            measurement_id: measurement_id.into(),
+            timestamp,
+            sequence: None,
+        };
+        let associated_data = AssociatedData {
+            topic: "experiment",
+            experiment_id,
+            schema_version: 2,
+        };
+        hash_data.encrypt(keyring.key_at(0.0).unwrap(), associated_data)
+    }
+
+    #[test]
+    fn parse_entries() {
+        assert_eq!(
+            Entry::parse(" default.AAAA.BBBB\n").unwrap(),
+            Some(Entry {
+                cipher_data: Some("default.AAAA.BBBB".into()),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            Entry::parse(
+                r#"{"experiment": "5678", "measurement_hash": "a.b", "temperature": 1.0}"#
+            )
+            .unwrap(),
+            Some(Entry {
+                cipher_data: Some("a.b".into()),
+                experiment_id: Some("5678".into()),
+                ..Default::default()
+            })
+        );
+        assert_eq!(Entry::parse(r#"{"experiment": "5678"}"#).unwrap(), None);
+        assert_eq!(Entry::parse("").unwrap(), None);
+        assert!(Entry::parse("{not json").is_err());
+    }
+
+    #[test]
+    fn list_expected_notifications_per_experiment() {
+        let dump = [
+            format!(
+                r#"{{"experiment": "b", "measurement_hash": "{}", "topic": "experiment", "schema_version": 2}}"#,
+                hash("b", "2", 20.0)
+            ),
+            r#"{"experiment": "a", "researcher": "d.landau@uu.nl"}"#.into(),
+            format!(
+                r#"{{"experiment_id": "a", "cipher_data": "{}", "schema_version": 2}}"#,
+                hash("a", "3", 30.0)
+            ),
+            format!(
+                r#"{{"experiment": "a", "measurement_hash": "{}", "schema_version": 2}}"#,
+                hash("a", "quiet", 15.0)
+            ),
+            format!(
+                r#"{{"experiment": "a", "measurement_hash": "{}", "schema_version": 2}}"#,
+                hash("a", "1", 10.0)
+            ),
+            // Issued for another experiment
+            format!(
+                r#"{{"experiment": "a", "measurement_hash": "{}", "schema_version": 2}}"#,
+                hash("c", "4", 40.0)
+            ),
+        ];
+        let (experiments, failed) = expected_notifications(&inspector(), entries(dump.into_iter()));
+        let measurement_ids = |experiment_id: &str| {
+            experiments[experiment_id]
+                .iter()
+                .map(|hash_data| hash_data.measurement_id.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(measurement_ids("a"), ["1", "3"]);
+        assert_eq!(measurement_ids("b"), ["2"]);
+        assert_eq!(experiments.len(), 2);
+        assert_eq!(failed, 1);
+    }
+}
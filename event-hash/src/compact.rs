@@ -0,0 +1,211 @@
+//! Compact binary encoding of [`HashData`], a fraction of the size of its JSON.
+//!
+//! ```text
+//! version: u8 = 1
+//! notification_type: u8   0 none, 1 OutOfRange, 2 Stabilized
+//! timestamp: f64          big endian
+//! experiment_id: id
+//! measurement_id: id
+//! researcher: u8          0 absent, 1 followed by a string
+//! sequence: u8            0 absent, 1 followed by a big endian u32
+//!
+//! id: u8                  0 followed by the 16 bytes of a UUID, 1 followed by a string
+//! string: u16             big endian length, followed by as many UTF-8 bytes
+//! ```
+//!
+//! The version never collides with the `{` JSON plaintexts start with.
+
+use uuid::Uuid;
+
+use crate::{DecryptError, HashData, NotificationType};
+
+pub const VERSION: u8 = 1;
+
+const UUID_TAG: u8 = 0;
+const STRING_TAG: u8 = 1;
+
+pub fn encode(hash_data: &HashData) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(64);
+    bytes.push(VERSION);
+    bytes.push(match hash_data.notification_type {
+        None => 0,
+        Some(NotificationType::OutOfRange) => 1,
+        Some(NotificationType::Stabilized) => 2,
+    });
+    bytes.extend_from_slice(&hash_data.timestamp.to_be_bytes());
+    encode_id(&mut bytes, &hash_data.experiment_id);
+    encode_id(&mut bytes, &hash_data.measurement_id);
+    match &hash_data.researcher {
+        None => bytes.push(0),
+        Some(researcher) => {
+            bytes.push(1);
+            encode_string(&mut bytes, researcher);
+        }
+    }
+    match hash_data.sequence {
+        None => bytes.push(0),
+        Some(sequence) => {
+            bytes.push(1);
+            bytes.extend_from_slice(&sequence.to_be_bytes());
+        }
+    }
+    bytes
+}
+
+/// UUIDs in their canonical form take 16 bytes, any other ID is kept as a string so that it
+/// decodes to the exact same ID.
+fn encode_id(bytes: &mut Vec<u8>, id: &str) {
+    match Uuid::try_parse(id) {
+        Ok(uuid) if uuid.hyphenated().to_string() == id => {
+            bytes.push(UUID_TAG);
+            bytes.extend_from_slice(uuid.as_bytes());
+        }
+        _ => {
+            bytes.push(STRING_TAG);
+            encode_string(bytes, id);
+        }
+    }
+}
+
+/// Strings longer than `u16::MAX` bytes are truncated, IDs and emails never are.
+fn encode_string(bytes: &mut Vec<u8>, s: &str) {
+    let mut len = s.len().min(u16::MAX as usize);
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+    bytes.extend_from_slice(&(len as u16).to_be_bytes());
+    bytes.extend_from_slice(&s.as_bytes()[..len]);
+}
+
+pub fn decode(bytes: &[u8]) -> Result<HashData, DecryptError> {
+    let mut reader = Reader(bytes);
+    if reader.u8()? != VERSION {
+        return Err(DecryptError::CompactDecodingError);
+    }
+    let notification_type = match reader.u8()? {
+        0 => None,
+        1 => Some(NotificationType::OutOfRange),
+        2 => Some(NotificationType::Stabilized),
+        _ => return Err(DecryptError::CompactDecodingError),
+    };
+    let timestamp = f64::from_be_bytes(reader.array()?);
+    let experiment_id = reader.id()?;
+    let measurement_id = reader.id()?;
+    let researcher = match reader.u8()? {
+        0 => None,
+        1 => Some(reader.string()?),
+        _ => return Err(DecryptError::CompactDecodingError),
+    };
+    let sequence = match reader.u8()? {
+        0 => None,
+        1 => Some(u32::from_be_bytes(reader.array()?)),
+        _ => return Err(DecryptError::CompactDecodingError),
+    };
+    if !reader.0.is_empty() {
+        return Err(DecryptError::CompactDecodingError);
+    }
+    Ok(HashData {
+        notification_type,
+        researcher,
+        experiment_id,
+        measurement_id,
+        timestamp,
+        sequence,
+    })
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecryptError> {
+        if self.0.len() < len {
+            return Err(DecryptError::CompactDecodingError);
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], DecryptError> {
+        Ok(self.take(N)?.try_into().expect("Took N bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8, DecryptError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn string(&mut self) -> Result<String, DecryptError> {
+        let len = u16::from_be_bytes(self.array()?);
+        let bytes = self.take(len as usize)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecryptError::Utf8DecodingError)
+    }
+
+    fn id(&mut self) -> Result<String, DecryptError> {
+        match self.u8()? {
+            UUID_TAG => Ok(Uuid::from_bytes(self.array()?).hyphenated().to_string()),
+            STRING_TAG => self.string(),
+            _ => Err(DecryptError::CompactDecodingError),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash_data() -> HashData {
+        HashData {
+            notification_type: Some(NotificationType::Stabilized),
+            researcher: Some("d.landau@uu.nl".into()),
+            experiment_id: "5e2f8ebd-bcc2-4a0b-9b3c-1c4e2b1a7f3d".into(),
+            measurement_id: "1234".into(),
+            timestamp: 1692029115.4314,
+            sequence: Some(1),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for hash_data in [
+            hash_data(),
+            HashData {
+                notification_type: None,
+                researcher: None,
+                sequence: None,
+                // Not in canonical form, kept as is
+                measurement_id: "5E2F8EBD-BCC2-4A0B-9B3C-1C4E2B1A7F3D".into(),
+                ..hash_data()
+            },
+        ] {
+            assert_eq!(decode(&encode(&hash_data)).unwrap(), hash_data);
+        }
+    }
+
+    #[test]
+    fn smaller_than_json() {
+        let hash_data = HashData {
+            measurement_id: "0b6a3cf4-52e1-4bd5-8d57-e4b6bbf0f6f1".into(),
+            ..hash_data()
+        };
+        let compact = encode(&hash_data);
+        assert_eq!(compact.len(), 1 + 1 + 8 + 17 + 17 + 1 + 2 + 14 + 1 + 4);
+        assert!(compact.len() * 3 < serde_json::to_vec(&hash_data).unwrap().len());
+    }
+
+    #[test]
+    fn reject_malformed_encodings() {
+        let compact = encode(&hash_data());
+        for len in 0..compact.len() {
+            assert!(decode(&compact[..len]).is_err());
+        }
+        let mut trailing = compact.clone();
+        trailing.push(0);
+        assert!(decode(&trailing).is_err());
+        let mut version = compact.clone();
+        version[0] = VERSION + 1;
+        assert!(decode(&version).is_err());
+        let mut notification_type = compact;
+        notification_type[1] = 3;
+        assert!(decode(&notification_type).is_err());
+    }
+}
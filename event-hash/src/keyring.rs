@@ -0,0 +1,296 @@
+use aes_gcm::{Aes256Gcm, Key};
+use hkdf::Hkdf;
+use serde::Deserialize;
+use sha2::Sha256;
+use std::{fs, io};
+
+pub const KEY_LEN: usize = 32;
+
+/// ID of the key `--secret-key` is given, and of the key decrypting hashes without a key ID.
+pub const DEFAULT_KEY_ID: &str = "default";
+
+/// HKDF info of the key of a group, followed by the group ID.
+const GROUP_KEY_INFO: &[u8] = b"event-hash group key ";
+
+#[derive(Debug)]
+pub enum KeyringError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// Key IDs are non-empty and made of ASCII letters, digits, `-` and `_`.
+    InvalidKeyId(String),
+    InvalidKeyLength(String),
+    /// Keys of the sealed mode are base64 encoded.
+    InvalidKeyEncoding(String),
+    DuplicateKeyId(String),
+    /// Group IDs are made of the same characters as key IDs.
+    InvalidGroupId(String),
+    /// Group keys are derived from master keys only.
+    AlreadyDerived(String),
+}
+
+impl std::error::Error for KeyringError {}
+
+impl std::fmt::Display for KeyringError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Master key of the keyring, or the key of a group derived from it.
+#[derive(Clone)]
+pub struct SecretKey {
+    id: String,
+    group: Option<String>,
+    key: Key<Aes256Gcm>,
+}
+
+impl SecretKey {
+    pub fn new(id: &str, key: &[u8]) -> Result<Self, KeyringError> {
+        if !is_valid_id(id) {
+            return Err(KeyringError::InvalidKeyId(id.into()));
+        }
+        if key.len() != KEY_LEN {
+            return Err(KeyringError::InvalidKeyLength(id.into()));
+        }
+        Ok(Self {
+            id: id.into(),
+            group: None,
+            key: *Key::<Aes256Gcm>::from_slice(key),
+        })
+    }
+
+    /// Key of `group`, derived from this master key with HKDF-SHA256. Hashes encrypted with it
+    /// decrypt for that group only.
+    pub fn for_group(&self, group: &str) -> Result<Self, KeyringError> {
+        if self.group.is_some() {
+            return Err(KeyringError::AlreadyDerived(self.id.clone()));
+        }
+        if !is_valid_id(group) {
+            return Err(KeyringError::InvalidGroupId(group.into()));
+        }
+        let mut key = Key::<Aes256Gcm>::default();
+        Hkdf::<Sha256>::new(None, &self.key)
+            .expand_multi_info(&[GROUP_KEY_INFO, group.as_bytes()], &mut key)
+            .expect("Key length is a valid HKDF-SHA256 output length");
+        Ok(Self {
+            id: self.id.clone(),
+            group: Some(group.into()),
+            key,
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Group the key was derived for, `None` for a master key.
+    pub fn group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+
+    pub(crate) fn key(&self) -> &Key<Aes256Gcm> {
+        &self.key
+    }
+}
+
+/// Leaves the key itself out of logs.
+impl std::fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SecretKey")
+            .field("id", &self.id)
+            .field("group", &self.group)
+            .finish()
+    }
+}
+
+pub(crate) fn is_valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyState {
+    Active,
+    /// No longer encrypts, still decrypts hashes in flight.
+    Retired,
+}
+
+#[derive(Deserialize)]
+struct KeyEntry {
+    id: String,
+    key: String,
+    /// Unix timestamp the producer starts encrypting with the key.
+    #[serde(default)]
+    active_from: f64,
+    #[serde(default)]
+    retired: bool,
+}
+
+#[derive(Clone, Debug)]
+struct ScheduledKey {
+    key: SecretKey,
+    active_from: f64,
+    state: KeyState,
+}
+
+/// Keys shared by the experiment-producer and the notifications-service, read from a JSON file
+/// such as
+///
+/// ```json
+/// [
+///     { "id": "2023-fall", "key": "QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh", "retired": true },
+///     { "id": "2024-spring", "key": "hEDYf0ikoGyFwu8mNFPl3VrqsZpHrBXi", "active_from": 1706745600 }
+/// ]
+/// ```
+///
+/// The producer encrypts with the active key scheduled last, the notifications-service decrypts
+/// with whichever key a hash names. Rotating adds the next key with its `active_from` ahead of
+/// time, and retires the previous one once every service has the new keyring. Retired keys can
+/// be removed once no notification encrypted with them is in flight.
+#[derive(Clone, Debug, Default)]
+pub struct Keyring {
+    /// Sorted by `active_from`.
+    keys: Vec<ScheduledKey>,
+}
+
+impl Keyring {
+    /// Keyring of the single active key `secret_key`, as given by `--secret-key`.
+    pub fn single(secret_key: &str) -> Result<Self, KeyringError> {
+        Ok(Self {
+            keys: vec![ScheduledKey {
+                key: SecretKey::new(DEFAULT_KEY_ID, secret_key.as_bytes())?,
+                active_from: 0.0,
+                state: KeyState::Active,
+            }],
+        })
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, KeyringError> {
+        let contents = fs::read_to_string(path).map_err(KeyringError::Io)?;
+        Self::from_json(&contents)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, KeyringError> {
+        let entries: Vec<KeyEntry> = serde_json::from_str(json).map_err(KeyringError::Json)?;
+        let mut keys: Vec<ScheduledKey> = Vec::with_capacity(entries.len());
+        for entry in entries {
+            if keys.iter().any(|scheduled| scheduled.key.id == entry.id) {
+                return Err(KeyringError::DuplicateKeyId(entry.id));
+            }
+            keys.push(ScheduledKey {
+                key: SecretKey::new(&entry.id, entry.key.as_bytes())?,
+                active_from: entry.active_from,
+                state: if entry.retired {
+                    KeyState::Retired
+                } else {
+                    KeyState::Active
+                },
+            });
+        }
+        keys.sort_by(|a, b| a.active_from.total_cmp(&b.active_from));
+        Ok(Self { keys })
+    }
+
+    /// Keyring of the keys of `group`, derived from every key of this keyring.
+    pub fn for_group(&self, group: &str) -> Result<Self, KeyringError> {
+        let keys = self
+            .keys
+            .iter()
+            .map(|scheduled| {
+                Ok(ScheduledKey {
+                    key: scheduled.key.for_group(group)?,
+                    ..scheduled.clone()
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { keys })
+    }
+
+    pub fn get(&self, id: &str) -> Option<(&SecretKey, KeyState)> {
+        self.keys
+            .iter()
+            .find(|scheduled| scheduled.key.id == id)
+            .map(|scheduled| (&scheduled.key, scheduled.state))
+    }
+
+    /// Key encrypting at `timestamp`: the active key scheduled last before it. `None` before the
+    /// first active key is scheduled.
+    pub fn key_at(&self, timestamp: f64) -> Option<&SecretKey> {
+        self.keys
+            .iter()
+            .rev()
+            .find(|scheduled| {
+                scheduled.state == KeyState::Active && scheduled.active_from <= timestamp
+            })
+            .map(|scheduled| &scheduled.key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYRING: &str = r#"[
+        { "id": "2024-spring", "key": "hEDYf0ikoGyFwu8mNFPl3VrqsZpHrBXi", "active_from": 200 },
+        { "id": "2023-fall", "key": "QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh", "retired": true },
+        { "id": "2023-spring", "key": "Jm1qQ9bV7cWkR2xT4yU6iO8pA0sD3fGh", "active_from": 100 }
+    ]"#;
+
+    #[test]
+    fn schedule_active_keys() {
+        let keyring = Keyring::from_json(KEYRING).unwrap();
+        assert!(keyring.key_at(99.0).is_none());
+        assert_eq!(keyring.key_at(100.0).unwrap().id(), "2023-spring");
+        assert_eq!(keyring.key_at(199.9).unwrap().id(), "2023-spring");
+        assert_eq!(keyring.key_at(200.0).unwrap().id(), "2024-spring");
+        assert_eq!(keyring.get("2023-fall").unwrap().1, KeyState::Retired);
+        assert!(keyring.get("2022-fall").is_none());
+    }
+
+    #[test]
+    fn reject_invalid_keys() {
+        assert!(matches!(
+            Keyring::single("too short"),
+            Err(KeyringError::InvalidKeyLength(_))
+        ));
+        assert!(matches!(
+            SecretKey::new("2023.fall", b"QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh"),
+            Err(KeyringError::InvalidKeyId(_))
+        ));
+        assert!(matches!(
+            Keyring::from_json(
+                r#"[
+                    { "id": "a", "key": "QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh" },
+                    { "id": "a", "key": "hEDYf0ikoGyFwu8mNFPl3VrqsZpHrBXi" }
+                ]"#
+            ),
+            Err(KeyringError::DuplicateKeyId(_))
+        ));
+    }
+
+    #[test]
+    fn derive_distinct_keys_per_group() {
+        let keyring = Keyring::from_json(KEYRING).unwrap();
+        let (master, _) = keyring.get("2023-spring").unwrap();
+        let group0 = master.for_group("group0").unwrap();
+        let group1 = master.for_group("group1").unwrap();
+        assert_eq!(group0.group(), Some("group0"));
+        assert_eq!(group0.key, master.for_group("group0").unwrap().key);
+        assert_ne!(group0.key, group1.key);
+        assert_ne!(group0.key, master.key);
+        assert!(matches!(
+            group0.for_group("group1"),
+            Err(KeyringError::AlreadyDerived(_))
+        ));
+        assert!(matches!(
+            master.for_group("group.0"),
+            Err(KeyringError::InvalidGroupId(_))
+        ));
+
+        let group_keyring = keyring.for_group("group0").unwrap();
+        assert_eq!(group_keyring.key_at(100.0).unwrap().key, group0.key);
+        assert_eq!(group_keyring.get("2023-fall").unwrap().1, KeyState::Retired);
+    }
+}
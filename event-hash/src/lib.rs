@@ -1,35 +1,100 @@
 use aes_gcm::{
-    aead::{Aead, AeadCore, KeyInit, OsRng},
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
     Aes256Gcm,
-    Key, // Or `Aes128Gcm`
 };
 use base64::{engine::general_purpose, Engine as _};
 use generic_array::GenericArray;
 use serde::{Deserialize, Serialize};
 
+mod compact;
+mod keyring;
+mod sealed;
+
+pub use keyring::{KeyState, Keyring, KeyringError, SecretKey, DEFAULT_KEY_ID, KEY_LEN};
+pub use sealed::{generate_keys, generate_producer_keys, is_sealed, Opener, Sealer};
+
+/// Longest hash decrypted, well above the length of any hash the producer encrypts.
+pub const MAX_HASH_DATA_LEN: usize = 4096;
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub enum NotificationType {
     OutOfRange,
     Stabilized,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct HashData {
     pub notification_type: Option<NotificationType>,
-    pub researcher: String,
+    /// Left out of the hash to keep it small, the researcher is then not checked.
+    pub researcher: Option<String>,
     pub experiment_id: String,
     pub measurement_id: String,
     pub timestamp: f64,
+    /// Number of the transition, counted per experiment from 1, for measurements expecting a
+    /// notification. Hashes from before sequence numbers have none.
+    #[serde(default)]
+    pub sequence: Option<u32>,
+}
+
+/// Context of a measurement hash, authenticated as AES-GCM associated data: a hash only decrypts
+/// in the context it was encrypted in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AssociatedData<'a> {
+    /// Topic the sensor event carrying the hash was sent to.
+    pub topic: &'a str,
+    pub experiment_id: &'a str,
+    /// Schema version of the sensor event, as in its `schema_version` header.
+    pub schema_version: u32,
+}
+
+impl AssociatedData<'_> {
+    /// Length-prefixed fields, so that no two contexts share an encoding.
+    fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(12 + self.topic.len() + self.experiment_id.len());
+        for field in [self.topic, self.experiment_id] {
+            bytes.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(field.as_bytes());
+        }
+        bytes.extend_from_slice(&self.schema_version.to_be_bytes());
+        bytes
+    }
 }
 
 #[derive(Debug, Serialize)]
 pub enum DecryptError {
+    /// The hash is longer than [`MAX_HASH_DATA_LEN`].
+    OversizedHashData,
     MalformedHashDataString,
     MalformedB64Nonce,
+    /// The nonce does not decode to the 96 bits of an AES-GCM nonce.
+    InvalidNonceLength,
     MalformedB64Ciphertext,
+    /// The ciphertext is too short to hold an authentication tag.
+    TruncatedCiphertext,
+    /// The hash names a key that is not in the keyring.
+    UnknownKeyId,
+    /// The sealed hash names a producer that is not trusted.
+    UntrustedSigner,
+    /// The sealed hash is not signed by the producer it names, or was tampered with.
+    InvalidSignature,
+    /// The hash does not authenticate: it was encrypted with another key, or tampered with. Only
+    /// hashes of `nonce.ciphertext`, bound to no context, and sealed hashes with a low order
+    /// ephemeral key fail this way.
     DecryptionError,
+    /// The hash does not authenticate in the context it is decrypted in: it was sent to another
+    /// topic, for another experiment or with another schema version. AES-GCM does not tell this
+    /// apart from a hash encrypted with another key under the same ID, or tampered with.
+    AssociatedDataMismatch,
+    /// The hash was encrypted with the key of another group, e.g. replayed from the topic of
+    /// another team.
+    GroupMismatch,
     Utf8DecodingError,
     JsonDeserializationError,
+    /// The plaintext is neither JSON nor a supported version of the compact encoding.
+    CompactDecodingError,
 }
 
 impl std::error::Error for DecryptError {}
@@ -40,42 +105,445 @@ impl std::fmt::Display for DecryptError {
     }
 }
 
-impl HashData {
-    pub fn decrypt(key: &[u8], hash_data: &str) -> Result<HashData, DecryptError> {
+/// ID of the key `hash_data` was encrypted with. Hashes of `nonce.ciphertext`, from before keys
+/// had IDs, were encrypted with the [`DEFAULT_KEY_ID`] key. Sealed hashes name the producer
+/// signing them.
+pub fn key_id(hash_data: &str) -> Result<&str, DecryptError> {
+    if is_sealed(hash_data) {
+        return hash_data
+            .split_once('.')
+            .map(|(signer_id, _)| signer_id)
+            .ok_or(DecryptError::MalformedHashDataString);
+    }
+    Envelope::parse(hash_data).map(|envelope| envelope.key_id)
+}
+
+/// Components of `key_id.group.nonce.ciphertext`, `key_id.nonce.ciphertext` or
+/// `nonce.ciphertext`.
+struct Envelope<'a> {
+    /// Hashes of `nonce.ciphertext` have no key ID, nor associated data.
+    legacy: bool,
+    key_id: &'a str,
+    group: Option<&'a str>,
+    nonce: &'a str,
+    ciphertext: &'a str,
+}
+
+impl<'a> Envelope<'a> {
+    fn parse(hash_data: &'a str) -> Result<Self, DecryptError> {
         let cipher_components: Vec<_> = hash_data.split('.').collect();
-        if cipher_components.len() != 2 {
+        let (key_id, group, nonce, ciphertext) = match cipher_components[..] {
+            [key_id, group, nonce, ciphertext] => (key_id, Some(group), nonce, ciphertext),
+            [key_id, nonce, ciphertext] => (key_id, None, nonce, ciphertext),
+            [nonce, ciphertext] => (DEFAULT_KEY_ID, None, nonce, ciphertext),
+            _ => return Err(DecryptError::MalformedHashDataString),
+        };
+        let invalid_group = group.is_some_and(|group| !keyring::is_valid_id(group));
+        if !keyring::is_valid_id(key_id) || invalid_group {
             return Err(DecryptError::MalformedHashDataString);
         }
-        let key = Key::<Aes256Gcm>::from_slice(key);
-        let cipher = Aes256Gcm::new(key);
+        Ok(Self {
+            legacy: cipher_components.len() == 2,
+            key_id,
+            group,
+            nonce,
+            ciphertext,
+        })
+    }
+}
+
+impl HashData {
+    /// Decrypts `hash_data` with the key of `keyring` it names, active or retired.
+    ///
+    /// Hashes encrypted with the key of a group decrypt for that `group` only, a `group` of
+    /// `None` accepts any. Hashes encrypted with a master key are not bound to a group, they
+    /// decrypt for every group.
+    ///
+    /// `associated_data` is rebuilt by the receiver from the event, or request, carrying the
+    /// hash. Hashes from before key IDs have none.
+    ///
+    /// Never panics: any malformed `hash_data` is a [`DecryptError`].
+    pub fn decrypt(
+        keyring: &Keyring,
+        group: Option<&str>,
+        associated_data: AssociatedData,
+        hash_data: &str,
+    ) -> Result<HashData, DecryptError> {
+        if hash_data.len() > MAX_HASH_DATA_LEN {
+            return Err(DecryptError::OversizedHashData);
+        }
+        let envelope = Envelope::parse(hash_data)?;
+        let (key, _) = keyring
+            .get(envelope.key_id)
+            .ok_or(DecryptError::UnknownKeyId)?;
+        let key = match envelope.group {
+            Some(hash_group) => key
+                .for_group(hash_group)
+                .map_err(|_| DecryptError::MalformedHashDataString)?,
+            None => key.clone(),
+        };
+        let cipher = Aes256Gcm::new(key.key());
         let nonce = general_purpose::STANDARD_NO_PAD
-            .decode(cipher_components[0])
+            .decode(envelope.nonce)
             .map_err(|_| DecryptError::MalformedB64Nonce)?;
-        let nonce = GenericArray::clone_from_slice(&nonce[..]);
+        if nonce.len() != NONCE_LEN {
+            return Err(DecryptError::InvalidNonceLength);
+        }
+        let nonce = GenericArray::clone_from_slice(&nonce);
         let ciphertext = general_purpose::STANDARD_NO_PAD
-            .decode(cipher_components[1])
+            .decode(envelope.ciphertext)
             .map_err(|_| DecryptError::MalformedB64Ciphertext)?;
-        let plaintext = cipher
-            .decrypt(&nonce, ciphertext.as_ref())
-            .map_err(|_| DecryptError::DecryptionError)?;
+        if ciphertext.len() < TAG_LEN {
+            return Err(DecryptError::TruncatedCiphertext);
+        }
+        let plaintext = if envelope.legacy {
+            cipher
+                .decrypt(&nonce, ciphertext.as_ref())
+                .map_err(|_| DecryptError::DecryptionError)?
+        } else {
+            let payload = Payload {
+                msg: &ciphertext,
+                aad: &associated_data.to_bytes(),
+            };
+            cipher
+                .decrypt(&nonce, payload)
+                .map_err(|_| DecryptError::AssociatedDataMismatch)?
+        };
+        // Only checked once the hash authenticated: a group rewritten by the sender does not
+        // decrypt in the first place.
+        if let (Some(group), Some(hash_group)) = (group, envelope.group) {
+            if group != hash_group {
+                return Err(DecryptError::GroupMismatch);
+            }
+        }
+        Self::decode(&plaintext)
+    }
+
+    /// Opens a hash sealed by a producer trusted by `opener`, see [`Sealer`], checking its group
+    /// and context as [`HashData::decrypt`] does. Hashes sealed for no group open for every
+    /// group.
+    ///
+    /// Never panics: any malformed `hash_data` is a [`DecryptError`].
+    pub fn open(
+        opener: &Opener,
+        group: Option<&str>,
+        associated_data: AssociatedData,
+        hash_data: &str,
+    ) -> Result<HashData, DecryptError> {
+        Self::decode(&opener.open(group, associated_data, hash_data)?)
+    }
+
+    /// Decodes a decrypted plaintext, in the compact encoding or, for hashes from before it, JSON.
+    pub fn decode(plaintext: &[u8]) -> Result<HashData, DecryptError> {
+        if plaintext.first() != Some(&b'{') {
+            return compact::decode(plaintext);
+        }
         let plaintext =
-            String::from_utf8(plaintext).map_err(|_| DecryptError::Utf8DecodingError)?;
+            std::str::from_utf8(plaintext).map_err(|_| DecryptError::Utf8DecodingError)?;
 
         let hash_data: HashData =
-            serde_json::from_str(&plaintext).map_err(|_| DecryptError::JsonDeserializationError)?;
+            serde_json::from_str(plaintext).map_err(|_| DecryptError::JsonDeserializationError)?;
         Ok(hash_data)
     }
 
-    pub fn encrypt(&self, key: &[u8]) -> String {
-        let key = Key::<Aes256Gcm>::from_slice(key);
-        let cipher = Aes256Gcm::new(key);
-        let nonce = Aes256Gcm::generate_nonce(&mut OsRng); // 96-bits; unique per message
-        let ciphertext = cipher
-            .encrypt(&nonce, serde_json::to_string(&self).unwrap().as_bytes())
-            .unwrap();
+    /// Encrypts the compact encoding into `key_id.nonce.ciphertext`, or
+    /// `key_id.group.nonce.ciphertext` with the key of a group, bound to `associated_data`.
+    pub fn encrypt(&self, key: &SecretKey, associated_data: AssociatedData) -> String {
+        encrypt_plaintext(&compact::encode(self), key, associated_data)
+    }
+
+    /// Seals the compact encoding into `signer_id.ephemeral_key.nonce.ciphertext.signature`, or
+    /// `signer_id.group.ephemeral_key.nonce.ciphertext.signature` by a sealer of a group, bound
+    /// to `associated_data`. Only the notifications-service `sealer` seals to can open it.
+    pub fn seal(&self, sealer: &Sealer, associated_data: AssociatedData) -> String {
+        sealer.seal(&compact::encode(self), associated_data)
+    }
+}
+
+fn encrypt_plaintext(plaintext: &[u8], key: &SecretKey, associated_data: AssociatedData) -> String {
+    let cipher = Aes256Gcm::new(key.key());
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng); // 96-bits; unique per message
+    let payload = Payload {
+        msg: plaintext,
+        aad: &associated_data.to_bytes(),
+    };
+    let ciphertext = cipher.encrypt(&nonce, payload).unwrap();
+
+    let b64_cipher: String = general_purpose::STANDARD_NO_PAD.encode(ciphertext);
+    let b64_nonce: String = general_purpose::STANDARD_NO_PAD.encode(nonce);
+    match key.group() {
+        Some(group) => format!("{}.{}.{}.{}", key.id(), group, b64_nonce, b64_cipher),
+        None => format!("{}.{}.{}", key.id(), b64_nonce, b64_cipher),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::{collection::vec, option, prelude::*};
+
+    const ASSOCIATED_DATA: AssociatedData = AssociatedData {
+        topic: "experiment",
+        experiment_id: "5678",
+        schema_version: 2,
+    };
+
+    fn hash_data() -> HashData {
+        HashData {
+            notification_type: Some(NotificationType::OutOfRange),
+            researcher: Some("d.landau@uu.nl".into()),
+            experiment_id: "5678".into(),
+            measurement_id: "1234".into(),
+            timestamp: 1692029115.4314,
+            sequence: Some(3),
+        }
+    }
+
+    #[test]
+    fn decrypt_with_the_key_named_by_the_hash() {
+        let keyring = Keyring::from_json(
+            r#"[
+                { "id": "2023-fall", "key": "QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh", "retired": true },
+                { "id": "2024-spring", "key": "hEDYf0ikoGyFwu8mNFPl3VrqsZpHrBXi" }
+            ]"#,
+        )
+        .unwrap();
+        for id in ["2023-fall", "2024-spring"] {
+            let (key, _) = keyring.get(id).unwrap();
+            let encrypted = hash_data().encrypt(key, ASSOCIATED_DATA);
+            assert_eq!(key_id(&encrypted).unwrap(), id);
+            let decrypted = HashData::decrypt(&keyring, None, ASSOCIATED_DATA, &encrypted).unwrap();
+            assert_eq!(decrypted.measurement_id, "1234");
+        }
+
+        let unknown = SecretKey::new("2025-spring", b"Jm1qQ9bV7cWkR2xT4yU6iO8pA0sD3fGh").unwrap();
+        let encrypted = hash_data().encrypt(&unknown, ASSOCIATED_DATA);
+        assert!(matches!(
+            HashData::decrypt(&keyring, None, ASSOCIATED_DATA, &encrypted),
+            Err(DecryptError::UnknownKeyId)
+        ));
+    }
+
+    #[test]
+    fn decrypt_hashes_without_key_id_with_the_default_key() {
+        let keyring = Keyring::single("QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh").unwrap();
+        let (key, _) = keyring.get(DEFAULT_KEY_ID).unwrap();
+        let cipher = Aes256Gcm::new(key.key());
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let plaintext = serde_json::to_string(&hash_data()).unwrap();
+        let ciphertext = cipher.encrypt(&nonce, plaintext.as_bytes()).unwrap();
+        let legacy = format!(
+            "{}.{}",
+            general_purpose::STANDARD_NO_PAD.encode(nonce),
+            general_purpose::STANDARD_NO_PAD.encode(ciphertext)
+        );
+        assert_eq!(key_id(&legacy).unwrap(), DEFAULT_KEY_ID);
+        assert!(HashData::decrypt(&keyring, Some("group0"), ASSOCIATED_DATA, &legacy).is_ok());
+        assert!(matches!(
+            HashData::decrypt(&keyring, None, ASSOCIATED_DATA, "not.a.valid.hash.at.all"),
+            Err(DecryptError::MalformedHashDataString)
+        ));
+    }
+
+    #[test]
+    fn decrypt_json_hashes() {
+        let keyring = Keyring::single("QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh").unwrap();
+        let plaintext = serde_json::to_vec(&hash_data()).unwrap();
+        let encrypted =
+            encrypt_plaintext(&plaintext, keyring.key_at(0.0).unwrap(), ASSOCIATED_DATA);
+        let decrypted = HashData::decrypt(&keyring, None, ASSOCIATED_DATA, &encrypted).unwrap();
+        assert_eq!(decrypted, hash_data());
 
-        let b64_cipher: String = general_purpose::STANDARD_NO_PAD.encode(ciphertext);
-        let b64_nonce: String = general_purpose::STANDARD_NO_PAD.encode(nonce);
-        b64_nonce + "." + &b64_cipher
+        let encrypted = hash_data().encrypt(keyring.key_at(0.0).unwrap(), ASSOCIATED_DATA);
+        let decrypted = HashData::decrypt(&keyring, None, ASSOCIATED_DATA, &encrypted).unwrap();
+        assert_eq!(decrypted, hash_data());
+    }
+
+    #[test]
+    fn reject_hashes_of_another_context() {
+        let keyring = Keyring::single("QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh").unwrap();
+        let encrypted = hash_data().encrypt(keyring.key_at(0.0).unwrap(), ASSOCIATED_DATA);
+        let contexts = [
+            AssociatedData {
+                topic: "group1",
+                ..ASSOCIATED_DATA
+            },
+            AssociatedData {
+                experiment_id: "1234",
+                ..ASSOCIATED_DATA
+            },
+            AssociatedData {
+                schema_version: 1,
+                ..ASSOCIATED_DATA
+            },
+            // Same bytes, split differently between the fields
+            AssociatedData {
+                topic: "experiment5",
+                experiment_id: "678",
+                ..ASSOCIATED_DATA
+            },
+        ];
+        for associated_data in contexts {
+            assert!(matches!(
+                HashData::decrypt(&keyring, None, associated_data, &encrypted),
+                Err(DecryptError::AssociatedDataMismatch)
+            ));
+        }
+        assert!(HashData::decrypt(&keyring, None, ASSOCIATED_DATA, &encrypted).is_ok());
+    }
+
+    #[test]
+    fn reject_hashes_of_another_group() {
+        let keyring = Keyring::single("QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh").unwrap();
+        let key = keyring.key_at(0.0).unwrap().for_group("group0").unwrap();
+        let encrypted = hash_data().encrypt(&key, ASSOCIATED_DATA);
+        let decrypt =
+            |group, hash_data: &str| HashData::decrypt(&keyring, group, ASSOCIATED_DATA, hash_data);
+        assert!(encrypted.starts_with("default.group0."));
+        assert!(decrypt(Some("group0"), &encrypted).is_ok());
+        assert!(decrypt(None, &encrypted).is_ok());
+        assert!(matches!(
+            decrypt(Some("group1"), &encrypted),
+            Err(DecryptError::GroupMismatch)
+        ));
+
+        // Does not authenticate with the key of the rewritten group
+        let rewritten = encrypted.replacen("group0", "group1", 1);
+        assert!(matches!(
+            decrypt(Some("group1"), &rewritten),
+            Err(DecryptError::AssociatedDataMismatch)
+        ));
+        let master_key = keyring.key_at(0.0).unwrap();
+        let encrypted = hash_data().encrypt(master_key, ASSOCIATED_DATA);
+        assert!(decrypt(Some("group1"), &encrypted).is_ok());
+    }
+
+    #[test]
+    fn reject_malformed_hashes() {
+        let keyring = Keyring::single("QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh").unwrap();
+        let b64 = |bytes: &[u8]| general_purpose::STANDARD_NO_PAD.encode(bytes);
+        let decrypt =
+            |hash_data: &str| HashData::decrypt(&keyring, None, ASSOCIATED_DATA, hash_data);
+        assert!(matches!(
+            decrypt(&format!("default.{}.{}", b64(&[0; 11]), b64(&[0; 32]))),
+            Err(DecryptError::InvalidNonceLength)
+        ));
+        assert!(matches!(
+            decrypt(&format!("default.{}.{}", b64(&[]), b64(&[0; 32]))),
+            Err(DecryptError::InvalidNonceLength)
+        ));
+        assert!(matches!(
+            decrypt(&format!("default.{}.{}", b64(&[0; 12]), b64(&[0; 15]))),
+            Err(DecryptError::TruncatedCiphertext)
+        ));
+        assert!(matches!(
+            decrypt(&format!(
+                "default.{}.{}",
+                b64(&[0; 12]),
+                b64(&[0; MAX_HASH_DATA_LEN])
+            )),
+            Err(DecryptError::OversizedHashData)
+        ));
+    }
+
+    #[test]
+    fn open_sealed_hashes() {
+        let (sealer, opener) = generate_keys("producer-2024").unwrap();
+        let (sealer, opener) = (
+            Sealer::from_json(&sealer).unwrap(),
+            Opener::from_json(&opener).unwrap(),
+        );
+        let sealed = hash_data().seal(&sealer, ASSOCIATED_DATA);
+        assert_eq!(key_id(&sealed).unwrap(), "producer-2024");
+        assert_eq!(
+            HashData::open(&opener, None, ASSOCIATED_DATA, &sealed).unwrap(),
+            hash_data()
+        );
+        let other = AssociatedData {
+            topic: "group1",
+            ..ASSOCIATED_DATA
+        };
+        assert!(matches!(
+            HashData::open(&opener, None, other, &sealed),
+            Err(DecryptError::AssociatedDataMismatch)
+        ));
+        // Not a symmetric hash
+        let keyring = Keyring::single("QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh").unwrap();
+        assert!(matches!(
+            HashData::decrypt(&keyring, None, ASSOCIATED_DATA, &sealed),
+            Err(DecryptError::MalformedHashDataString)
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn decrypt_never_panics(hash_data in "\\PC*") {
+            let keyring = Keyring::single("QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh").unwrap();
+            let _ = HashData::decrypt(&keyring, Some("group0"), ASSOCIATED_DATA, &hash_data);
+        }
+
+        #[test]
+        fn open_never_panics(
+            components in vec(vec(any::<u8>(), 0..96), 4),
+            signer_id in "[a-z0-9-]{1,16}",
+        ) {
+            let (_, opener) = generate_keys(&signer_id).unwrap();
+            let opener = Opener::from_json(&opener).unwrap();
+            let mut hash_data = vec![signer_id];
+            hash_data.extend(
+                components
+                    .iter()
+                    .map(|bytes| general_purpose::STANDARD_NO_PAD.encode(bytes)),
+            );
+            prop_assert!(HashData::open(&opener, None, ASSOCIATED_DATA, &hash_data.join(".")).is_err());
+        }
+
+        #[test]
+        fn decrypt_envelopes_never_panics(
+            group in option::of("[a-z0-9]{1,8}"),
+            nonce in vec(any::<u8>(), 0..32),
+            ciphertext in vec(any::<u8>(), 0..128),
+        ) {
+            let keyring = Keyring::single("QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh").unwrap();
+            let b64 = |bytes: &[u8]| general_purpose::STANDARD_NO_PAD.encode(bytes);
+            let hash_data = match group {
+                Some(group) => format!("default.{}.{}.{}", group, b64(&nonce), b64(&ciphertext)),
+                None => format!("default.{}.{}", b64(&nonce), b64(&ciphertext)),
+            };
+            prop_assert!(
+                HashData::decrypt(&keyring, None, ASSOCIATED_DATA, &hash_data).is_err()
+            );
+        }
+
+        #[test]
+        fn decode_never_panics(plaintext in vec(any::<u8>(), 0..256)) {
+            let _ = HashData::decode(&plaintext);
+        }
+
+        #[test]
+        fn decrypt_what_is_encrypted(
+            notification_type in option::of(prop_oneof![
+                Just(NotificationType::OutOfRange),
+                Just(NotificationType::Stabilized),
+            ]),
+            researcher in option::of("\\PC{0,64}"),
+            experiment_id in "\\PC{0,64}",
+            measurement_id in "\\PC{0,64}",
+            timestamp in prop::num::f64::NORMAL | prop::num::f64::ZERO,
+            sequence in option::of(any::<u32>()),
+        ) {
+            let keyring = Keyring::single("QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh").unwrap();
+            let hash_data = HashData {
+                notification_type,
+                researcher,
+                experiment_id,
+                measurement_id,
+                timestamp,
+                sequence,
+            };
+            let encrypted = hash_data.encrypt(keyring.key_at(0.0).unwrap(), ASSOCIATED_DATA);
+            let decrypted = HashData::decrypt(&keyring, None, ASSOCIATED_DATA, &encrypted);
+            prop_assert_eq!(decrypted.unwrap(), hash_data);
+        }
     }
 }
@@ -0,0 +1,544 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key,
+};
+use base64::{engine::general_purpose, Engine as _};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use generic_array::GenericArray;
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::fs;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+use crate::{
+    keyring::{self, KeyringError, KEY_LEN},
+    AssociatedData, DecryptError, MAX_HASH_DATA_LEN, NONCE_LEN, TAG_LEN,
+};
+
+/// HKDF info of the key of a sealed hash, followed by the ephemeral and recipient public keys.
+const SEALED_KEY_INFO: &[u8] = b"event-hash sealed key ";
+
+/// Signed ahead of the hash, so that signatures of sealed hashes are never valid elsewhere. The
+/// associated data is authenticated by AES-GCM, under a key only the producer and the
+/// notifications-service agree on.
+const SIGNATURE_CONTEXT: &[u8] = b"event-hash sealed hash ";
+
+const SIGNATURE_LEN: usize = 64;
+
+/// Whether `hash_data` is a sealed hash, `signer_id.ephemeral_key.nonce.ciphertext.signature`
+/// or, bound to a group, `signer_id.group.ephemeral_key.nonce.ciphertext.signature`.
+pub fn is_sealed(hash_data: &str) -> bool {
+    matches!(hash_data.split('.').count(), 5 | 6)
+}
+
+/// Keys of a producer sealing hashes, read from a JSON file such as
+///
+/// ```json
+/// {
+///     "signer_id": "producer-2024",
+///     "signing_key": "<base64 Ed25519 secret key of the producer>",
+///     "recipient_key": "<base64 X25519 public key of the notifications-service>"
+/// }
+/// ```
+///
+/// Holds no secret opening hashes: only the notifications-service decrypts them.
+#[derive(Clone)]
+pub struct Sealer {
+    signer_id: String,
+    /// Group the hashes are bound to, see [`Sealer::for_group`].
+    group: Option<String>,
+    signing_key: SigningKey,
+    recipient_key: PublicKey,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SealerFile {
+    signer_id: String,
+    signing_key: String,
+    recipient_key: String,
+}
+
+impl Sealer {
+    pub fn from_file(path: &str) -> Result<Self, KeyringError> {
+        let contents = fs::read_to_string(path).map_err(KeyringError::Io)?;
+        Self::from_json(&contents)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, KeyringError> {
+        let file: SealerFile = serde_json::from_str(json).map_err(KeyringError::Json)?;
+        if !keyring::is_valid_id(&file.signer_id) {
+            return Err(KeyringError::InvalidKeyId(file.signer_id));
+        }
+        Ok(Self {
+            signing_key: SigningKey::from_bytes(&decode_key(&file.signer_id, &file.signing_key)?),
+            recipient_key: PublicKey::from(decode_key(&file.signer_id, &file.recipient_key)?),
+            signer_id: file.signer_id,
+            group: None,
+        })
+    }
+
+    /// Sealer of hashes bound to `group`, named in the signed hash. They open for that group
+    /// only.
+    pub fn for_group(&self, group: &str) -> Result<Self, KeyringError> {
+        if self.group.is_some() {
+            return Err(KeyringError::AlreadyDerived(self.signer_id.clone()));
+        }
+        if !keyring::is_valid_id(group) {
+            return Err(KeyringError::InvalidGroupId(group.into()));
+        }
+        Ok(Self {
+            group: Some(group.into()),
+            ..self.clone()
+        })
+    }
+
+    pub fn signer_id(&self) -> &str {
+        &self.signer_id
+    }
+
+    pub fn group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+
+    /// Encrypts `plaintext` to the recipient, with a key agreed with a fresh ephemeral key, and
+    /// signs the result.
+    pub(crate) fn seal(&self, plaintext: &[u8], associated_data: AssociatedData) -> String {
+        let ephemeral_secret = EphemeralSecret::random_from_rng(OsRng);
+        let ephemeral_key = PublicKey::from(&ephemeral_secret);
+        let shared_secret = ephemeral_secret.diffie_hellman(&self.recipient_key);
+        let cipher = cipher(
+            shared_secret.as_bytes(),
+            &ephemeral_key,
+            &self.recipient_key,
+        );
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let associated_data = associated_data.to_bytes();
+        let payload = Payload {
+            msg: plaintext,
+            aad: &associated_data,
+        };
+        let ciphertext = cipher.encrypt(&nonce, payload).unwrap();
+
+        let signer = match &self.group {
+            Some(group) => format!("{}.{}", self.signer_id, group),
+            None => self.signer_id.clone(),
+        };
+        let signed = format!(
+            "{}.{}.{}.{}",
+            signer,
+            general_purpose::STANDARD_NO_PAD.encode(ephemeral_key.as_bytes()),
+            general_purpose::STANDARD_NO_PAD.encode(nonce),
+            general_purpose::STANDARD_NO_PAD.encode(ciphertext),
+        );
+        let signature = self.signing_key.sign(&signed_message(&signed));
+        format!(
+            "{}.{}",
+            signed,
+            general_purpose::STANDARD_NO_PAD.encode(signature.to_bytes())
+        )
+    }
+}
+
+/// Leaves the signing key out of logs.
+impl std::fmt::Debug for Sealer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Sealer")
+            .field("signer_id", &self.signer_id)
+            .field("group", &self.group)
+            .finish()
+    }
+}
+
+/// Keys of the notifications-service opening sealed hashes, read from a JSON file such as
+///
+/// ```json
+/// {
+///     "opening_key": "<base64 X25519 secret key of the notifications-service>",
+///     "producers": [
+///         { "id": "producer-2024", "verifying_key": "<base64 Ed25519 public key>" }
+///     ]
+/// }
+/// ```
+///
+/// Only hashes signed by one of the trusted `producers` are opened.
+#[derive(Clone)]
+pub struct Opener {
+    opening_key: StaticSecret,
+    producers: Vec<(String, VerifyingKey)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OpenerFile {
+    opening_key: String,
+    producers: Vec<ProducerEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProducerEntry {
+    id: String,
+    verifying_key: String,
+}
+
+impl Opener {
+    pub fn from_file(path: &str) -> Result<Self, KeyringError> {
+        let contents = fs::read_to_string(path).map_err(KeyringError::Io)?;
+        Self::from_json(&contents)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, KeyringError> {
+        let file: OpenerFile = serde_json::from_str(json).map_err(KeyringError::Json)?;
+        let mut producers: Vec<(String, VerifyingKey)> = Vec::with_capacity(file.producers.len());
+        for entry in file.producers {
+            if !keyring::is_valid_id(&entry.id) {
+                return Err(KeyringError::InvalidKeyId(entry.id));
+            }
+            if producers.iter().any(|(id, _)| *id == entry.id) {
+                return Err(KeyringError::DuplicateKeyId(entry.id));
+            }
+            let verifying_key =
+                VerifyingKey::from_bytes(&decode_key(&entry.id, &entry.verifying_key)?)
+                    .map_err(|_| KeyringError::InvalidKeyEncoding(entry.id.clone()))?;
+            producers.push((entry.id, verifying_key));
+        }
+        Ok(Self {
+            opening_key: StaticSecret::from(decode_key("opening_key", &file.opening_key)?),
+            producers,
+        })
+    }
+
+    /// Checks the signature and decrypts the plaintext bound to `associated_data`. Hashes bound
+    /// to a group open for that `group` only, a `group` of `None` accepts any.
+    pub(crate) fn open(
+        &self,
+        group: Option<&str>,
+        associated_data: AssociatedData,
+        hash_data: &str,
+    ) -> Result<Vec<u8>, DecryptError> {
+        if hash_data.len() > MAX_HASH_DATA_LEN {
+            return Err(DecryptError::OversizedHashData);
+        }
+        let (signed, signature) = hash_data
+            .rsplit_once('.')
+            .ok_or(DecryptError::MalformedHashDataString)?;
+        let components: Vec<_> = signed.split('.').collect();
+        let (signer_id, hash_group, ephemeral_key, nonce, ciphertext) = match components[..] {
+            [signer_id, hash_group, ephemeral_key, nonce, ciphertext] => (
+                signer_id,
+                Some(hash_group),
+                ephemeral_key,
+                nonce,
+                ciphertext,
+            ),
+            [signer_id, ephemeral_key, nonce, ciphertext] => {
+                (signer_id, None, ephemeral_key, nonce, ciphertext)
+            }
+            _ => return Err(DecryptError::MalformedHashDataString),
+        };
+        let (_, verifying_key) = self
+            .producers
+            .iter()
+            .find(|(id, _)| id == signer_id)
+            .ok_or(DecryptError::UntrustedSigner)?;
+        let signature: [u8; SIGNATURE_LEN] = decode_b64(signature)?
+            .try_into()
+            .map_err(|_| DecryptError::InvalidSignature)?;
+        verifying_key
+            .verify_strict(&signed_message(signed), &Signature::from_bytes(&signature))
+            .map_err(|_| DecryptError::InvalidSignature)?;
+
+        let ephemeral_key: [u8; KEY_LEN] = decode_b64(ephemeral_key)?
+            .try_into()
+            .map_err(|_| DecryptError::MalformedHashDataString)?;
+        let ephemeral_key = PublicKey::from(ephemeral_key);
+        let nonce = decode_b64(nonce)?;
+        if nonce.len() != NONCE_LEN {
+            return Err(DecryptError::InvalidNonceLength);
+        }
+        let ciphertext = decode_b64(ciphertext)?;
+        if ciphertext.len() < TAG_LEN {
+            return Err(DecryptError::TruncatedCiphertext);
+        }
+        let shared_secret = self.opening_key.diffie_hellman(&ephemeral_key);
+        // Low order ephemeral keys agree on a secret known to anyone
+        if !shared_secret.was_contributory() {
+            return Err(DecryptError::DecryptionError);
+        }
+        let cipher = cipher(
+            shared_secret.as_bytes(),
+            &ephemeral_key,
+            &PublicKey::from(&self.opening_key),
+        );
+        let payload = Payload {
+            msg: &ciphertext,
+            aad: &associated_data.to_bytes(),
+        };
+        let plaintext = cipher
+            .decrypt(GenericArray::from_slice(&nonce), payload)
+            .map_err(|_| DecryptError::AssociatedDataMismatch)?;
+        // The group is signed along with the hash, a rewritten one fails the signature
+        if let (Some(group), Some(hash_group)) = (group, hash_group) {
+            if group != hash_group {
+                return Err(DecryptError::GroupMismatch);
+            }
+        }
+        Ok(plaintext)
+    }
+}
+
+/// Leaves the opening key out of logs.
+impl std::fmt::Debug for Opener {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let producers: Vec<_> = self.producers.iter().map(|(id, _)| id).collect();
+        f.debug_struct("Opener")
+            .field("producers", &producers)
+            .finish()
+    }
+}
+
+/// New key files of a producer `signer_id` and of the notifications-service, as JSON.
+pub fn generate_keys(signer_id: &str) -> Result<(String, String), KeyringError> {
+    let opening_key = StaticSecret::random_from_rng(OsRng);
+    let opener = OpenerFile {
+        opening_key: general_purpose::STANDARD.encode(opening_key.to_bytes()),
+        producers: Vec::new(),
+    };
+    add_producer(opener, signer_id)
+}
+
+/// New key file of a producer `signer_id`, and the key file `opener` of the notifications-service
+/// trusting it along with the producers it already trusts, as JSON.
+pub fn generate_producer_keys(
+    opener: &str,
+    signer_id: &str,
+) -> Result<(String, String), KeyringError> {
+    // Validates the keys of the file
+    Opener::from_json(opener)?;
+    add_producer(
+        serde_json::from_str(opener).map_err(KeyringError::Json)?,
+        signer_id,
+    )
+}
+
+fn add_producer(mut opener: OpenerFile, signer_id: &str) -> Result<(String, String), KeyringError> {
+    if !keyring::is_valid_id(signer_id) {
+        return Err(KeyringError::InvalidKeyId(signer_id.into()));
+    }
+    if opener.producers.iter().any(|entry| entry.id == signer_id) {
+        return Err(KeyringError::DuplicateKeyId(signer_id.into()));
+    }
+    let opening_key = StaticSecret::from(decode_key("opening_key", &opener.opening_key)?);
+    let signing_key = SigningKey::generate(&mut OsRng);
+    let sealer = SealerFile {
+        signer_id: signer_id.into(),
+        signing_key: general_purpose::STANDARD.encode(signing_key.to_bytes()),
+        recipient_key: general_purpose::STANDARD.encode(PublicKey::from(&opening_key).as_bytes()),
+    };
+    opener.producers.push(ProducerEntry {
+        id: signer_id.into(),
+        verifying_key: general_purpose::STANDARD.encode(signing_key.verifying_key().as_bytes()),
+    });
+    Ok((
+        serde_json::to_string_pretty(&sealer).map_err(KeyringError::Json)?,
+        serde_json::to_string_pretty(&opener).map_err(KeyringError::Json)?,
+    ))
+}
+
+fn decode_key(id: &str, key: &str) -> Result<[u8; KEY_LEN], KeyringError> {
+    general_purpose::STANDARD
+        .decode(key)
+        .map_err(|_| KeyringError::InvalidKeyEncoding(id.into()))?
+        .try_into()
+        .map_err(|_| KeyringError::InvalidKeyLength(id.into()))
+}
+
+fn decode_b64(s: &str) -> Result<Vec<u8>, DecryptError> {
+    general_purpose::STANDARD_NO_PAD
+        .decode(s)
+        .map_err(|_| DecryptError::MalformedHashDataString)
+}
+
+/// AES-256-GCM keyed by HKDF-SHA256 of the shared secret, bound to both public keys.
+fn cipher(shared_secret: &[u8], ephemeral_key: &PublicKey, recipient_key: &PublicKey) -> Aes256Gcm {
+    let mut key = Key::<Aes256Gcm>::default();
+    Hkdf::<Sha256>::new(None, shared_secret)
+        .expand_multi_info(
+            &[
+                SEALED_KEY_INFO,
+                ephemeral_key.as_bytes(),
+                recipient_key.as_bytes(),
+            ],
+            &mut key,
+        )
+        .expect("Key length is a valid HKDF-SHA256 output length");
+    Aes256Gcm::new(&key)
+}
+
+fn signed_message(signed: &str) -> Vec<u8> {
+    [SIGNATURE_CONTEXT, signed.as_bytes()].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASSOCIATED_DATA: AssociatedData = AssociatedData {
+        topic: "experiment",
+        experiment_id: "5678",
+        schema_version: 2,
+    };
+
+    fn keys(signer_id: &str) -> (Sealer, Opener) {
+        let (sealer, opener) = generate_keys(signer_id).unwrap();
+        (
+            Sealer::from_json(&sealer).unwrap(),
+            Opener::from_json(&opener).unwrap(),
+        )
+    }
+
+    #[test]
+    fn open_what_is_sealed() {
+        let (sealer, opener) = keys("producer-2024");
+        let sealed = sealer.seal(b"plaintext", ASSOCIATED_DATA);
+        assert!(is_sealed(&sealed));
+        assert!(sealed.starts_with("producer-2024."));
+        assert_eq!(
+            opener.open(None, ASSOCIATED_DATA, &sealed).unwrap(),
+            b"plaintext"
+        );
+        assert!(matches!(
+            opener.open(
+                None,
+                AssociatedData {
+                    topic: "group1",
+                    ..ASSOCIATED_DATA
+                },
+                &sealed
+            ),
+            Err(DecryptError::AssociatedDataMismatch)
+        ));
+    }
+
+    #[test]
+    fn reject_hashes_of_another_group() {
+        let (sealer, opener) = keys("producer-2024");
+        let sealed = sealer
+            .for_group("group0")
+            .unwrap()
+            .seal(b"plaintext", ASSOCIATED_DATA);
+        assert!(is_sealed(&sealed));
+        assert!(sealed.starts_with("producer-2024.group0."));
+        let open = |group, hash_data: &str| opener.open(group, ASSOCIATED_DATA, hash_data);
+        assert!(open(Some("group0"), &sealed).is_ok());
+        assert!(open(None, &sealed).is_ok());
+        assert!(matches!(
+            open(Some("group1"), &sealed),
+            Err(DecryptError::GroupMismatch)
+        ));
+
+        // The signature covers the group
+        let rewritten = sealed.replacen("group0", "group1", 1);
+        assert!(matches!(
+            open(Some("group1"), &rewritten),
+            Err(DecryptError::InvalidSignature)
+        ));
+        // Hashes of no group open for every group
+        assert!(open(Some("group1"), &sealer.seal(b"plaintext", ASSOCIATED_DATA)).is_ok());
+        assert!(matches!(
+            sealer.for_group("group0.1"),
+            Err(KeyringError::InvalidGroupId(_))
+        ));
+    }
+
+    #[test]
+    fn reject_hashes_of_untrusted_producers() {
+        let (_, opener) = keys("producer-2024");
+        let (sealer, other_opener) = keys("producer-2024");
+        let sealed = sealer.seal(b"plaintext", ASSOCIATED_DATA);
+        // Signed by another key under the same ID
+        assert!(matches!(
+            opener.open(None, ASSOCIATED_DATA, &sealed),
+            Err(DecryptError::InvalidSignature)
+        ));
+        let renamed = sealed.replacen("producer-2024", "producer-2025", 1);
+        assert!(matches!(
+            other_opener.open(None, ASSOCIATED_DATA, &renamed),
+            Err(DecryptError::UntrustedSigner)
+        ));
+
+        // Sealed to the notifications-service by anyone, but not signed by the producer
+        let (forger, _) = keys("producer-2024");
+        let forged = Sealer {
+            recipient_key: sealer.recipient_key,
+            ..forger
+        }
+        .seal(b"plaintext", ASSOCIATED_DATA);
+        assert!(matches!(
+            other_opener.open(None, ASSOCIATED_DATA, &forged),
+            Err(DecryptError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn reject_tampered_hashes() {
+        let (sealer, opener) = keys("producer-2024");
+        let sealed = sealer.seal(b"plaintext", ASSOCIATED_DATA);
+        let components: Vec<_> = sealed.split('.').collect();
+        for idx in 1..components.len() {
+            let mut tampered = components.clone();
+            let replaced = general_purpose::STANDARD_NO_PAD.encode([0; 32]);
+            tampered[idx] = &replaced;
+            assert!(opener
+                .open(None, ASSOCIATED_DATA, &tampered.join("."))
+                .is_err());
+        }
+        assert!(matches!(
+            opener.open(None, ASSOCIATED_DATA, "producer-2024.a.b.c"),
+            Err(DecryptError::MalformedHashDataString)
+        ));
+    }
+
+    #[test]
+    fn add_producers_to_an_opener() {
+        let (sealer, opener) = generate_keys("producer-2024").unwrap();
+        let (other_sealer, opener) = generate_producer_keys(&opener, "producer-2025").unwrap();
+        let opener = Opener::from_json(&opener).unwrap();
+        for sealer in [sealer, other_sealer] {
+            let sealed = Sealer::from_json(&sealer)
+                .unwrap()
+                .seal(b"plaintext", ASSOCIATED_DATA);
+            assert_eq!(
+                opener.open(None, ASSOCIATED_DATA, &sealed).unwrap(),
+                b"plaintext"
+            );
+        }
+
+        let (_, opener) = generate_keys("producer-2024").unwrap();
+        assert!(matches!(
+            generate_producer_keys(&opener, "producer-2024"),
+            Err(KeyringError::DuplicateKeyId(_))
+        ));
+        assert!(matches!(
+            generate_producer_keys(&opener, "producer.2025"),
+            Err(KeyringError::InvalidKeyId(_))
+        ));
+    }
+
+    #[test]
+    fn reject_invalid_key_files() {
+        assert!(matches!(
+            Sealer::from_json(
+                r#"{ "signer_id": "producer.2024", "signing_key": "", "recipient_key": "" }"#
+            ),
+            Err(KeyringError::InvalidKeyId(_))
+        ));
+        assert!(matches!(
+            Opener::from_json(r#"{ "opening_key": "c2hvcnQ=", "producers": [] }"#),
+            Err(KeyringError::InvalidKeyLength(_))
+        ));
+        assert!(matches!(
+            Opener::from_json(r#"{ "opening_key": "~", "producers": [] }"#),
+            Err(KeyringError::InvalidKeyEncoding(_))
+        ));
+    }
+}
@@ -0,0 +1,70 @@
+use apache_avro::{schema_compatibility::SchemaCompatibility, Schema};
+
+/// Which way events can be exchanged between a producer and a consumer that disagree on the
+/// schema version.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compatibility {
+    /// Backward and forward compatible.
+    Full,
+    /// Consumers using the new schema can read events written with the old one.
+    Backward,
+    /// Consumers using the old schema can read events written with the new one.
+    Forward,
+    None,
+}
+
+/// Checks whether `new` can replace `old` without breaking either producers or consumers.
+pub fn compatibility(old: &Schema, new: &Schema) -> Compatibility {
+    match (
+        SchemaCompatibility::can_read(old, new),
+        SchemaCompatibility::can_read(new, old),
+    ) {
+        (true, true) => Compatibility::Full,
+        (true, false) => Compatibility::Backward,
+        (false, true) => Compatibility::Forward,
+        (false, false) => Compatibility::None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{raw_schema, LATEST_VERSION, RECORD_NAMES};
+
+    #[test]
+    fn every_version_is_fully_compatible_with_the_previous_one() {
+        for record_name in RECORD_NAMES {
+            for version in 2..=LATEST_VERSION {
+                let old = Schema::parse_str(raw_schema(record_name, version - 1).unwrap()).unwrap();
+                let new = Schema::parse_str(raw_schema(record_name, version).unwrap()).unwrap();
+                assert_eq!(
+                    compatibility(&old, &new),
+                    Compatibility::Full,
+                    "{} v{}",
+                    record_name,
+                    version
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fields_without_default() {
+        let old = Schema::parse_str(
+            r#"{"type": "record", "name": "experiment_started", "fields": [
+                {"name": "experiment", "type": "string"}
+            ]}"#,
+        )
+        .unwrap();
+        let new = Schema::parse_str(
+            r#"{"type": "record", "name": "experiment_started", "fields": [
+                {"name": "experiment", "type": "string"},
+                {"name": "timestamp", "type": "double"}
+            ]}"#,
+        )
+        .unwrap();
+        // Old events have no timestamp, while old consumers skip the new field
+        assert_eq!(compatibility(&old, &new), Compatibility::Forward);
+        assert_eq!(compatibility(&new, &old), Compatibility::Backward);
+    }
+}
@@ -0,0 +1,51 @@
+//! Plain JSON encoding of the events, an object per record. Optional fields are `null` when
+//! absent.
+use apache_avro::{types::Value, Schema};
+
+use crate::{DecodeError, EncodeError};
+
+pub fn encode(value: Value) -> Result<Vec<u8>, EncodeError> {
+    let json = serde_json::Value::try_from(value).map_err(EncodeError::Avro)?;
+    Ok(serde_json::to_vec(&json).expect("Serializable JSON value"))
+}
+
+/// Decodes a JSON object into a record of `schema`.
+///
+/// Properties unknown to `schema` are skipped and missing properties get the default value of
+/// their field.
+pub fn decode(schema: &Schema, payload: &[u8]) -> Result<Value, DecodeError> {
+    let json: serde_json::Value = serde_json::from_slice(payload).map_err(DecodeError::Json)?;
+    Value::from(json).resolve(schema).map_err(DecodeError::Avro)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v2;
+    use apache_avro::types::Record;
+
+    #[test]
+    fn roundtrip() {
+        let schema = Schema::parse_str(v2::EXPERIMENT_TERMINATED).unwrap();
+        let mut record = Record::new(&schema).unwrap();
+        record.put("experiment", "5678");
+        record.put("timestamp", Value::Double(1692029115.4314));
+        record.put("interrupted", true);
+        let value: Value = record.into();
+
+        let payload = encode(value.clone()).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&payload).unwrap(),
+            serde_json::json!({
+                "experiment": "5678",
+                "timestamp": 1692029115.4314,
+                "interrupted": true
+            })
+        );
+        assert_eq!(decode(&schema, &payload).unwrap(), value);
+        assert!(matches!(
+            decode(&schema, br#"{"experiment": "5678", "interrupted": true}"#),
+            Err(DecodeError::Avro(_))
+        ));
+    }
+}
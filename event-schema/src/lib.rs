@@ -0,0 +1,625 @@
+use apache_avro::{
+    from_avro_datum, rabin::Rabin, to_avro_datum, types::Value, Reader, Schema, Writer,
+};
+use std::str::FromStr;
+
+mod compatibility;
+pub mod json;
+pub mod protobuf;
+mod registry;
+
+pub use compatibility::{compatibility, Compatibility};
+pub use registry::{RegistryError, SchemaRegistry};
+
+/// Schemas of the first version of every event.
+pub mod v1 {
+    pub const EXPERIMENT_CONFIGURED: &str =
+        include_str!("../../experiment-producer/schemas/v1/experiment_configured.avsc");
+    pub const STABILIZATION_STARTED: &str =
+        include_str!("../../experiment-producer/schemas/v1/stabilization_started.avsc");
+    pub const EXPERIMENT_STARTED: &str =
+        include_str!("../../experiment-producer/schemas/v1/experiment_started.avsc");
+    pub const EXPERIMENT_TERMINATED: &str =
+        include_str!("../../experiment-producer/schemas/v1/experiment_terminated.avsc");
+    pub const SENSOR_TEMPERATURE_MEASURED: &str =
+        include_str!("../../experiment-producer/schemas/v1/sensor_temperature_measured.avsc");
+    pub const EXPERIMENT_DOCUMENT: &str =
+        include_str!("../../experiment-producer/schemas/v1/experiment_document.avsc");
+    pub const PRODUCER_HEARTBEAT: &str =
+        include_str!("../../experiment-producer/schemas/v1/producer_heartbeat.avsc");
+}
+
+/// Schemas of the events that changed in the second version, every other event is unchanged
+/// since [`v1`].
+pub mod v2 {
+    pub const EXPERIMENT_CONFIGURED: &str =
+        include_str!("../../experiment-producer/schemas/v2/experiment_configured.avsc");
+    pub const EXPERIMENT_TERMINATED: &str =
+        include_str!("../../experiment-producer/schemas/v2/experiment_terminated.avsc");
+    pub const SENSOR_TEMPERATURE_MEASURED: &str =
+        include_str!("../../experiment-producer/schemas/v2/sensor_temperature_measured.avsc");
+    pub const EXPERIMENT_DOCUMENT: &str =
+        include_str!("../../experiment-producer/schemas/v2/experiment_document.avsc");
+}
+
+pub const LATEST_VERSION: u32 = 2;
+
+pub const RECORD_NAME_HEADER: &str = "record_name";
+/// Header carrying the schema version an event was written with. Events without it were
+/// written with version 1.
+pub const SCHEMA_VERSION_HEADER: &str = "schema_version";
+/// Header carrying the [`Format`] of an event. Events without it are Avro encoded.
+pub const CONTENT_TYPE_HEADER: &str = "content-type";
+
+/// `record_name` header of every event. The record name doubles as the schema registry subject.
+pub const RECORD_NAMES: [&str; 7] = [
+    "experiment_configured",
+    "stabilization_started",
+    "experiment_started",
+    "experiment_terminated",
+    "sensor_temperature_measured",
+    "experiment_document",
+    "producer_heartbeat",
+];
+
+/// Every schema by record name and the version that introduced it.
+pub const SCHEMAS: [(&str, u32, &str); 11] = [
+    ("experiment_configured", 1, v1::EXPERIMENT_CONFIGURED),
+    ("stabilization_started", 1, v1::STABILIZATION_STARTED),
+    ("experiment_started", 1, v1::EXPERIMENT_STARTED),
+    ("experiment_terminated", 1, v1::EXPERIMENT_TERMINATED),
+    (
+        "sensor_temperature_measured",
+        1,
+        v1::SENSOR_TEMPERATURE_MEASURED,
+    ),
+    ("experiment_document", 1, v1::EXPERIMENT_DOCUMENT),
+    ("producer_heartbeat", 1, v1::PRODUCER_HEARTBEAT),
+    ("experiment_configured", 2, v2::EXPERIMENT_CONFIGURED),
+    ("experiment_terminated", 2, v2::EXPERIMENT_TERMINATED),
+    (
+        "sensor_temperature_measured",
+        2,
+        v2::SENSOR_TEMPERATURE_MEASURED,
+    ),
+    ("experiment_document", 2, v2::EXPERIMENT_DOCUMENT),
+];
+
+/// Schema of `record_name` in `version`, that is the most recent schema introduced at or
+/// before `version`.
+pub fn raw_schema(record_name: &str, version: u32) -> Option<&'static str> {
+    if version > LATEST_VERSION {
+        return None;
+    }
+    SCHEMAS
+        .iter()
+        .filter(|(name, introduced, _)| *name == record_name && *introduced <= version)
+        .max_by_key(|(_, introduced, _)| *introduced)
+        .map(|(_, _, raw_schema)| *raw_schema)
+}
+
+/// Metadata of an event, carried in its Kafka headers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EventHeaders {
+    pub record_name: Option<String>,
+    pub schema_version: u32,
+    pub format: Format,
+}
+
+impl EventHeaders {
+    /// Parses the `(name, value)` pairs of the Kafka headers of an event.
+    pub fn parse<'a>(
+        headers: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+    ) -> Result<Self, DecodeError> {
+        let mut event_headers = EventHeaders {
+            record_name: None,
+            schema_version: 1,
+            format: Format::Avro,
+        };
+        for (name, value) in headers {
+            let value = std::str::from_utf8(value).map_err(|_| DecodeError::InvalidHeader);
+            match name {
+                RECORD_NAME_HEADER => event_headers.record_name = Some(value?.to_string()),
+                SCHEMA_VERSION_HEADER => {
+                    event_headers.schema_version =
+                        value?.parse().map_err(|_| DecodeError::InvalidHeader)?
+                }
+                CONTENT_TYPE_HEADER => {
+                    event_headers.format =
+                        Format::from_content_type(value?).ok_or(DecodeError::InvalidHeader)?
+                }
+                _ => {}
+            }
+        }
+        Ok(event_headers)
+    }
+}
+
+const SINGLE_OBJECT_MAGIC: [u8; 2] = [0xC3, 0x01];
+const CONFLUENT_MAGIC: u8 = 0x00;
+
+/// How an Avro record is laid out in the payload of a Kafka message.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    /// Object container file, the writer schema is embedded in every message.
+    Container,
+    /// Avro single-object encoding, `C3 01` followed by the 8 byte Rabin fingerprint of the
+    /// writer schema.
+    SingleObject,
+    /// Confluent wire format, `00` followed by the 4 byte big-endian schema registry ID.
+    Confluent,
+}
+
+impl Encoding {
+    /// Accepted values of the `--encoding` command line argument.
+    pub const VALUES: [&'static str; 3] = ["container", "single-object", "confluent"];
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "container" => Ok(Encoding::Container),
+            "single-object" => Ok(Encoding::SingleObject),
+            "confluent" => Ok(Encoding::Confluent),
+            _ => Err(format!("Unknown encoding `{}`", s)),
+        }
+    }
+}
+
+/// Serialization format of the events on a topic.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// Laid out according to an [`Encoding`].
+    Avro,
+    Json,
+    /// Messages defined in `experiment-producer/schemas/events.proto`.
+    Protobuf,
+}
+
+impl Format {
+    /// Accepted values of the `--format` command line argument.
+    pub const VALUES: [&'static str; 3] = ["avro", "json", "protobuf"];
+
+    /// Value of the [`CONTENT_TYPE_HEADER`].
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Avro => "avro/binary",
+            Format::Json => "application/json",
+            Format::Protobuf => "application/x-protobuf",
+        }
+    }
+
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        match content_type {
+            "avro/binary" => Some(Format::Avro),
+            "application/json" => Some(Format::Json),
+            "application/x-protobuf" => Some(Format::Protobuf),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "avro" => Ok(Format::Avro),
+            "json" => Ok(Format::Json),
+            "protobuf" => Ok(Format::Protobuf),
+            _ => Err(format!("Unknown format `{}`", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum EncodeError {
+    Avro(apache_avro::Error),
+    MissingSchemaId,
+    Protobuf(String),
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Avro(apache_avro::Error),
+    Truncated,
+    UnexpectedMagicByte,
+    FingerprintMismatch,
+    InvalidHeader,
+    UnknownSchemaVersion(u32),
+    Json(serde_json::Error),
+    Protobuf(String),
+    Registry(RegistryError),
+}
+
+impl std::error::Error for EncodeError {}
+
+impl std::fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+fn fingerprint(schema: &Schema) -> [u8; 8] {
+    let fingerprint = schema.fingerprint::<Rabin>();
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&fingerprint.bytes[..8]);
+    bytes
+}
+
+/// Encodes `value` with `schema`.
+///
+/// `schema_id` is the schema registry ID of `schema`, only required by [`Encoding::Confluent`].
+pub fn encode<T: Into<Value>>(
+    schema: &Schema,
+    value: T,
+    encoding: Encoding,
+    schema_id: Option<u32>,
+) -> Result<Vec<u8>, EncodeError> {
+    match encoding {
+        Encoding::Container => {
+            let mut writer = Writer::new(schema, Vec::new());
+            writer.append(value).map_err(EncodeError::Avro)?;
+            writer.into_inner().map_err(EncodeError::Avro)
+        }
+        Encoding::SingleObject => {
+            let datum = to_avro_datum(schema, value).map_err(EncodeError::Avro)?;
+            let mut payload = Vec::with_capacity(10 + datum.len());
+            payload.extend_from_slice(&SINGLE_OBJECT_MAGIC);
+            payload.extend_from_slice(&fingerprint(schema));
+            payload.extend_from_slice(&datum);
+            Ok(payload)
+        }
+        Encoding::Confluent => {
+            let schema_id = schema_id.ok_or(EncodeError::MissingSchemaId)?;
+            let datum = to_avro_datum(schema, value).map_err(EncodeError::Avro)?;
+            let mut payload = Vec::with_capacity(5 + datum.len());
+            payload.push(CONFLUENT_MAGIC);
+            payload.extend_from_slice(&schema_id.to_be_bytes());
+            payload.extend_from_slice(&datum);
+            Ok(payload)
+        }
+    }
+}
+
+/// Decodes the records contained in `payload`, written with `writer_schema`, into
+/// `reader_schema`.
+///
+/// Container payloads embed their writer schema, `writer_schema` is only used by the other
+/// encodings.
+pub fn decode(
+    payload: &[u8],
+    encoding: Encoding,
+    writer_schema: &Schema,
+    reader_schema: &Schema,
+) -> Result<Vec<Value>, DecodeError> {
+    match encoding {
+        Encoding::Container => Reader::with_schema(reader_schema, payload)
+            .map_err(DecodeError::Avro)?
+            .map(|value| value.map_err(DecodeError::Avro))
+            .collect(),
+        Encoding::SingleObject => {
+            if payload.len() < 10 {
+                return Err(DecodeError::Truncated);
+            }
+            if payload[..2] != SINGLE_OBJECT_MAGIC {
+                return Err(DecodeError::UnexpectedMagicByte);
+            }
+            if payload[2..10] != fingerprint(writer_schema) {
+                return Err(DecodeError::FingerprintMismatch);
+            }
+            let value = from_avro_datum(writer_schema, &mut &payload[10..], Some(reader_schema))
+                .map_err(DecodeError::Avro)?;
+            Ok(vec![value])
+        }
+        Encoding::Confluent => {
+            let (_, mut datum) = confluent_schema_id(payload)?;
+            let value = from_avro_datum(writer_schema, &mut datum, Some(reader_schema))
+                .map_err(DecodeError::Avro)?;
+            Ok(vec![value])
+        }
+    }
+}
+
+/// Decodes the records contained in `payload` like [`decode`], except that with a `registry`
+/// Confluent payloads are decoded with the writer schema registered under their schema ID.
+pub async fn decode_with_registry(
+    payload: &[u8],
+    encoding: Encoding,
+    writer_schema: &Schema,
+    reader_schema: &Schema,
+    registry: Option<&SchemaRegistry>,
+) -> Result<Vec<Value>, DecodeError> {
+    let registry = match registry {
+        Some(registry) if encoding == Encoding::Confluent => registry,
+        _ => return decode(payload, encoding, writer_schema, reader_schema),
+    };
+    let (schema_id, mut datum) = confluent_schema_id(payload)?;
+    let writer_schema = registry
+        .schema(schema_id)
+        .await
+        .map_err(DecodeError::Registry)?;
+    let value = from_avro_datum(&writer_schema, &mut datum, Some(reader_schema))
+        .map_err(DecodeError::Avro)?;
+    Ok(vec![value])
+}
+
+/// Every version of the schema of one record, resolved into the reader schema a consumer was
+/// written against.
+pub struct RecordSchemas {
+    /// Writer schema of every version, the version is the index + 1.
+    writers: Vec<Schema>,
+    reader: Schema,
+}
+
+impl RecordSchemas {
+    /// Panics if `record_name` is not the name of an event or `reader_version` does not exist.
+    pub fn new(record_name: &str, reader_version: u32) -> Self {
+        let parse = |version| {
+            let raw_schema = raw_schema(record_name, version)
+                .unwrap_or_else(|| panic!("No version {} of `{}`", version, record_name));
+            Schema::parse_str(raw_schema).expect("Valid embedded schema")
+        };
+        Self {
+            writers: (1..=LATEST_VERSION).map(parse).collect(),
+            reader: parse(reader_version),
+        }
+    }
+
+    pub fn reader(&self) -> &Schema {
+        &self.reader
+    }
+
+    pub fn writer(&self, version: u32) -> Option<&Schema> {
+        self.writers.get((version as usize).checked_sub(1)?)
+    }
+
+    /// Decodes a payload, in the format and schema version given by its `headers`, into the
+    /// reader schema.
+    ///
+    /// `encoding` and `registry` only apply to Avro payloads.
+    pub async fn decode(
+        &self,
+        payload: &[u8],
+        headers: &EventHeaders,
+        encoding: Encoding,
+        registry: Option<&SchemaRegistry>,
+    ) -> Result<Vec<Value>, DecodeError> {
+        match headers.format {
+            Format::Avro => {
+                let version = headers.schema_version;
+                let writer_schema = self
+                    .writer(version)
+                    .ok_or(DecodeError::UnknownSchemaVersion(version))?;
+                decode_with_registry(payload, encoding, writer_schema, &self.reader, registry).await
+            }
+            Format::Json => Ok(vec![json::decode(&self.reader, payload)?]),
+            Format::Protobuf => Ok(vec![protobuf::decode(&self.reader, payload)?]),
+        }
+    }
+}
+
+/// Splits a Confluent wire format payload into its schema ID and the Avro datum.
+pub fn confluent_schema_id(payload: &[u8]) -> Result<(u32, &[u8]), DecodeError> {
+    if payload.len() < 5 {
+        return Err(DecodeError::Truncated);
+    }
+    if payload[0] != CONFLUENT_MAGIC {
+        return Err(DecodeError::UnexpectedMagicByte);
+    }
+    let schema_id = u32::from_be_bytes(payload[1..5].try_into().expect("4 bytes"));
+    Ok((schema_id, &payload[5..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use apache_avro::types::Record;
+
+    fn sensor_fields() -> [(&'static str, Value); 6] {
+        [
+            ("experiment", "5678".into()),
+            ("sensor", "sensor-1".into()),
+            ("measurement_id", "1234".into()),
+            ("timestamp", Value::Double(1692029115.4314)),
+            ("temperature", Value::Float(25.7)),
+            ("measurement_hash", "nonce.ciphertext".into()),
+        ]
+    }
+
+    fn sensor_event(schema: &Schema) -> Value {
+        let mut record = Record::new(schema).unwrap();
+        for (field, value) in sensor_fields() {
+            record.put(field, value);
+        }
+        record.into()
+    }
+
+    #[test]
+    fn roundtrip_every_encoding() {
+        let schema = Schema::parse_str(v1::SENSOR_TEMPERATURE_MEASURED).unwrap();
+        for encoding in [
+            Encoding::Container,
+            Encoding::SingleObject,
+            Encoding::Confluent,
+        ] {
+            let payload = encode(&schema, sensor_event(&schema), encoding, Some(7)).unwrap();
+            let values = decode(&payload, encoding, &schema, &schema).unwrap();
+            assert_eq!(values, vec![sensor_event(&schema)], "{:?}", encoding);
+        }
+    }
+
+    #[test]
+    fn single_object_is_smaller_than_container() {
+        let schema = Schema::parse_str(v1::SENSOR_TEMPERATURE_MEASURED).unwrap();
+        let container = encode(&schema, sensor_event(&schema), Encoding::Container, None).unwrap();
+        let single = encode(&schema, sensor_event(&schema), Encoding::SingleObject, None).unwrap();
+        assert!(single.len() * 4 < container.len());
+    }
+
+    #[test]
+    fn embedded_schemas_are_valid() {
+        for (record_name, version, raw_schema) in SCHEMAS {
+            assert!(
+                Schema::parse_str(raw_schema).is_ok(),
+                "{} v{}",
+                record_name,
+                version
+            );
+        }
+    }
+
+    #[test]
+    fn confluent_requires_schema_id() {
+        let schema = Schema::parse_str(v1::SENSOR_TEMPERATURE_MEASURED).unwrap();
+        let result = encode(&schema, sensor_event(&schema), Encoding::Confluent, None);
+        assert!(matches!(result, Err(EncodeError::MissingSchemaId)));
+    }
+
+    #[test]
+    fn reject_single_object_of_other_schema() {
+        let schema = Schema::parse_str(v1::SENSOR_TEMPERATURE_MEASURED).unwrap();
+        let other = Schema::parse_str(v1::EXPERIMENT_DOCUMENT).unwrap();
+        let payload = encode(&schema, sensor_event(&schema), Encoding::SingleObject, None).unwrap();
+        assert!(matches!(
+            decode(&payload, Encoding::SingleObject, &other, &other),
+            Err(DecodeError::FingerprintMismatch)
+        ));
+        assert!(matches!(
+            decode(&payload[..4], Encoding::SingleObject, &schema, &schema),
+            Err(DecodeError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn unchanged_schemas_carry_over_to_later_versions() {
+        assert_eq!(
+            raw_schema("experiment_started", LATEST_VERSION),
+            Some(v1::EXPERIMENT_STARTED)
+        );
+        assert_eq!(
+            raw_schema("experiment_terminated", 2),
+            Some(v2::EXPERIMENT_TERMINATED)
+        );
+        assert_eq!(raw_schema("experiment_started", LATEST_VERSION + 1), None);
+        assert_eq!(raw_schema("unknown", 1), None);
+    }
+
+    #[test]
+    fn parse_event_headers() {
+        let headers = EventHeaders::parse([]).unwrap();
+        assert_eq!(headers.schema_version, 1);
+        assert_eq!(headers.format, Format::Avro);
+
+        let headers = EventHeaders::parse([
+            (RECORD_NAME_HEADER, "experiment_started".as_bytes()),
+            (SCHEMA_VERSION_HEADER, b"2"),
+            (
+                CONTENT_TYPE_HEADER,
+                Format::Protobuf.content_type().as_bytes(),
+            ),
+        ])
+        .unwrap();
+        assert_eq!(
+            headers,
+            EventHeaders {
+                record_name: Some("experiment_started".into()),
+                schema_version: 2,
+                format: Format::Protobuf,
+            }
+        );
+        assert!(matches!(
+            EventHeaders::parse([(SCHEMA_VERSION_HEADER, "two".as_bytes())]),
+            Err(DecodeError::InvalidHeader)
+        ));
+    }
+
+    #[tokio::test]
+    async fn resolve_every_version_into_the_reader_schema() {
+        let v1_schemas = RecordSchemas::new("sensor_temperature_measured", 1);
+        let v2_schemas = RecordSchemas::new("sensor_temperature_measured", 2);
+        let v1 = v1_schemas.reader();
+        let v2 = v2_schemas.reader();
+        let mut record = Record::new(v2).unwrap();
+        for (field, value) in sensor_fields() {
+            record.put(field, value);
+        }
+        record.put("stage", Value::Union(1, Box::new("carry_out".into())));
+        let v2_event: Value = record.into();
+
+        let headers = |schema_version| EventHeaders {
+            record_name: None,
+            schema_version,
+            format: Format::Avro,
+        };
+        for encoding in [Encoding::Container, Encoding::SingleObject] {
+            // Last year's consumer reading this year's event skips the new field
+            let payload = encode(v2, v2_event.clone(), encoding, None).unwrap();
+            let values = v1_schemas
+                .decode(&payload, &headers(2), encoding, None)
+                .await
+                .unwrap();
+            assert_eq!(values, vec![sensor_event(v1)], "{:?}", encoding);
+
+            // This year's consumer reading last year's event gets the default
+            let payload = encode(v1, sensor_event(v1), encoding, None).unwrap();
+            let values = v2_schemas
+                .decode(&payload, &headers(1), encoding, None)
+                .await
+                .unwrap();
+            let Value::Record(fields) = &values[0] else {
+                panic!("Expected a record");
+            };
+            assert_eq!(
+                fields.last(),
+                Some(&("stage".to_string(), Value::Union(0, Box::new(Value::Null)))),
+                "{:?}",
+                encoding
+            );
+        }
+        assert!(matches!(
+            v1_schemas
+                .decode(&[], &headers(3), Encoding::SingleObject, None)
+                .await,
+            Err(DecodeError::UnknownSchemaVersion(3))
+        ));
+    }
+
+    #[tokio::test]
+    async fn decode_every_format() {
+        let schemas = RecordSchemas::new("sensor_temperature_measured", 1);
+        let schema = schemas.reader();
+        for (format, payload) in [
+            (
+                Format::Avro,
+                encode(schema, sensor_event(schema), Encoding::Container, None).unwrap(),
+            ),
+            (Format::Json, json::encode(sensor_event(schema)).unwrap()),
+            (
+                Format::Protobuf,
+                protobuf::encode(schema, &sensor_event(schema)).unwrap(),
+            ),
+        ] {
+            let headers = EventHeaders {
+                record_name: Some("sensor_temperature_measured".into()),
+                schema_version: 1,
+                format,
+            };
+            let values = schemas
+                .decode(&payload, &headers, Encoding::Container, None)
+                .await
+                .unwrap();
+            assert_eq!(values, vec![sensor_event(schema)], "{:?}", format);
+        }
+    }
+}
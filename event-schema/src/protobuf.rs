@@ -0,0 +1,483 @@
+//! Protobuf encoding of the events, derived from their Avro schema.
+//!
+//! Every Avro record maps to a message whose field numbers follow the order of the record
+//! fields, starting at 1, as spelled out in `experiment-producer/schemas/events.proto`. Fields
+//! are only ever appended to the Avro schemas, so the field numbers are stable across versions.
+//!
+//! | Avro                | Protobuf             |
+//! |---------------------|----------------------|
+//! | `string`            | `string`             |
+//! | `bytes`             | `bytes`              |
+//! | `boolean`           | `bool`               |
+//! | `int`, `long`       | `int32`, `int64`     |
+//! | `float`, `double`   | `float`, `double`    |
+//! | `record`            | message              |
+//! | `array`             | `repeated`           |
+//! | `["null", T]`       | `optional T`         |
+use apache_avro::{
+    schema::{RecordSchema, UnionSchema},
+    types::Value,
+    Schema,
+};
+
+use crate::{DecodeError, EncodeError};
+
+const VARINT: u8 = 0;
+const I64: u8 = 1;
+const LEN: u8 = 2;
+const I32: u8 = 5;
+
+/// Encodes `value`, a record of `schema`, as a protobuf message.
+pub fn encode(schema: &Schema, value: &Value) -> Result<Vec<u8>, EncodeError> {
+    let mut buf = Vec::new();
+    encode_message(schema, value, &mut buf)?;
+    Ok(buf)
+}
+
+/// Decodes a protobuf message into a record of `schema`.
+///
+/// Fields unknown to `schema` are skipped and missing fields get their protobuf default value.
+pub fn decode(schema: &Schema, payload: &[u8]) -> Result<Value, DecodeError> {
+    decode_message(schema, payload)
+}
+
+fn unsupported(schema: &Schema) -> EncodeError {
+    EncodeError::Protobuf(format!("Unsupported schema {:?}", schema))
+}
+
+fn malformed(message: &str) -> DecodeError {
+    DecodeError::Protobuf(message.to_string())
+}
+
+/// The `T` of a `["null", T]` union, with its index in the union.
+fn optional(union: &UnionSchema) -> Option<(usize, &Schema)> {
+    match union.variants() {
+        [Schema::Null, schema] => Some((1, schema)),
+        [schema, Schema::Null] => Some((0, schema)),
+        _ => None,
+    }
+}
+
+fn is_packed(schema: &Schema) -> bool {
+    matches!(
+        schema,
+        Schema::Boolean | Schema::Int | Schema::Long | Schema::Float | Schema::Double
+    )
+}
+
+fn put_varint(mut value: u64, buf: &mut Vec<u8>) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn put_tag(number: usize, wire_type: u8, buf: &mut Vec<u8>) {
+    put_varint(((number as u64) << 3) | wire_type as u64, buf);
+}
+
+fn put_len(bytes: &[u8], buf: &mut Vec<u8>) {
+    put_varint(bytes.len() as u64, buf);
+    buf.extend_from_slice(bytes);
+}
+
+fn encode_message(schema: &Schema, value: &Value, buf: &mut Vec<u8>) -> Result<(), EncodeError> {
+    let (Schema::Record(RecordSchema { fields, .. }), Value::Record(values)) = (schema, value)
+    else {
+        return Err(unsupported(schema));
+    };
+    for (idx, field) in fields.iter().enumerate() {
+        let value = values
+            .iter()
+            .find(|(name, _)| *name == field.name)
+            .map(|(_, value)| value)
+            .ok_or_else(|| EncodeError::Protobuf(format!("Missing field `{}`", field.name)))?;
+        encode_field(idx + 1, &field.schema, value, false, buf)?;
+    }
+    Ok(())
+}
+
+/// Encodes a singular field, omitted when it holds the default value unless it has `presence`.
+fn encode_field(
+    number: usize,
+    schema: &Schema,
+    value: &Value,
+    presence: bool,
+    buf: &mut Vec<u8>,
+) -> Result<(), EncodeError> {
+    match (schema, value) {
+        (Schema::Union(union), Value::Union(_, value)) => match (optional(union), &**value) {
+            (Some(_), Value::Null) => {}
+            (Some((_, schema)), value) => encode_field(number, schema, value, true, buf)?,
+            (None, _) => return Err(unsupported(schema)),
+        },
+        (Schema::String, Value::String(s)) if presence || !s.is_empty() => {
+            put_tag(number, LEN, buf);
+            put_len(s.as_bytes(), buf);
+        }
+        (Schema::Bytes, Value::Bytes(b)) if presence || !b.is_empty() => {
+            put_tag(number, LEN, buf);
+            put_len(b, buf);
+        }
+        (Schema::Boolean, Value::Boolean(b)) if presence || *b => {
+            put_tag(number, VARINT, buf);
+            put_varint(*b as u64, buf);
+        }
+        (Schema::Int, Value::Int(i)) if presence || *i != 0 => {
+            put_tag(number, VARINT, buf);
+            put_varint(*i as i64 as u64, buf);
+        }
+        (Schema::Long, Value::Long(l)) if presence || *l != 0 => {
+            put_tag(number, VARINT, buf);
+            put_varint(*l as u64, buf);
+        }
+        (Schema::Float, Value::Float(f)) if presence || *f != 0.0 => {
+            put_tag(number, I32, buf);
+            buf.extend_from_slice(&f.to_le_bytes());
+        }
+        (Schema::Double, Value::Double(d)) if presence || *d != 0.0 => {
+            put_tag(number, I64, buf);
+            buf.extend_from_slice(&d.to_le_bytes());
+        }
+        (Schema::Record(_), Value::Record(_)) => {
+            let mut message = Vec::new();
+            encode_message(schema, value, &mut message)?;
+            put_tag(number, LEN, buf);
+            put_len(&message, buf);
+        }
+        (Schema::Array(items), Value::Array(values)) if is_packed(items) => {
+            if !values.is_empty() {
+                let mut packed = Vec::new();
+                for value in values {
+                    encode_scalar(items, value, &mut packed)?;
+                }
+                put_tag(number, LEN, buf);
+                put_len(&packed, buf);
+            }
+        }
+        (Schema::Array(items), Value::Array(values)) => {
+            for value in values {
+                encode_field(number, items, value, true, buf)?;
+            }
+        }
+        (
+            Schema::String
+            | Schema::Bytes
+            | Schema::Boolean
+            | Schema::Int
+            | Schema::Long
+            | Schema::Float
+            | Schema::Double,
+            _,
+        ) if value.validate(schema) => {}
+        _ => return Err(unsupported(schema)),
+    }
+    Ok(())
+}
+
+/// Encodes an element of a packed repeated field, without tag.
+fn encode_scalar(schema: &Schema, value: &Value, buf: &mut Vec<u8>) -> Result<(), EncodeError> {
+    match value {
+        Value::Boolean(b) => put_varint(*b as u64, buf),
+        Value::Int(i) => put_varint(*i as i64 as u64, buf),
+        Value::Long(l) => put_varint(*l as u64, buf),
+        Value::Float(f) => buf.extend_from_slice(&f.to_le_bytes()),
+        Value::Double(d) => buf.extend_from_slice(&d.to_le_bytes()),
+        _ => return Err(unsupported(schema)),
+    }
+    Ok(())
+}
+
+struct Input<'a>(&'a [u8]);
+
+impl<'a> Input<'a> {
+    fn varint(&mut self) -> Result<u64, DecodeError> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self.0.split_first().ok_or(DecodeError::Truncated)?;
+            self.0 = rest;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte < 0x80 {
+                return Ok(value);
+            }
+        }
+        Err(malformed("Varint longer than 10 bytes"))
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if self.0.len() < len {
+            return Err(DecodeError::Truncated);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn len_delimited(&mut self) -> Result<&'a [u8], DecodeError> {
+        let len = usize::try_from(self.varint()?).map_err(|_| DecodeError::Truncated)?;
+        self.bytes(len)
+    }
+
+    fn fixed<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        Ok(self.bytes(N)?.try_into().expect("N bytes"))
+    }
+
+    fn skip(&mut self, wire_type: u8) -> Result<(), DecodeError> {
+        match wire_type {
+            VARINT => self.varint().map(drop),
+            I64 => self.bytes(8).map(drop),
+            LEN => self.len_delimited().map(drop),
+            I32 => self.bytes(4).map(drop),
+            _ => Err(malformed("Unsupported wire type")),
+        }
+    }
+}
+
+/// Protobuf default value of a field of `schema`.
+fn default_value(schema: &Schema) -> Result<Value, DecodeError> {
+    Ok(match schema {
+        Schema::Union(union) => match optional(union) {
+            Some((idx, _)) => Value::Union(1 - idx as u32, Box::new(Value::Null)),
+            None => return Err(malformed("Unsupported union")),
+        },
+        Schema::String => Value::String(String::new()),
+        Schema::Bytes => Value::Bytes(Vec::new()),
+        Schema::Boolean => Value::Boolean(false),
+        Schema::Int => Value::Int(0),
+        Schema::Long => Value::Long(0),
+        Schema::Float => Value::Float(0.0),
+        Schema::Double => Value::Double(0.0),
+        Schema::Array(_) => Value::Array(Vec::new()),
+        Schema::Record(_) => decode_message(schema, &[])?,
+        _ => return Err(malformed("Unsupported schema")),
+    })
+}
+
+fn decode_message(schema: &Schema, payload: &[u8]) -> Result<Value, DecodeError> {
+    let Schema::Record(RecordSchema { fields, .. }) = schema else {
+        return Err(malformed("Unsupported schema"));
+    };
+    let mut values = fields
+        .iter()
+        .map(|field| Ok((field.name.clone(), default_value(&field.schema)?)))
+        .collect::<Result<Vec<_>, DecodeError>>()?;
+    let mut input = Input(payload);
+    while !input.0.is_empty() {
+        let tag = input.varint()?;
+        let wire_type = (tag & 0x07) as u8;
+        let field = usize::try_from(tag >> 3)
+            .ok()
+            .and_then(|number| number.checked_sub(1))
+            .and_then(|idx| Some((idx, fields.get(idx)?)));
+        match field {
+            Some((idx, field)) => {
+                decode_field(&field.schema, wire_type, &mut input, &mut values[idx].1)?
+            }
+            // Field added by a later version
+            None => input.skip(wire_type)?,
+        }
+    }
+    Ok(Value::Record(values))
+}
+
+fn decode_field(
+    schema: &Schema,
+    wire_type: u8,
+    input: &mut Input,
+    value: &mut Value,
+) -> Result<(), DecodeError> {
+    match schema {
+        Schema::Union(union) => {
+            let (idx, schema) = optional(union).ok_or_else(|| malformed("Unsupported union"))?;
+            let mut inner = default_value(schema)?;
+            decode_field(schema, wire_type, input, &mut inner)?;
+            *value = Value::Union(idx as u32, Box::new(inner));
+        }
+        Schema::Record(_) if wire_type == LEN => {
+            *value = decode_message(schema, input.len_delimited()?)?;
+        }
+        Schema::Array(items) => {
+            let Value::Array(values) = value else {
+                return Err(malformed("Unexpected value"));
+            };
+            if is_packed(items) && wire_type == LEN {
+                let mut packed = Input(input.len_delimited()?);
+                while !packed.0.is_empty() {
+                    values.push(decode_scalar(items, &mut packed)?);
+                }
+            } else {
+                let mut item = default_value(items)?;
+                decode_field(items, wire_type, input, &mut item)?;
+                values.push(item);
+            }
+        }
+        Schema::String if wire_type == LEN => {
+            let s = std::str::from_utf8(input.len_delimited()?)
+                .map_err(|_| malformed("Invalid UTF-8 string"))?;
+            *value = Value::String(s.to_string());
+        }
+        Schema::Bytes if wire_type == LEN => {
+            *value = Value::Bytes(input.len_delimited()?.to_vec());
+        }
+        Schema::Boolean | Schema::Int | Schema::Long if wire_type == VARINT => {
+            *value = decode_scalar(schema, input)?;
+        }
+        Schema::Float if wire_type == I32 => *value = decode_scalar(schema, input)?,
+        Schema::Double if wire_type == I64 => *value = decode_scalar(schema, input)?,
+        _ => return Err(malformed("Unexpected wire type")),
+    }
+    Ok(())
+}
+
+/// Decodes a scalar, without tag.
+fn decode_scalar(schema: &Schema, input: &mut Input) -> Result<Value, DecodeError> {
+    Ok(match schema {
+        Schema::Boolean => Value::Boolean(input.varint()? != 0),
+        Schema::Int => Value::Int(input.varint()? as i32),
+        Schema::Long => Value::Long(input.varint()? as i64),
+        Schema::Float => Value::Float(f32::from_le_bytes(input.fixed()?)),
+        Schema::Double => Value::Double(f64::from_le_bytes(input.fixed()?)),
+        _ => return Err(malformed("Unsupported schema")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{v1, v2};
+    use apache_avro::types::Record;
+    use prost::Message;
+
+    // Generated from experiment-producer/schemas/events.proto
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct SensorTemperatureMeasured {
+        #[prost(string, tag = "1")]
+        experiment: String,
+        #[prost(string, tag = "2")]
+        sensor: String,
+        #[prost(string, tag = "3")]
+        measurement_id: String,
+        #[prost(double, tag = "4")]
+        timestamp: f64,
+        #[prost(float, tag = "5")]
+        temperature: f32,
+        #[prost(string, tag = "6")]
+        measurement_hash: String,
+        #[prost(string, optional, tag = "7")]
+        stage: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Measurement {
+        #[prost(double, tag = "1")]
+        timestamp: f64,
+        #[prost(float, tag = "2")]
+        temperature: f32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct TemperatureRange {
+        #[prost(float, tag = "1")]
+        upper_threshold: f32,
+        #[prost(float, tag = "2")]
+        lower_threshold: f32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct ExperimentDocument {
+        #[prost(string, tag = "1")]
+        experiment: String,
+        #[prost(message, repeated, tag = "2")]
+        measurements: Vec<Measurement>,
+        #[prost(message, optional, tag = "3")]
+        temperature_range: Option<TemperatureRange>,
+        #[prost(bool, tag = "4")]
+        interrupted: bool,
+    }
+
+    fn sensor_event(schema: &Schema, stage: Option<&str>) -> Value {
+        let mut record = Record::new(schema).unwrap();
+        record.put("experiment", "5678");
+        record.put("sensor", "sensor-1");
+        record.put("measurement_id", "1234");
+        record.put("timestamp", Value::Double(1692029115.4314));
+        record.put("temperature", Value::Float(0.0));
+        record.put("measurement_hash", "nonce.ciphertext");
+        if let Some(stage) = stage {
+            record.put("stage", Value::Union(1, Box::new(stage.into())));
+        }
+        record.into()
+    }
+
+    #[test]
+    fn match_proto_definitions() {
+        let schema = Schema::parse_str(v2::SENSOR_TEMPERATURE_MEASURED).unwrap();
+        let payload = encode(&schema, &sensor_event(&schema, Some(""))).unwrap();
+        let message = SensorTemperatureMeasured {
+            experiment: "5678".into(),
+            sensor: "sensor-1".into(),
+            measurement_id: "1234".into(),
+            timestamp: 1692029115.4314,
+            temperature: 0.0,
+            measurement_hash: "nonce.ciphertext".into(),
+            stage: Some("".into()),
+        };
+        assert_eq!(payload, message.encode_to_vec());
+
+        let document = ExperimentDocument {
+            experiment: "5678".into(),
+            measurements: vec![
+                Measurement {
+                    timestamp: 1692029115.4314,
+                    temperature: 11.5,
+                },
+                Measurement {
+                    timestamp: 1692029116.4314,
+                    temperature: -1.0,
+                },
+            ],
+            temperature_range: Some(TemperatureRange {
+                upper_threshold: 12.0,
+                lower_threshold: 10.0,
+            }),
+            interrupted: true,
+        };
+        let schema = Schema::parse_str(v2::EXPERIMENT_DOCUMENT).unwrap();
+        let value = decode(&schema, &document.encode_to_vec()).unwrap();
+        assert_eq!(encode(&schema, &value).unwrap(), document.encode_to_vec());
+    }
+
+    #[test]
+    fn resolve_across_versions() {
+        let v1 = Schema::parse_str(v1::SENSOR_TEMPERATURE_MEASURED).unwrap();
+        let v2 = Schema::parse_str(v2::SENSOR_TEMPERATURE_MEASURED).unwrap();
+
+        let payload = encode(&v2, &sensor_event(&v2, Some("carry_out"))).unwrap();
+        assert_eq!(decode(&v1, &payload).unwrap(), sensor_event(&v1, None));
+
+        let payload = encode(&v1, &sensor_event(&v1, None)).unwrap();
+        let Value::Record(fields) = decode(&v2, &payload).unwrap() else {
+            panic!("Expected a record");
+        };
+        assert_eq!(
+            fields.last(),
+            Some(&("stage".to_string(), Value::Union(0, Box::new(Value::Null))))
+        );
+    }
+
+    #[test]
+    fn reject_malformed_payloads() {
+        let schema = Schema::parse_str(v1::SENSOR_TEMPERATURE_MEASURED).unwrap();
+        let payload = encode(&schema, &sensor_event(&schema, None)).unwrap();
+        assert!(matches!(
+            decode(&schema, &payload[..payload.len() - 1]),
+            Err(DecodeError::Truncated)
+        ));
+        // `experiment` as a varint
+        assert!(matches!(
+            decode(&schema, &[0x08, 0x01]),
+            Err(DecodeError::Protobuf(_))
+        ));
+    }
+}
@@ -0,0 +1,111 @@
+use apache_avro::Schema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Debug)]
+pub enum RegistryError {
+    Http(reqwest::Error),
+    Status(u16, String),
+    InvalidSchema(apache_avro::Error),
+}
+
+impl std::error::Error for RegistryError {}
+
+impl std::fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl From<reqwest::Error> for RegistryError {
+    fn from(e: reqwest::Error) -> Self {
+        RegistryError::Http(e)
+    }
+}
+
+#[derive(Serialize)]
+struct RegisterRequest<'a> {
+    schema: &'a str,
+}
+
+#[derive(Deserialize)]
+struct RegisterResponse {
+    id: u32,
+}
+
+#[derive(Deserialize)]
+struct SchemaResponse {
+    schema: String,
+}
+
+/// Client for the subset of the Confluent schema registry REST API used by the producer and
+/// the consumers.
+///
+/// Schemas fetched by ID are cached, a registered schema never changes.
+pub struct SchemaRegistry {
+    url: String,
+    client: reqwest::Client,
+    schemas: Mutex<HashMap<u32, Arc<Schema>>>,
+}
+
+impl SchemaRegistry {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+            schemas: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Registers `schema` under `subject`, returning its ID.
+    ///
+    /// Registering a schema that already exists returns the existing ID.
+    pub async fn register(&self, subject: &str, schema: &Schema) -> Result<u32, RegistryError> {
+        let response = self
+            .client
+            .post(format!("{}/subjects/{}/versions", self.url, subject))
+            .header("Content-Type", "application/vnd.schemaregistry.v1+json")
+            .json(&RegisterRequest {
+                schema: &schema.canonical_form(),
+            })
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(RegistryError::Status(
+                response.status().as_u16(),
+                response.text().await?,
+            ));
+        }
+        let RegisterResponse { id } = response.json().await?;
+        self.schemas
+            .lock()
+            .unwrap()
+            .insert(id, Arc::new(schema.clone()));
+        Ok(id)
+    }
+
+    /// Returns the schema registered under `id`.
+    pub async fn schema(&self, id: u32) -> Result<Arc<Schema>, RegistryError> {
+        if let Some(schema) = self.schemas.lock().unwrap().get(&id) {
+            return Ok(schema.clone());
+        }
+        let response = self
+            .client
+            .get(format!("{}/schemas/ids/{}", self.url, id))
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(RegistryError::Status(
+                response.status().as_u16(),
+                response.text().await?,
+            ));
+        }
+        let SchemaResponse { schema } = response.json().await?;
+        let schema = Arc::new(Schema::parse_str(&schema).map_err(RegistryError::InvalidSchema)?);
+        self.schemas.lock().unwrap().insert(id, schema.clone());
+        Ok(schema)
+    }
+}
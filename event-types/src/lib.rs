@@ -0,0 +1,261 @@
+use apache_avro::{types::Value, Schema};
+use event_schema::{EncodeError, Encoding};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Event of the experiment-producer, with the fields of the latest version of its schema.
+///
+/// Fields introduced after the first version default when reading an older record.
+pub trait Event: Serialize + DeserializeOwned {
+    const RECORD_NAME: &'static str;
+
+    /// Converts the event into a record of `schema`, any version of its schema. Fields the
+    /// version does not have are left out.
+    fn to_value(&self, schema: &Schema) -> Result<Value, apache_avro::Error> {
+        apache_avro::to_value(self)?.resolve(schema)
+    }
+
+    /// Reads the event from a record of its schema.
+    fn from_value(value: &Value) -> Result<Self, apache_avro::Error> {
+        apache_avro::from_value(value)
+    }
+
+    /// Encodes the event as a record of `schema`, see [`event_schema::encode`].
+    fn encode(
+        &self,
+        schema: &Schema,
+        encoding: Encoding,
+        schema_id: Option<u32>,
+    ) -> Result<Vec<u8>, EncodeError> {
+        let value = self.to_value(schema).map_err(EncodeError::Avro)?;
+        event_schema::encode(schema, value, encoding, schema_id)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TemperatureRange {
+    pub upper_threshold: f32,
+    pub lower_threshold: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Measurement {
+    pub timestamp: f64,
+    pub temperature: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExperimentConfigured {
+    pub experiment: String,
+    pub researcher: String,
+    pub sensors: Vec<String>,
+    pub temperature_range: TemperatureRange,
+    /// Since v2
+    #[serde(default)]
+    pub timestamp: f64,
+}
+
+impl Event for ExperimentConfigured {
+    const RECORD_NAME: &'static str = "experiment_configured";
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StabilizationStarted {
+    pub experiment: String,
+    pub timestamp: f64,
+}
+
+impl Event for StabilizationStarted {
+    const RECORD_NAME: &'static str = "stabilization_started";
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExperimentStarted {
+    pub experiment: String,
+    pub timestamp: f64,
+}
+
+impl Event for ExperimentStarted {
+    const RECORD_NAME: &'static str = "experiment_started";
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExperimentTerminated {
+    pub experiment: String,
+    pub timestamp: f64,
+    /// Since v2
+    #[serde(default)]
+    pub interrupted: bool,
+}
+
+impl Event for ExperimentTerminated {
+    const RECORD_NAME: &'static str = "experiment_terminated";
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SensorTemperatureMeasured {
+    pub experiment: String,
+    pub sensor: String,
+    pub measurement_id: String,
+    pub timestamp: f64,
+    pub temperature: f32,
+    pub measurement_hash: String,
+    /// Since v2, `stabilization` or `carry_out`
+    #[serde(default)]
+    pub stage: Option<String>,
+}
+
+impl Event for SensorTemperatureMeasured {
+    const RECORD_NAME: &'static str = "sensor_temperature_measured";
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExperimentDocument {
+    pub experiment: String,
+    pub measurements: Vec<Measurement>,
+    pub temperature_range: TemperatureRange,
+    /// Since v2
+    #[serde(default)]
+    pub interrupted: bool,
+}
+
+impl Event for ExperimentDocument {
+    const RECORD_NAME: &'static str = "experiment_document";
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProducerHeartbeat {
+    pub experiment: String,
+    pub timestamp: f64,
+    /// Timestamp of the latest measurement whose sensor events were all published, unset
+    /// before the first one.
+    pub watermark: Option<f64>,
+}
+
+impl Event for ProducerHeartbeat {
+    const RECORD_NAME: &'static str = "producer_heartbeat";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use event_schema::{raw_schema, LATEST_VERSION, RECORD_NAMES};
+    use std::fmt::Debug;
+
+    const TEMPERATURE_RANGE: TemperatureRange = TemperatureRange {
+        upper_threshold: 26.0,
+        lower_threshold: 24.0,
+    };
+
+    fn schema(record_name: &str, version: u32) -> Schema {
+        Schema::parse_str(raw_schema(record_name, version).unwrap()).unwrap()
+    }
+
+    /// The fields of `value` are those of `schema`, in the same order.
+    fn assert_fields(value: &Value, schema: &Schema, path: &str) {
+        match (value, schema) {
+            (Value::Record(fields), Schema::Record(record)) => {
+                let names: Vec<_> = fields.iter().map(|(name, _)| name.as_str()).collect();
+                let expected: Vec<_> = record.fields.iter().map(|f| f.name.as_str()).collect();
+                assert_eq!(names, expected, "{}", path);
+                for ((name, value), field) in fields.iter().zip(&record.fields) {
+                    assert_fields(value, &field.schema, &format!("{}.{}", path, name));
+                }
+            }
+            (Value::Array(items), Schema::Array(items_schema)) => {
+                for item in items {
+                    assert_fields(item, items_schema, path);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn assert_matches_schemas<E: Event + PartialEq + Debug>(event: E) -> &'static str {
+        let latest = schema(E::RECORD_NAME, LATEST_VERSION);
+        assert_fields(&apache_avro::to_value(&event).unwrap(), &latest, E::RECORD_NAME);
+
+        for version in 1..=LATEST_VERSION {
+            let writer = schema(E::RECORD_NAME, version);
+            let payload = event.encode(&writer, Encoding::SingleObject, None).unwrap();
+            for reader in [&writer, &latest] {
+                let values =
+                    event_schema::decode(&payload, Encoding::SingleObject, &writer, reader)
+                        .unwrap();
+                let decoded = E::from_value(&values[0]).unwrap();
+                if version == LATEST_VERSION {
+                    assert_eq!(decoded, event);
+                }
+            }
+        }
+        E::RECORD_NAME
+    }
+
+    #[test]
+    fn every_event_matches_every_version_of_its_schema() {
+        let record_names = [
+            assert_matches_schemas(ExperimentConfigured {
+                experiment: "5678".into(),
+                researcher: "d.landau@uu.nl".into(),
+                sensors: vec!["sensor-1".into(), "sensor-2".into()],
+                temperature_range: TEMPERATURE_RANGE,
+                timestamp: 1692029115.4314,
+            }),
+            assert_matches_schemas(StabilizationStarted {
+                experiment: "5678".into(),
+                timestamp: 1692029115.4314,
+            }),
+            assert_matches_schemas(ExperimentStarted {
+                experiment: "5678".into(),
+                timestamp: 1692029115.4314,
+            }),
+            assert_matches_schemas(ExperimentTerminated {
+                experiment: "5678".into(),
+                timestamp: 1692029115.4314,
+                interrupted: true,
+            }),
+            assert_matches_schemas(SensorTemperatureMeasured {
+                experiment: "5678".into(),
+                sensor: "sensor-1".into(),
+                measurement_id: "1234".into(),
+                timestamp: 1692029115.4314,
+                temperature: 25.7,
+                measurement_hash: "nonce.ciphertext".into(),
+                stage: Some("carry_out".into()),
+            }),
+            assert_matches_schemas(ExperimentDocument {
+                experiment: "5678".into(),
+                measurements: vec![Measurement {
+                    timestamp: 1692029115.4314,
+                    temperature: 25.7,
+                }],
+                temperature_range: TEMPERATURE_RANGE,
+                interrupted: true,
+            }),
+            assert_matches_schemas(ProducerHeartbeat {
+                experiment: "5678".into(),
+                timestamp: 1692029115.4314,
+                watermark: None,
+            }),
+        ];
+        assert_eq!(record_names, RECORD_NAMES);
+    }
+
+    #[test]
+    fn older_records_default_newer_fields() {
+        let event = ExperimentTerminated {
+            experiment: "5678".into(),
+            timestamp: 1692029115.4314,
+            interrupted: true,
+        };
+        let v1 = schema(ExperimentTerminated::RECORD_NAME, 1);
+        let value = event.to_value(&v1).unwrap();
+        assert!(value.validate(&v1));
+        assert_eq!(
+            ExperimentTerminated::from_value(&value).unwrap(),
+            ExperimentTerminated {
+                interrupted: false,
+                ..event
+            }
+        );
+    }
+}
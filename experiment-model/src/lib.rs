@@ -0,0 +1,350 @@
+use serde::{Deserialize, Serialize};
+use std::ops::{Bound, RangeBounds, RangeInclusive};
+use std::sync::OnceLock;
+
+/// Largest difference between a timestamp, or a temperature, and its value rounded by an API.
+pub const TOLERANCE: f64 = 0.001;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Measurement {
+    pub timestamp: f64,
+    pub temperature: f32,
+}
+
+impl Measurement {
+    /// Whether `other` is this measurement up to [`TOLERANCE`].
+    pub fn approx_eq(&self, other: &Self) -> bool {
+        (self.timestamp - other.timestamp).abs() < TOLERANCE
+            && ((self.temperature - other.temperature).abs() as f64) < TOLERANCE
+    }
+}
+
+/// Whether both slices hold the same measurements in the same order, up to [`TOLERANCE`].
+pub fn approx_eq(measurements: &[Measurement], other: &[Measurement]) -> bool {
+    measurements.len() == other.len()
+        && measurements
+            .iter()
+            .zip(other)
+            .all(|(measurement, other)| measurement.approx_eq(other))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TempRange {
+    pub upper_threshold: f32,
+    pub lower_threshold: f32,
+}
+
+impl TempRange {
+    /// The thresholds themselves are in range.
+    pub fn is_out_of_range(&self, temperature: f32) -> bool {
+        temperature > self.upper_threshold || temperature < self.lower_threshold
+    }
+}
+
+/// Measurements of an experiment, as published in its `experiment_document`.
+#[derive(Clone, Debug)]
+pub struct ExperimentDocument {
+    pub experiment: String,
+    pub temperature_range: TempRange,
+    /// Sorted by timestamp.
+    measurements: Vec<Measurement>,
+    out_of_range: OnceLock<Vec<Measurement>>,
+}
+
+impl ExperimentDocument {
+    pub fn new(
+        experiment: String,
+        mut measurements: Vec<Measurement>,
+        temperature_range: TempRange,
+    ) -> Self {
+        measurements.sort_by(|a, b| a.timestamp.total_cmp(&b.timestamp));
+        Self {
+            experiment,
+            temperature_range,
+            measurements,
+            out_of_range: OnceLock::new(),
+        }
+    }
+
+    /// Every measurement, sorted by timestamp.
+    pub fn measurements(&self) -> &[Measurement] {
+        &self.measurements
+    }
+
+    /// Measurements whose timestamp lies in `range`, e.g. `start..=end` or `start..end`. Empty if
+    /// the range is.
+    pub fn range(&self, range: impl RangeBounds<f64>) -> &[Measurement] {
+        let start = match range.start_bound() {
+            Bound::Included(start) => self.partition_point(|timestamp| timestamp < *start),
+            Bound::Excluded(start) => self.partition_point(|timestamp| timestamp <= *start),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => self.partition_point(|timestamp| timestamp <= *end),
+            Bound::Excluded(end) => self.partition_point(|timestamp| timestamp < *end),
+            Bound::Unbounded => self.measurements.len(),
+        };
+        &self.measurements[start..end.max(start)]
+    }
+
+    fn partition_point(&self, before: impl Fn(f64) -> bool) -> usize {
+        self.measurements
+            .partition_point(|measurement| before(measurement.timestamp))
+    }
+
+    /// Measurements out of the temperature range, computed on first use.
+    pub fn out_of_range(&self) -> &[Measurement] {
+        self.out_of_range.get_or_init(|| {
+            self.measurements
+                .iter()
+                .filter(|measurement| {
+                    self.temperature_range
+                        .is_out_of_range(measurement.temperature)
+                })
+                .copied()
+                .collect()
+        })
+    }
+
+    /// Timestamps of the first and last measurement of every run of consecutive out-of-range
+    /// measurements.
+    pub fn out_of_range_intervals(&self) -> Vec<RangeInclusive<f64>> {
+        let mut intervals = Vec::new();
+        let mut run: Option<(f64, f64)> = None;
+        for measurement in &self.measurements {
+            if self
+                .temperature_range
+                .is_out_of_range(measurement.temperature)
+            {
+                let start = run.map_or(measurement.timestamp, |(start, _)| start);
+                run = Some((start, measurement.timestamp));
+            } else if let Some((start, end)) = run.take() {
+                intervals.push(start..=end);
+            }
+        }
+        intervals.extend(run.map(|(start, end)| start..=end));
+        intervals
+    }
+
+    /// Whether `measurement` was taken before the document was published. The document may be
+    /// a snapshot of a live experiment, which misses the measurements taken since.
+    pub fn covers(&self, measurement: &Measurement) -> bool {
+        match self.measurements.last() {
+            Some(last) => measurement.timestamp <= last.timestamp || measurement.approx_eq(last),
+            None => false,
+        }
+    }
+}
+
+impl From<event_types::ExperimentDocument> for ExperimentDocument {
+    fn from(event: event_types::ExperimentDocument) -> Self {
+        Self::new(
+            event.experiment,
+            event
+                .measurements
+                .into_iter()
+                .map(|measurement| Measurement {
+                    timestamp: measurement.timestamp,
+                    temperature: measurement.temperature,
+                })
+                .collect(),
+            TempRange {
+                upper_threshold: event.temperature_range.upper_threshold,
+                lower_threshold: event.temperature_range.lower_threshold,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const TEMPERATURE_RANGE: TempRange = TempRange {
+        upper_threshold: 20.0,
+        lower_threshold: 10.0,
+    };
+
+    fn measurement(timestamp: f64, temperature: f32) -> Measurement {
+        Measurement {
+            timestamp,
+            temperature,
+        }
+    }
+
+    fn document(measurements: Vec<Measurement>) -> ExperimentDocument {
+        ExperimentDocument::new("1234".into(), measurements, TEMPERATURE_RANGE)
+    }
+
+    #[test]
+    fn sort_measurements() {
+        let document = document(vec![
+            measurement(0.03, 20.0),
+            measurement(0.00, 20.0),
+            measurement(0.02, 20.0),
+            measurement(0.01, 20.0),
+        ]);
+        let timestamps: Vec<_> = document
+            .measurements()
+            .iter()
+            .map(|m| m.timestamp)
+            .collect();
+        assert_eq!(timestamps, [0.00, 0.01, 0.02, 0.03]);
+    }
+
+    #[test]
+    fn compare_measurements_with_different_precision() {
+        let document = document(vec![
+            measurement(0.0031, 20.0),
+            measurement(0.0001, 20.0),
+            measurement(0.0021, 20.0),
+            measurement(0.0011, 20.0),
+        ]);
+        let rounded = [
+            measurement(0.000, 20.0),
+            measurement(0.001, 20.0),
+            measurement(0.002, 20.0),
+            measurement(0.003, 20.0),
+        ];
+        assert!(approx_eq(document.measurements(), &rounded));
+        assert!(!approx_eq(document.measurements(), &rounded[1..]));
+        assert_ne!(document.measurements(), rounded);
+    }
+
+    #[test]
+    fn query_inclusive_and_exclusive_ranges() {
+        let document = document(vec![
+            measurement(1.0, 20.0),
+            measurement(2.0, 20.0),
+            measurement(3.0, 20.0),
+        ]);
+        assert_eq!(document.range(1.0..=3.0).len(), 3);
+        assert_eq!(document.range(1.0..3.0).len(), 2);
+        assert_eq!(
+            document
+                .range((Bound::Excluded(1.0), Bound::Unbounded))
+                .len(),
+            2
+        );
+        assert_eq!(document.range(1.5..=2.5), [measurement(2.0, 20.0)]);
+        assert!(document.range(2.5..=2.6).is_empty());
+        assert!(document.range(3.0..=1.0).is_empty());
+        assert!(document.range(4.0..).is_empty());
+        assert!(document.range(f64::NAN..=f64::NAN).is_empty());
+    }
+
+    #[test]
+    fn edge_case() {
+        let document = document(vec![measurement(1698695808.2251582, -7.32378)]);
+        assert_eq!(
+            document.range(1698695808.225..=1698695808.226),
+            [measurement(1698695808.2251582, -7.32378)]
+        );
+    }
+
+    #[test]
+    fn empty_document() {
+        let document = document(vec![]);
+        assert!(document.range(..).is_empty());
+        assert!(document.range(0.0..=1.0).is_empty());
+        assert!(document.out_of_range().is_empty());
+        assert!(document.out_of_range_intervals().is_empty());
+        assert!(!document.covers(&measurement(0.0, 20.0)));
+    }
+
+    #[test]
+    fn snapshot_covers_measurements_up_to_the_last_one() {
+        let document = document(vec![
+            measurement(1698695808.2251582, 20.0),
+            measurement(1698695807.2251582, 20.0),
+        ]);
+        assert!(document.covers(&measurement(1698695807.0, 25.0)));
+        // Rounded by the API
+        assert!(document.covers(&measurement(1698695808.2256, 20.0)));
+        assert!(!document.covers(&measurement(1698695809.2251582, 20.0)));
+    }
+
+    #[test]
+    fn extract_out_of_range_intervals() {
+        let document = document(vec![
+            measurement(1.0, 21.0),
+            measurement(2.0, 20.0),
+            measurement(3.0, 9.0),
+            measurement(4.0, 25.0),
+            measurement(5.0, 10.0),
+            measurement(6.0, 30.0),
+        ]);
+        assert_eq!(
+            document.out_of_range_intervals(),
+            [1.0..=1.0, 3.0..=4.0, 6.0..=6.0]
+        );
+        assert_eq!(document.out_of_range().len(), 4);
+    }
+
+    fn measurements() -> impl Strategy<Value = Vec<Measurement>> {
+        prop::collection::vec(
+            (-1e3..1e3f64, 0.0..30.0f32).prop_map(|(timestamp, temperature)| Measurement {
+                timestamp,
+                temperature,
+            }),
+            0..50,
+        )
+    }
+
+    fn naive_range(
+        document: &ExperimentDocument,
+        range: impl RangeBounds<f64>,
+    ) -> Vec<Measurement> {
+        document
+            .measurements()
+            .iter()
+            .filter(|measurement| range.contains(&measurement.timestamp))
+            .copied()
+            .collect()
+    }
+
+    proptest! {
+        #[test]
+        fn range_matches_a_linear_scan(
+            measurements in measurements(),
+            start in -1.2e3..1.2e3f64,
+            end in -1.2e3..1.2e3f64,
+        ) {
+            let document = document(measurements);
+            prop_assert_eq!(document.range(start..=end), naive_range(&document, start..=end));
+            prop_assert_eq!(document.range(start..end), naive_range(&document, start..end));
+            prop_assert_eq!(document.range(start..), naive_range(&document, start..));
+            prop_assert_eq!(document.range(..=end), naive_range(&document, ..=end));
+        }
+
+        #[test]
+        fn range_of_existing_timestamps_is_inclusive(measurements in measurements(), a: usize, b: usize) {
+            let document = document(measurements);
+            let len = document.measurements().len();
+            prop_assume!(len > 0);
+            let (a, b) = (a % len, b % len);
+            let (start, end) = (a.min(b), a.max(b));
+            let start_time = document.measurements()[start].timestamp;
+            let end_time = document.measurements()[end].timestamp;
+            let range = document.range(start_time..=end_time);
+            prop_assert!(range.contains(&document.measurements()[start]));
+            prop_assert!(range.contains(&document.measurements()[end]));
+        }
+
+        #[test]
+        fn intervals_cover_exactly_the_out_of_range_measurements(measurements in measurements()) {
+            let document = document(measurements);
+            let intervals = document.out_of_range_intervals();
+            for pair in intervals.windows(2) {
+                prop_assert!(pair[0].end() <= pair[1].start());
+            }
+            let in_intervals: Vec<_> = intervals
+                .iter()
+                .flat_map(|interval| document.range(interval.clone()).iter().copied())
+                .filter(|measurement| TEMPERATURE_RANGE.is_out_of_range(measurement.temperature))
+                .collect();
+            prop_assert_eq!(document.out_of_range(), in_intervals);
+        }
+    }
+}
@@ -1,5 +1,6 @@
+use event_hash::{Keyring, Sealer};
 use serde::Deserialize;
-use std::fs;
+use std::{fs, sync::Arc};
 
 use crate::simulator::TempRange;
 
@@ -9,6 +10,22 @@ pub struct UncheckedTempRange {
     pub upper_threshold: f32,
 }
 
+/// Keys encrypting the measurement hashes.
+#[derive(Clone, Debug)]
+pub enum HashKeys {
+    /// Shared with the notifications-service, see `--keyring`.
+    Symmetric(Keyring),
+    /// Public key of the notifications-service and signing key of the producer, see
+    /// `--sealing-keys`. The producer cannot decrypt the hashes it seals.
+    Sealed(Box<Sealer>),
+}
+
+impl Default for HashKeys {
+    fn default() -> Self {
+        HashKeys::Symmetric(Keyring::default())
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct ConfigFile(pub Vec<ConfigEntry>);
 
@@ -45,7 +62,7 @@ pub struct ConfigEntry {
     pub start_temperature: f32,
 
     #[serde(skip)]
-    pub secret_key: String,
+    pub hash_keys: Arc<HashKeys>,
 
     #[serde(skip)]
     pub topic: String,
@@ -79,8 +96,8 @@ impl ConfigEntry {
         0.0
     }
 
-    pub fn set_secret_key(&mut self, secret_key: &str) {
-        self.secret_key = secret_key.into();
+    pub fn set_hash_keys(&mut self, hash_keys: &Arc<HashKeys>) {
+        self.hash_keys = hash_keys.clone();
     }
 
     pub fn set_topic(&mut self, topic: &str) {
@@ -1,23 +1,246 @@
-use sqlx::{Pool, Postgres};
+use sqlx::{PgConnection, Pool, Postgres};
 
-pub async fn insert_ground_truth(
-    pool: &Pool<Postgres>,
+use event_hash::NotificationType;
+
+use crate::simulator::{ExperimentStage, Measurement, TempRange};
+use crate::writer::{GroundTruth, Row, Writer};
+
+/// Writer of the experiments, their measurements and ground truth, set when `DATABASE_URL` is.
+/// The database only serves grading, it never holds up an experiment.
+#[derive(Clone)]
+pub struct Database {
+    pub writer: Writer,
+}
+
+/// Writes a batch of rows in a single transaction. Experiments are inserted before their
+/// interruption is recorded.
+pub async fn insert_rows(pool: &Pool<Postgres>, rows: &[Row]) -> Result<(), sqlx::Error> {
+    let mut stages = vec![];
+    let mut measurements = vec![];
+    let mut ground_truths = vec![];
+    let mut tx = pool.begin().await?;
+    for row in rows {
+        match row {
+            Row::Experiment {
+                experiment_id,
+                researcher,
+                sensors,
+                temp_range,
+            } => {
+                insert_experiment(&mut tx, experiment_id, researcher, sensors, *temp_range).await?
+            }
+            Row::Stage {
+                experiment_id,
+                stage,
+                timestamp,
+            } => stages.push((experiment_id.as_str(), *stage, *timestamp)),
+            Row::Measurement {
+                experiment_id,
+                measurement,
+            } => measurements.push((experiment_id.as_str(), measurement)),
+            Row::GroundTruth(ground_truth) => ground_truths.push(ground_truth),
+            Row::Interrupted { .. } => {}
+        }
+    }
+    insert_experiment_stages(&mut tx, &stages).await?;
+    insert_measurements(&mut tx, &measurements).await?;
+    insert_ground_truths(&mut tx, &ground_truths).await?;
+    for row in rows {
+        if let Row::Interrupted {
+            experiment_id,
+            interrupted,
+        } = row
+        {
+            update_experiment_interrupted(&mut tx, experiment_id, *interrupted).await?;
+        }
+    }
+    tx.commit().await
+}
+
+fn notification_type_name(notification_type: &NotificationType) -> &'static str {
+    match notification_type {
+        NotificationType::OutOfRange => "OutOfRange",
+        NotificationType::Stabilized => "Stabilized",
+    }
+}
+
+async fn insert_ground_truths(
+    conn: &mut PgConnection,
+    rows: &[&GroundTruth],
+) -> Result<(), sqlx::Error> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    let mut experiment_ids = Vec::with_capacity(rows.len());
+    let mut measurement_ids = Vec::with_capacity(rows.len());
+    let mut notification_types = Vec::with_capacity(rows.len());
+    let mut measurement_timestamps = Vec::with_capacity(rows.len());
+    let mut researchers = Vec::with_capacity(rows.len());
+    let mut topics = Vec::with_capacity(rows.len());
+    for row in rows {
+        experiment_ids.push(row.experiment_id.as_str());
+        measurement_ids.push(row.measurement_id.as_str());
+        notification_types.push(notification_type_name(&row.notification_type));
+        measurement_timestamps.push(row.measurement_timestamp);
+        researchers.push(row.researcher.as_str());
+        topics.push(row.topic.as_str());
+    }
+    sqlx::query!(
+        "
+            INSERT INTO
+                demo.notification_ground_truth (experiment_id, measurement_id, notification_type, measurement_timestamp, researcher, topic)
+            SELECT
+                *
+            FROM
+                UNNEST($1::TEXT[], $2::TEXT[], $3::TEXT[], $4::DOUBLE PRECISION[], $5::TEXT[], $6::TEXT[])
+            ON CONFLICT
+                DO NOTHING;
+            ",
+        &experiment_ids as &[&str],
+        &measurement_ids as &[&str],
+        &notification_types as &[&str],
+        &measurement_timestamps,
+        &researchers as &[&str],
+        &topics as &[&str],
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+async fn insert_experiment(
+    conn: &mut PgConnection,
     experiment_id: &str,
-    measurement_id: &str,
+    researcher: &str,
+    sensors: &[String],
+    temp_range: TempRange,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "
+            INSERT INTO
+                demo.experiment (experiment_id, researcher, sensors, lower_threshold, upper_threshold)
+            VALUES
+                ($1, $2, $3, $4, $5)
+            ON CONFLICT
+                DO NOTHING;
+            ",
+        experiment_id,
+        researcher,
+        sensors,
+        temp_range.lower_threshold,
+        temp_range.upper_threshold,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+async fn insert_experiment_stages(
+    conn: &mut PgConnection,
+    rows: &[(&str, ExperimentStage, f64)],
 ) -> Result<(), sqlx::Error> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    let mut experiment_ids = Vec::with_capacity(rows.len());
+    let mut stages = Vec::with_capacity(rows.len());
+    let mut timestamps = Vec::with_capacity(rows.len());
+    for (experiment_id, stage, timestamp) in rows {
+        experiment_ids.push(*experiment_id);
+        stages.push(stage.as_str());
+        timestamps.push(*timestamp);
+    }
     sqlx::query!(
         "
-            INSERT INTO 
-                demo.notification_ground_truth (experiment_id, measurement_id) 
-            VALUES 
-                ($1, $2)
+            INSERT INTO
+                demo.experiment_stage (experiment_id, stage, timestamp)
+            SELECT
+                *
+            FROM
+                UNNEST($1::TEXT[], $2::TEXT[], $3::DOUBLE PRECISION[])
             ON CONFLICT
                 DO NOTHING;
             ",
+        &experiment_ids as &[&str],
+        &stages as &[&str],
+        &timestamps,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+async fn update_experiment_interrupted(
+    conn: &mut PgConnection,
+    experiment_id: &str,
+    interrupted: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "
+            UPDATE
+                demo.experiment
+            SET
+                interrupted = $2
+            WHERE
+                experiment_id = $1;
+            ",
         experiment_id,
-        measurement_id,
+        interrupted,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+async fn insert_measurements(
+    conn: &mut PgConnection,
+    rows: &[(&str, &Measurement)],
+) -> Result<(), sqlx::Error> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    let mut experiment_ids = Vec::with_capacity(rows.len());
+    let mut measurement_ids = Vec::with_capacity(rows.len());
+    let mut stages = Vec::with_capacity(rows.len());
+    let mut timestamps = Vec::with_capacity(rows.len());
+    let mut temperatures = Vec::with_capacity(rows.len());
+    let mut notification_types = Vec::with_capacity(rows.len());
+    for (experiment_id, measurement) in rows {
+        experiment_ids.push(*experiment_id);
+        measurement_ids.push(measurement.measurement_id.as_str());
+        stages.push(measurement.stage.as_str());
+        timestamps.push(measurement.timestamp);
+        temperatures.push(measurement.temperature);
+        notification_types.push(
+            measurement
+                .notification_type
+                .as_ref()
+                .map(notification_type_name),
+        );
+    }
+    sqlx::query!(
+        "
+            INSERT INTO
+                demo.measurement (experiment_id, measurement_id, stage, timestamp, temperature, notification_type)
+            SELECT
+                *
+            FROM
+                UNNEST($1::TEXT[], $2::TEXT[], $3::TEXT[], $4::DOUBLE PRECISION[], $5::REAL[], $6::TEXT[])
+            ON CONFLICT
+                DO NOTHING;
+            ",
+        &experiment_ids as &[&str],
+        &measurement_ids as &[&str],
+        &stages as &[&str],
+        &timestamps,
+        &temperatures,
+        &notification_types as &[Option<&str>],
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
     Ok(())
@@ -0,0 +1,248 @@
+use base64::{engine::general_purpose, Engine as _};
+use clap::ArgMatches;
+use rdkafka::{
+    error::{KafkaError, RDKafkaErrorCode},
+    message::{Headers, OwnedHeaders},
+};
+use serde::Serialize;
+use std::{
+    fs::{create_dir_all, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::mpsc::{self, TrySendError},
+    time::Duration,
+};
+use tokio::task::{self, JoinHandle};
+use tracing::error;
+
+use crate::metric::{Metrics, TopicLabels};
+use crate::time;
+
+/// Exponential backoff applied between delivery attempts of a single event.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Backoff to wait before retry number `retry` (starting at 0).
+    pub fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2_u32.saturating_pow(retry))
+            .min(self.max_backoff)
+    }
+}
+
+impl From<&ArgMatches> for RetryPolicy {
+    fn from(args: &ArgMatches) -> Self {
+        Self {
+            max_retries: *args.get_one::<u32>("max-retries").expect("required"),
+            initial_backoff: Duration::from_millis(
+                *args.get_one::<u64>("retry-backoff-ms").expect("required"),
+            ),
+            max_backoff: Duration::from_millis(
+                *args.get_one::<u64>("retry-max-backoff-ms").expect("required"),
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorClass {
+    /// The broker or the network is temporarily unavailable, the event can be sent again.
+    Retriable,
+    /// Sending the same event again will fail in the same way.
+    Fatal,
+}
+
+pub fn classify(error: &KafkaError) -> ErrorClass {
+    if is_fatal(error) {
+        return ErrorClass::Fatal;
+    }
+    if let KafkaError::Transaction(error) = error {
+        // An aborted transaction can be started over
+        return if error.is_retriable() || error.txn_requires_abort() {
+            ErrorClass::Retriable
+        } else {
+            ErrorClass::Fatal
+        };
+    }
+    match error.rdkafka_error_code() {
+        Some(
+            RDKafkaErrorCode::MessageTimedOut
+            | RDKafkaErrorCode::QueueFull
+            | RDKafkaErrorCode::BrokerTransportFailure
+            | RDKafkaErrorCode::AllBrokersDown
+            | RDKafkaErrorCode::OperationTimedOut
+            | RDKafkaErrorCode::RequestTimedOut
+            | RDKafkaErrorCode::NetworkException
+            | RDKafkaErrorCode::BrokerNotAvailable
+            | RDKafkaErrorCode::LeaderNotAvailable
+            | RDKafkaErrorCode::NotLeaderForPartition
+            | RDKafkaErrorCode::PreferredLeaderNotAvailable
+            | RDKafkaErrorCode::NotEnoughReplicas
+            | RDKafkaErrorCode::NotEnoughReplicasAfterAppend
+            | RDKafkaErrorCode::UnknownTopicOrPartition,
+        ) => ErrorClass::Retriable,
+        _ => ErrorClass::Fatal,
+    }
+}
+
+/// Whether the producer itself can no longer be used, e.g. because another instance with the same
+/// transactional ID fenced it. No transaction can be started again.
+pub fn is_fatal(error: &KafkaError) -> bool {
+    match error {
+        KafkaError::Transaction(error) => error.is_fatal(),
+        _ => matches!(
+            error.rdkafka_error_code(),
+            Some(
+                RDKafkaErrorCode::Fatal
+                    | RDKafkaErrorCode::Fenced
+                    | RDKafkaErrorCode::ProducerFenced
+                    | RDKafkaErrorCode::TransactionCoordinatorFenced
+            )
+        ),
+    }
+}
+
+/// `OwnedHeaders` is not `Clone`, every delivery attempt needs its own copy.
+pub fn copy_headers(headers: &OwnedHeaders) -> OwnedHeaders {
+    (0..headers.count())
+        .filter_map(|idx| headers.get(idx))
+        .fold(OwnedHeaders::new(), |copy, (name, value)| {
+            copy.add(name, value)
+        })
+}
+
+#[derive(Serialize)]
+struct DeadLetter<'a> {
+    timestamp: f64,
+    topic: &'a str,
+    key: Option<String>,
+    headers: Vec<(&'a str, String)>,
+    error: String,
+    attempts: u32,
+    payload: String,
+}
+
+/// Append-only file of newline-delimited JSON entries, one for every event whose delivery
+/// ultimately failed.
+///
+/// Key, header values and payload are base64 encoded so the events can be replayed as is.
+///
+/// Entries are appended by a blocking task, writing never holds up the runtime. At most `buffer`
+/// entries wait for the task, further ones are dropped and counted. The task stops once every
+/// handle has been dropped and the queued entries have been written.
+#[derive(Clone)]
+pub struct DeadLetterFile {
+    tx: mpsc::SyncSender<String>,
+    metrics: Metrics,
+}
+
+impl DeadLetterFile {
+    pub fn open(
+        path: &str,
+        buffer: usize,
+        metrics: Metrics,
+    ) -> anyhow::Result<(Self, JoinHandle<()>)> {
+        if let Some(parent) = Path::new(path).parent() {
+            create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (tx, rx) = mpsc::sync_channel::<String>(buffer);
+        let handle = task::spawn_blocking(move || {
+            for line in rx {
+                if let Err(e) = writeln!(file, "{}", line) {
+                    error!(error = e.to_string(), "Failed to write dead letter");
+                }
+            }
+        });
+        Ok((Self { tx, metrics }, handle))
+    }
+
+    pub fn write(
+        &self,
+        topic: &str,
+        key: Option<&[u8]>,
+        headers: &OwnedHeaders,
+        payload: &[u8],
+        error: &KafkaError,
+        attempts: u32,
+    ) {
+        let entry = DeadLetter {
+            timestamp: time::current_epoch(),
+            topic,
+            key: key.map(|key| general_purpose::STANDARD.encode(key)),
+            headers: (0..headers.count())
+                .filter_map(|idx| headers.get(idx))
+                .map(|(name, value)| (name, general_purpose::STANDARD.encode(value)))
+                .collect(),
+            error: error.to_string(),
+            attempts,
+            payload: general_purpose::STANDARD.encode(payload),
+        };
+        let line = serde_json::to_string(&entry).expect("Serializable dead letter");
+        match self.tx.try_send(line) {
+            Ok(()) => return,
+            Err(TrySendError::Full(_)) => {
+                error!(topic, "Dead-letter buffer full, dropping dead letter");
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                error!(topic, "Dead-letter writer stopped, dropping dead letter");
+            }
+        }
+        self.metrics
+            .dead_letter_drop_count
+            .get_or_create(&TopicLabels {
+                topic: topic.to_string(),
+            })
+            .inc();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_is_exponential_and_capped() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(1000),
+        };
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(1), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(800));
+        assert_eq!(policy.backoff(4), Duration::from_millis(1000));
+        assert_eq!(policy.backoff(64), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn classify_production_errors() {
+        let timeout = KafkaError::MessageProduction(RDKafkaErrorCode::MessageTimedOut);
+        assert_eq!(classify(&timeout), ErrorClass::Retriable);
+        let too_large = KafkaError::MessageProduction(RDKafkaErrorCode::MessageSizeTooLarge);
+        assert_eq!(classify(&too_large), ErrorClass::Fatal);
+        let fenced = KafkaError::MessageProduction(RDKafkaErrorCode::ProducerFenced);
+        assert_eq!(classify(&fenced), ErrorClass::Fatal);
+        assert!(is_fatal(&fenced));
+        assert!(!is_fatal(&too_large));
+    }
+
+    #[test]
+    fn copy_headers_keeps_order_and_values() {
+        let headers = OwnedHeaders::new()
+            .add("record_name", "sensor_temperature_measured")
+            .add("other", "value");
+        let copy = copy_headers(&headers);
+        assert_eq!(copy.count(), 2);
+        assert_eq!(
+            copy.get(0),
+            Some(("record_name", "sensor_temperature_measured".as_bytes()))
+        );
+        assert_eq!(copy.get(1), Some(("other", "value".as_bytes())));
+    }
+}
@@ -1,22 +1,38 @@
-use apache_avro::types::{Record, Value};
-use apache_avro::{Reader, Schema, Writer};
+use apache_avro::types::Value;
+use apache_avro::{Reader, Schema};
+use futures::future;
 use rdkafka::{
     config::ClientConfig,
     error::KafkaError,
-    message::{OwnedHeaders, OwnedMessage, ToBytes},
-    producer::{FutureProducer, FutureRecord},
+    message::{OwnedHeaders, ToBytes},
+    producer::{FutureProducer, FutureRecord, Producer},
 };
 use std::collections::HashMap;
-use std::{fs, time::Duration};
-use tracing::{trace, debug, info, span, Level, Span};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::{debug, error, info, span, trace, warn, Level, Span};
 use uuid::Uuid;
 
-use event_hash::{HashData, NotificationType};
+use event_hash::{AssociatedData, HashData, NotificationType};
+use event_schema::{Encoding, Format};
+use event_types::{
+    Event, ExperimentConfigured, ExperimentDocument, ExperimentStarted, ExperimentTerminated,
+    ProducerHeartbeat, SensorTemperatureMeasured, StabilizationStarted, TemperatureRange,
+};
+use kafka_client::KafkaConfig;
 
-use crate::metric::{EventCountLabels, Metrics};
+use crate::config::HashKeys;
+use crate::delivery::{self, DeadLetterFile, ErrorClass, RetryPolicy};
+use crate::metric::{
+    EventCountLabels, Metrics, TopicLabels, TransactionLabels, TransactionOutcome,
+};
 use crate::simulator::{self, ExperimentStage, IterMut, Measurement, TempRange, TemperatureSample};
 use crate::time;
 
+/// Upper bound on committing or aborting a transaction.
+const TRANSACTION_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// `Vec<u8>` wrapper
 ///
 /// FutureRecord::payload requires a type that implements the trait `ToBytes` as an argument. This is our
@@ -29,213 +45,242 @@ impl ToBytes for EventWrapper {
     }
 }
 
+/// Wire encoding of the events, shared by all experiments.
+#[derive(Clone, Debug)]
+pub struct EventEncoding {
+    pub encoding: Encoding,
+    /// Version of the event schemas, sent along in the `schema_version` header.
+    pub schema_version: u32,
+    /// Schema registry IDs by record name, required by [`Encoding::Confluent`].
+    pub schema_ids: HashMap<String, u32>,
+    /// Format of the events sent to topics missing from `topic_formats`.
+    pub format: Format,
+    pub topic_formats: HashMap<String, Format>,
+}
+
+impl EventEncoding {
+    fn topic_format(&self, topic: &str) -> Format {
+        self.topic_formats.get(topic).copied().unwrap_or(self.format)
+    }
+
+    fn encode(
+        &self,
+        schema: &Schema,
+        value: Value,
+        record_name: &str,
+        format: Format,
+    ) -> EventWrapper {
+        let payload = match format {
+            Format::Avro => {
+                let schema_id = self.schema_ids.get(record_name).copied();
+                event_schema::encode(schema, value, self.encoding, schema_id)
+            }
+            Format::Json => event_schema::json::encode(value),
+            Format::Protobuf => event_schema::protobuf::encode(schema, &value),
+        };
+        EventWrapper(
+            payload.unwrap_or_else(|e| panic!("Failed to encode `{}` event: {}", record_name, e)),
+        )
+    }
+}
+
 pub struct ExperimentSchemas {
     schemas: HashMap<&'static str, Schema>,
-    raw_schema: HashMap<&'static str, String>,
+    encoding: EventEncoding,
+    /// Topic of the experiment events.
+    topic: String,
+    /// Format of the events sent to the experiment topic.
+    event_format: Format,
+    /// Format of the experiment document, sent to the document topic.
+    document_format: Format,
 }
 
 impl ExperimentSchemas {
-    pub fn new() -> Self {
+    pub fn new(encoding: EventEncoding, topic: &str, topic_document: Option<&str>) -> Self {
+        let schemas = event_schema::RECORD_NAMES
+            .into_iter()
+            .map(|record_name| {
+                let raw_schema = event_schema::raw_schema(record_name, encoding.schema_version)
+                    .expect("Validated by clap");
+                (record_name, Schema::parse_str(raw_schema).unwrap())
+            })
+            .collect();
         Self {
-            schemas: HashMap::new(),
-            raw_schema: HashMap::new(),
+            schemas,
+            topic: topic.into(),
+            event_format: encoding.topic_format(topic),
+            document_format: topic_document
+                .map(|topic| encoding.topic_format(topic))
+                .unwrap_or(encoding.format),
+            encoding,
+        }
+    }
+
+    fn schema(&self, record_name: &str) -> &Schema {
+        &self.schemas[record_name]
+    }
+
+    fn format(&self, record_name: &str) -> Format {
+        match record_name {
+            "experiment_document" => self.document_format,
+            _ => self.event_format,
         }
     }
 
+    /// Encodes `event` with the schema version of the experiment, leaving out the fields the
+    /// version does not have.
+    fn encode<E: Event>(&self, event: &E) -> EventWrapper {
+        let schema = self.schema(E::RECORD_NAME);
+        let value = event
+            .to_value(schema)
+            .unwrap_or_else(|e| panic!("Invalid `{}` event: {}", E::RECORD_NAME, e));
+        self.encoding
+            .encode(schema, value, E::RECORD_NAME, self.format(E::RECORD_NAME))
+    }
+
+    /// Encrypts `hash_data` bound to the topic and schema version of the sensor events carrying
+    /// it, with the key active at its timestamp or sealed to the notifications-service.
+    fn measurement_hash(&self, hash_data: &HashData, hash_keys: &HashKeys) -> String {
+        let associated_data = AssociatedData {
+            topic: &self.topic,
+            experiment_id: &hash_data.experiment_id,
+            schema_version: self.encoding.schema_version,
+        };
+        match hash_keys {
+            HashKeys::Symmetric(keyring) => {
+                let key = keyring
+                    .key_at(hash_data.timestamp)
+                    .expect("Active key checked at startup");
+                hash_data.encrypt(key, associated_data)
+            }
+            HashKeys::Sealed(sealer) => hash_data.seal(sealer, associated_data),
+        }
+    }
+
+    /// Headers identifying the record, the schema version and the format of an event.
+    pub fn headers(&self, record_name: &str) -> OwnedHeaders {
+        OwnedHeaders::new()
+            .add(event_schema::RECORD_NAME_HEADER, record_name)
+            .add(
+                event_schema::SCHEMA_VERSION_HEADER,
+                &self.encoding.schema_version.to_string(),
+            )
+            .add(
+                event_schema::CONTENT_TYPE_HEADER,
+                self.format(record_name).content_type(),
+            )
+    }
+
     pub fn experiment_configured_event(
-        &mut self,
+        &self,
         experiment_id: &str,
         researcher: &str,
         sensors: &[String],
         temp_range: TempRange,
     ) -> EventWrapper {
-        let schema = self
-            .schemas
-            .entry("experiment-producer/schemas/experiment_configured_event.avsc")
-            .or_insert_with(|| {
-                let raw_schema = self
-                    .raw_schema
-                    .entry("experiment-producer/schemas/experiment_configured.avsc")
-                    .or_insert_with(|| {
-                        fs::read_to_string("experiment-producer/schemas/experiment_configured.avsc")
-                            .unwrap()
-                    });
-                Schema::parse_str(raw_schema).unwrap()
-            });
-        let mut writer = Writer::new(schema, Vec::new());
-
-        let mut record = Record::new(writer.schema()).unwrap();
-        record.put("experiment", experiment_id);
-        record.put("researcher", researcher);
-        let sensors = Value::Array(sensors.iter().map(|v| (&**v).into()).collect());
-        record.put("sensors", sensors);
-
-        let schema_json: serde_json::Value = serde_json::from_str(
-            self.raw_schema
-                .get("experiment-producer/schemas/experiment_configured.avsc")
-                .unwrap(),
-        )
-        .unwrap();
-        let temp_schema_json = &schema_json["fields"][3]["type"];
-
-        let temp_schema = Schema::parse_str(&temp_schema_json.to_string()).unwrap();
-        let mut record_temp_range = Record::new(&temp_schema).unwrap();
-        record_temp_range.put("upper_threshold", Value::Float(temp_range.upper_threshold));
-        record_temp_range.put("lower_threshold", Value::Float(temp_range.lower_threshold));
-        record.put("temperature_range", record_temp_range);
-        writer.append(record).unwrap();
-
-        EventWrapper(writer.into_inner().unwrap())
-    }
-
-    pub fn stabilization_started_event(&mut self, experiment_id: &str) -> EventWrapper {
-        let schema = self
-            .schemas
-            .entry("experiment-producer/schemas/stabilization_started.avsc")
-            .or_insert_with(|| {
-                let raw_schema =
-                    fs::read_to_string("experiment-producer/schemas/stabilization_started.avsc")
-                        .unwrap();
-                Schema::parse_str(&raw_schema).unwrap()
-            });
-        let mut writer = Writer::new(schema, Vec::new());
-
-        let mut record = Record::new(writer.schema()).unwrap();
-        record.put("experiment", experiment_id);
-
-        let current_time = time::current_epoch();
-        record.put("timestamp", Value::Double(current_time));
-        writer.append(record).unwrap();
-
-        EventWrapper(writer.into_inner().unwrap())
+        self.encode(&ExperimentConfigured {
+            experiment: experiment_id.into(),
+            researcher: researcher.into(),
+            sensors: sensors.to_vec(),
+            temperature_range: temp_range.into(),
+            timestamp: time::current_epoch(),
+        })
     }
 
-    pub fn experiment_started_event(&mut self, experiment_id: &str) -> EventWrapper {
-        let schema = self
-            .schemas
-            .entry("experiment-producer/schemas/experiment_started.avsc")
-            .or_insert_with(|| {
-                let raw_schema =
-                    fs::read_to_string("experiment-producer/schemas/experiment_started.avsc")
-                        .unwrap();
-                Schema::parse_str(&raw_schema).unwrap()
-            });
-        let mut writer = Writer::new(schema, Vec::new());
-
-        let mut record = Record::new(writer.schema()).unwrap();
-        record.put("experiment", experiment_id);
-
-        let current_time = time::current_epoch();
-        record.put("timestamp", Value::Double(current_time));
-        writer.append(record).unwrap();
-
-        EventWrapper(writer.into_inner().unwrap())
+    pub fn stabilization_started_event(&self, experiment_id: &str) -> EventWrapper {
+        self.encode(&StabilizationStarted {
+            experiment: experiment_id.into(),
+            timestamp: time::current_epoch(),
+        })
     }
 
-    pub fn experiment_terminated_event(&mut self, experiment_id: &str) -> EventWrapper {
-        let schema = self
-            .schemas
-            .entry("experiment-producer/schemas/experiment_started.avsc")
-            .or_insert_with(|| {
-                let raw_schema =
-                    fs::read_to_string("experiment-producer/schemas/experiment_started.avsc")
-                        .unwrap();
-                Schema::parse_str(&raw_schema).unwrap()
-            });
-        let mut writer = Writer::new(schema, Vec::new());
-
-        let mut record = Record::new(writer.schema()).unwrap();
-        record.put("experiment", experiment_id);
-
-        let current_time = time::current_epoch();
-        record.put("timestamp", Value::Double(current_time));
+    pub fn experiment_started_event(&self, experiment_id: &str) -> EventWrapper {
+        self.encode(&ExperimentStarted {
+            experiment: experiment_id.into(),
+            timestamp: time::current_epoch(),
+        })
+    }
 
-        writer.append(record).unwrap();
-        EventWrapper(writer.into_inner().unwrap())
+    pub fn experiment_terminated_event(
+        &self,
+        experiment_id: &str,
+        interrupted: bool,
+    ) -> EventWrapper {
+        self.encode(&ExperimentTerminated {
+            experiment: experiment_id.into(),
+            timestamp: time::current_epoch(),
+            interrupted,
+        })
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn temperature_measured_event(
-        &mut self,
+        &self,
         experiment: &str,
         measurement_id: &str,
         sensor: &str,
         temperature: f32,
         timestamp: f64,
         measurement_hash: &str,
+        stage: &ExperimentStage,
     ) -> EventWrapper {
-        let schema = self
-            .schemas
-            .entry("experiment-producer/schemas/sensor_temperature_measured.avsc")
-            .or_insert_with(|| {
-                let raw_schema = fs::read_to_string(
-                    "experiment-producer/schemas/sensor_temperature_measured.avsc",
-                )
-                .unwrap();
-                Schema::parse_str(&raw_schema).unwrap()
-            });
-        let mut writer = Writer::new(schema, Vec::new());
-
-        let mut record = Record::new(writer.schema()).unwrap();
-        record.put("experiment", experiment);
-        record.put("sensor", sensor);
-        record.put("measurement_id", measurement_id);
-        record.put("temperature", temperature);
-        record.put("measurement_hash", measurement_hash);
-        record.put("timestamp", Value::Double(timestamp));
-
-        writer.append(record).unwrap();
-        let encoded = writer.into_inner().unwrap();
-        EventWrapper(encoded)
+        let stage = match stage {
+            ExperimentStage::Stabilization | ExperimentStage::CarryOut => {
+                Some(stage.as_str().into())
+            }
+            _ => None,
+        };
+        self.encode(&SensorTemperatureMeasured {
+            experiment: experiment.into(),
+            sensor: sensor.into(),
+            measurement_id: measurement_id.into(),
+            timestamp,
+            temperature,
+            measurement_hash: measurement_hash.into(),
+            stage,
+        })
     }
 
     pub fn experiment_document_event(
-        &mut self,
+        &self,
         experiment_id: &str,
         measurements: &[Measurement],
         temp_range: TempRange,
+        interrupted: bool,
     ) -> EventWrapper {
-        let schema = self
-            .schemas
-            .entry("experiment-producer/schemas/experiment_document.avsc")
-            .or_insert_with(|| {
-                let raw_schema =
-                    fs::read_to_string("experiment-producer/schemas/experiment_document.avsc")
-                        .unwrap();
-                Schema::parse_str(&raw_schema).unwrap()
-            });
-        let mut writer = Writer::new(schema, Vec::new());
-
-        let schema_json: serde_json::Value =
-            serde_json::from_str(&schema.canonical_form()).unwrap();
-        let measurement_schema = &schema_json["fields"][1]["type"]["items"];
-        let measurement_schema = Schema::parse_str(&measurement_schema.to_string())
-            .expect("Valid measurement avro schema");
-        let temp_schema = &schema_json["fields"][2]["type"];
-        let temp_schema = Schema::parse_str(&temp_schema.to_string()).unwrap();
-
-        let mut record = Record::new(writer.schema()).unwrap();
-        record.put("experiment", experiment_id);
-        let measurements = Value::Array(
-            measurements
+        self.encode(&ExperimentDocument {
+            experiment: experiment_id.into(),
+            measurements: measurements
                 .iter()
-                .map(|measurement| {
-                    let mut record =
-                        Record::new(&measurement_schema).expect("Valid measurement schema");
-                    record.put("timestamp", Value::Double(measurement.timestamp));
-                    record.put("temperature", Value::Float(measurement.temperature));
-                    record.into()
+                .map(|measurement| event_types::Measurement {
+                    timestamp: measurement.timestamp,
+                    temperature: measurement.temperature,
                 })
                 .collect(),
-        );
-        record.put("measurements", measurements);
+            temperature_range: temp_range.into(),
+            interrupted,
+        })
+    }
 
-        let mut record_temp_range = Record::new(&temp_schema).unwrap();
-        record_temp_range.put("upper_threshold", Value::Float(temp_range.upper_threshold));
-        record_temp_range.put("lower_threshold", Value::Float(temp_range.lower_threshold));
-        record.put("temperature_range", record_temp_range);
+    pub fn producer_heartbeat_event(
+        &self,
+        experiment_id: &str,
+        watermark: Option<f64>,
+    ) -> EventWrapper {
+        self.encode(&ProducerHeartbeat {
+            experiment: experiment_id.into(),
+            timestamp: time::current_epoch(),
+            watermark,
+        })
+    }
+}
 
-        writer.append(record).unwrap();
-        EventWrapper(writer.into_inner().unwrap())
+impl From<TempRange> for TemperatureRange {
+    fn from(temp_range: TempRange) -> Self {
+        Self {
+            upper_threshold: temp_range.upper_threshold,
+            lower_threshold: temp_range.lower_threshold,
+        }
     }
 }
 
@@ -281,15 +326,17 @@ fn compute_notification_type(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn temperature_events<'b>(
-    experiment_schemas: &'b mut ExperimentSchemas,
+    experiment_schemas: &'b ExperimentSchemas,
     sample_iter: IterMut<'b>,
     experiment_id: &'b str,
     researcher: &'b str,
     sensors: &'b [String],
     stage: &'b ExperimentStage,
-    secret_key: &'b str,
-) -> Box<dyn Iterator<Item = (Vec<EventWrapper>, Span, Measurement)> + 'b + Send> {
+    mut transitions: u32,
+    hash_keys: &'b HashKeys,
+) -> Box<dyn Iterator<Item = (Vec<SensorEvent>, Span, Measurement)> + 'b + Send> {
     let mut prev_sample = None;
 
     Box::new(sample_iter.map(move |sample| {
@@ -300,33 +347,42 @@ pub fn temperature_events<'b>(
         let current_time = time::current_epoch();
 
         let notification_type = compute_notification_type(sample, prev_sample, stage);
+        let sequence = notification_type.is_some().then(|| {
+            transitions += 1;
+            transitions
+        });
         let hash_data = HashData {
             notification_type: notification_type.clone(),
             timestamp: current_time,
             experiment_id: experiment_id.into(),
             measurement_id: measurement_id.clone(),
-            researcher: researcher.into(),
+            researcher: Some(researcher.into()),
+            sequence,
         };
         let measurement = Measurement {
             measurement_id: measurement_id.clone(),
             temperature: sample.cur(),
             timestamp: current_time,
             notification_type,
+            stage: *stage,
         };
-        let measurement_hash = hash_data.encrypt(secret_key.as_bytes());
+        let measurement_hash = experiment_schemas.measurement_hash(&hash_data, hash_keys);
         prev_sample = Some(sample);
 
         let sensor_events = simulator::compute_sensor_temperatures(sensors, sample.cur())
             .into_iter()
-            .map(|(sensor_id, sensor_temperature)| {
-                experiment_schemas.temperature_measured_event(
+            .map(|(sensor_id, sensor_temperature)| RecordData {
+                payload: experiment_schemas.temperature_measured_event(
                     experiment_id,
                     measurement_id.as_str(),
                     sensor_id,
                     sensor_temperature,
                     current_time,
                     &measurement_hash,
-                )
+                    stage,
+                ),
+                key: Some(experiment_id.to_string()),
+                headers: experiment_schemas.headers("sensor_temperature_measured"),
             })
             .collect();
         drop(_enter);
@@ -340,14 +396,29 @@ pub struct RecordData<K: ToBytes, T: ToBytes> {
     pub headers: OwnedHeaders,
 }
 
+/// `sensor_temperature_measured` event keyed by experiment ID.
+pub type SensorEvent = RecordData<String, EventWrapper>;
+
 #[derive(Clone)]
 pub struct KafkaTopicProducer {
     producer: FutureProducer, // partition: Option<usize>
     metrics: Metrics,
+    retry_policy: RetryPolicy,
+    dead_letter: DeadLetterFile,
+    /// Set in transactional mode. The producer runs one transaction at a time, experiments take
+    /// turns: every measurement waits for the commit of the previous one, of any experiment.
+    transaction_lock: Option<Arc<Mutex<()>>>,
 }
 
 impl KafkaTopicProducer {
-    pub fn new(brokers: &str, metrics: Metrics, use_ssl: bool) -> Self {
+    pub fn new(
+        brokers: &str,
+        metrics: Metrics,
+        kafka_config: &KafkaConfig,
+        retry_policy: RetryPolicy,
+        dead_letter: DeadLetterFile,
+        transactional_id: Option<&str>,
+    ) -> Self {
         let mut client_config = ClientConfig::new();
         client_config
             .set("bootstrap.servers", brokers)
@@ -355,27 +426,35 @@ impl KafkaTopicProducer {
             .set("linger.ms", "100")
             .set("queue.buffering.max.kbytes", "8388608")
             .set("queue.buffering.max.messages", "1000000");
-
-        if use_ssl {
-            info!("Client configured with SSL");
-            client_config
-                .set("security.protocol", "SSL")
-                .set("ssl.ca.location", "experiment-producer/auth/ca.crt")
-                .set(
-                    "ssl.keystore.location",
-                    "experiment-producer/auth/kafka.keystore.pkcs12",
-                )
-                .set("ssl.keystore.password", "cc2023");
+        kafka_config.apply(&mut client_config, "experiment-producer/auth");
+        info!(
+            security_protocol = client_config.get("security.protocol"),
+            "Client configured"
+        );
+        if let Some(transactional_id) = transactional_id {
+            info!(transactional_id, "Client configured with transactions");
+            client_config.set("transactional.id", transactional_id);
         }
 
         let producer: FutureProducer = client_config.create().expect("Producer creation error");
+        if transactional_id.is_some() {
+            producer
+                .init_transactions(TRANSACTION_TIMEOUT)
+                .expect("Failed to initialize transactions");
+        }
 
         // For some reason this is required so the first level
         // span is printed to stdout. This happens because of the
         // call to ClientConfig::new()
         span!(Level::INFO, "");
 
-        KafkaTopicProducer { producer, metrics }
+        KafkaTopicProducer {
+            producer,
+            metrics,
+            retry_policy,
+            dead_letter,
+            transaction_lock: transactional_id.map(|_| Arc::new(Mutex::new(()))),
+        }
     }
 
     fn update_count<K>(&self, topic: &str, key: Option<&K>)
@@ -393,38 +472,388 @@ impl KafkaTopicProducer {
             .inc();
     }
 
-    pub async fn send_event<'a, K, T>(
+    fn trace_record<K, T>(record: &RecordData<K, T>, topic: &str)
+    where
+        T: ToBytes,
+        K: ToBytes + std::fmt::Debug,
+    {
+        trace!(
+            topic,
+            key = format!("{:?}", record.key),
+            record = match Reader::new(record.payload.to_bytes()) {
+                Ok(reader) => format!(
+                    "{:?}",
+                    reader
+                        .filter_map(Result::ok)
+                        .collect::<Vec<apache_avro::types::Value>>()
+                ),
+                // Only container payloads carry the schema required to decode them
+                Err(_) => format!("{} bytes", record.payload.to_bytes().len()),
+            }
+        );
+    }
+
+    /// Single delivery attempt.
+    async fn produce<K, T>(
         &self,
-        record: RecordData<K, T>,
+        record: &RecordData<K, T>,
         topic: &str,
-    ) -> Result<(i32, i64), (KafkaError, OwnedMessage)>
+    ) -> Result<(i32, i64), KafkaError>
     where
         T: ToBytes,
-        K: ToBytes + std::fmt::Debug,
+        K: ToBytes,
     {
         let mut future_record: FutureRecord<'_, K, T> = FutureRecord::to(topic)
             .payload(&record.payload)
-            .headers(record.headers);
+            .headers(delivery::copy_headers(&record.headers));
+        if let Some(key) = record.key.as_ref() {
+            future_record = future_record.key(key);
+        }
+        self.producer
+            .send(future_record, Duration::from_secs(0))
+            .await
+            .map_err(|(error, _)| error)
+    }
 
-        trace!(
+    fn dead_letter<K, T>(
+        &self,
+        record: &RecordData<K, T>,
+        topic: &str,
+        error: &KafkaError,
+        attempts: u32,
+    ) where
+        T: ToBytes,
+        K: ToBytes,
+    {
+        self.metrics
+            .event_failure_count
+            .get_or_create(&TopicLabels {
+                topic: topic.to_string(),
+            })
+            .inc();
+        self.dead_letter.write(
             topic,
-            key = format!("{:?}", record.key),
-            record = format!(
-                "{:?}",
-                Reader::new(record.payload.to_bytes())
-                    .unwrap()
-                    .map(|value| value.unwrap())
-                    .collect::<Vec<apache_avro::types::Value>>()
-            )
+            record.key.as_ref().map(|key| key.to_bytes()),
+            &record.headers,
+            record.payload.to_bytes(),
+            error,
+            attempts,
         );
+    }
 
-        if record.key.is_some() {
-            future_record = future_record.key(record.key.as_ref().unwrap());
+    /// Sends the event, in a transaction of its own in transactional mode.
+    ///
+    /// Events that could not be delivered are written to the dead-letter file, so the caller
+    /// can carry on with the experiment.
+    pub async fn send_event<K, T>(
+        &self,
+        record: RecordData<K, T>,
+        topic: &str,
+    ) -> Result<(), KafkaError>
+    where
+        T: ToBytes,
+        K: ToBytes + std::fmt::Debug,
+    {
+        self.send_events(vec![(topic, record)]).await
+    }
+
+    /// Sends the events concurrently, retrying with exponential backoff while the error is
+    /// retriable.
+    ///
+    /// In transactional mode the events are committed atomically: consumers reading with
+    /// `isolation.level=read_committed` see either all of them or none. A failed transaction is
+    /// retried as a whole.
+    pub async fn send_events<K, T>(
+        &self,
+        records: Vec<(&str, RecordData<K, T>)>,
+    ) -> Result<(), KafkaError>
+    where
+        T: ToBytes,
+        K: ToBytes + std::fmt::Debug,
+    {
+        for (topic, record) in &records {
+            Self::trace_record(record, topic);
+            self.update_count(topic, record.key.as_ref());
+        }
+        match &self.transaction_lock {
+            Some(lock) => self.send_transaction(lock, &records).await,
+            None => future::join_all(
+                records
+                    .iter()
+                    .map(|(topic, record)| self.send_with_retries(record, topic)),
+            )
+            .await
+            .into_iter()
+            .collect(),
         }
-        self.update_count(topic, record.key.as_ref());
+    }
 
-        self.producer
-            .send(future_record, Duration::from_secs(0))
+    async fn send_with_retries<K, T>(
+        &self,
+        record: &RecordData<K, T>,
+        topic: &str,
+    ) -> Result<(), KafkaError>
+    where
+        T: ToBytes,
+        K: ToBytes,
+    {
+        let mut retry = 0;
+        loop {
+            let error = match self.produce(record, topic).await {
+                Ok(_) => return Ok(()),
+                Err(error) => error,
+            };
+
+            if delivery::classify(&error) == ErrorClass::Retriable
+                && retry < self.retry_policy.max_retries
+            {
+                let backoff = self.retry_policy.backoff(retry);
+                warn!(
+                    topic,
+                    error = error.to_string(),
+                    retry,
+                    backoff = backoff.as_millis(),
+                    "Failed to produce message, retrying"
+                );
+                self.metrics
+                    .event_retry_count
+                    .get_or_create(&TopicLabels {
+                        topic: topic.to_string(),
+                    })
+                    .inc();
+                tokio::time::sleep(backoff).await;
+                retry += 1;
+                continue;
+            }
+
+            error!(
+                topic,
+                error = error.to_string(),
+                attempts = retry + 1,
+                "Failed to produce message, writing it to the dead-letter file"
+            );
+            self.dead_letter(record, topic, &error, retry + 1);
+            return Err(error);
+        }
+    }
+
+    async fn send_transaction<K, T>(
+        &self,
+        lock: &Mutex<()>,
+        records: &[(&str, RecordData<K, T>)],
+    ) -> Result<(), KafkaError>
+    where
+        T: ToBytes,
+        K: ToBytes,
+    {
+        let _guard = lock.lock().await;
+        let mut retry = 0;
+        loop {
+            let error = match self.try_transaction(records).await {
+                Ok(()) => {
+                    self.count_transaction(TransactionOutcome::Committed);
+                    return Ok(());
+                }
+                Err(error) => error,
+            };
+            self.count_transaction(TransactionOutcome::Aborted);
+
+            if delivery::classify(&error) == ErrorClass::Retriable
+                && retry < self.retry_policy.max_retries
+            {
+                let backoff = self.retry_policy.backoff(retry);
+                warn!(
+                    error = error.to_string(),
+                    events = records.len(),
+                    retry,
+                    backoff = backoff.as_millis(),
+                    "Transaction aborted, retrying"
+                );
+                for (topic, _) in records {
+                    self.metrics
+                        .event_retry_count
+                        .get_or_create(&TopicLabels {
+                            topic: topic.to_string(),
+                        })
+                        .inc();
+                }
+                tokio::time::sleep(backoff).await;
+                retry += 1;
+                continue;
+            }
+
+            if delivery::is_fatal(&error) {
+                error!(
+                    error = error.to_string(),
+                    "Transactional producer failed fatally, no transaction can be committed anymore"
+                );
+            }
+            error!(
+                error = error.to_string(),
+                events = records.len(),
+                attempts = retry + 1,
+                "Transaction aborted, writing its events to the dead-letter file"
+            );
+            for (topic, record) in records {
+                self.dead_letter(record, topic, &error, retry + 1);
+            }
+            return Err(error);
+        }
+    }
+
+    /// Sends the records in a new transaction, which is aborted if any of them fails.
+    async fn try_transaction<K, T>(
+        &self,
+        records: &[(&str, RecordData<K, T>)],
+    ) -> Result<(), KafkaError>
+    where
+        T: ToBytes,
+        K: ToBytes,
+    {
+        self.producer.begin_transaction()?;
+        let produced = future::join_all(
+            records
+                .iter()
+                .map(|(topic, record)| self.produce(record, topic)),
+        )
+        .await;
+        let result = match produced.into_iter().find_map(Result::err) {
+            Some(error) => Err(error),
+            None => {
+                let producer = self.producer.clone();
+                tokio::task::spawn_blocking(move || {
+                    producer.commit_transaction(TRANSACTION_TIMEOUT)
+                })
+                .await
+                .expect("Commit task panicked")
+            }
+        };
+        // A fatal error leaves no transaction to abort
+        if result
+            .as_ref()
+            .is_err_and(|error| !delivery::is_fatal(error))
+        {
+            let producer = self.producer.clone();
+            let aborted = tokio::task::spawn_blocking(move || {
+                producer.abort_transaction(TRANSACTION_TIMEOUT)
+            })
             .await
+            .expect("Abort task panicked");
+            if let Err(e) = aborted {
+                error!(error = e.to_string(), "Failed to abort transaction");
+            }
+        }
+        result
+    }
+
+    fn count_transaction(&self, outcome: TransactionOutcome) {
+        self.metrics
+            .transaction_count
+            .get_or_create(&TransactionLabels { outcome })
+            .inc();
+    }
+
+    /// Waits up to `timeout` for the queued messages to be delivered.
+    ///
+    /// Returns the number of messages that were still in flight once the timeout expired.
+    pub fn flush(&self, timeout: Duration) -> i32 {
+        self.producer.flush(timeout);
+        self.producer.in_flight_count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use event_schema::{EventHeaders, RecordSchemas};
+    use rdkafka::message::Headers;
+
+    fn experiment_schemas(schema_version: u32) -> ExperimentSchemas {
+        ExperimentSchemas::new(
+            EventEncoding {
+                encoding: Encoding::SingleObject,
+                schema_version,
+                schema_ids: HashMap::new(),
+                format: Format::Avro,
+                topic_formats: HashMap::from([("experiment-document".into(), Format::Protobuf)]),
+            },
+            "experiment",
+            Some("experiment-document"),
+        )
+    }
+
+    #[tokio::test]
+    async fn every_event_matches_its_headers() {
+        let temp_range = TempRange::new(10.0, 12.0).unwrap();
+        let measurements = [Measurement {
+            measurement_id: "1234".into(),
+            timestamp: 1692029115.4314,
+            temperature: 11.0,
+            notification_type: None,
+            stage: ExperimentStage::CarryOut,
+        }];
+        for schema_version in 1..=event_schema::LATEST_VERSION {
+            let schemas = experiment_schemas(schema_version);
+            let events = [
+                (
+                    "experiment_configured",
+                    schemas.experiment_configured_event("5678", "d.landau", &[], temp_range),
+                ),
+                (
+                    "stabilization_started",
+                    schemas.stabilization_started_event("5678"),
+                ),
+                ("experiment_started", schemas.experiment_started_event("5678")),
+                (
+                    "experiment_terminated",
+                    schemas.experiment_terminated_event("5678", true),
+                ),
+                (
+                    "sensor_temperature_measured",
+                    schemas.temperature_measured_event(
+                        "5678",
+                        "1234",
+                        "sensor-1",
+                        11.0,
+                        1692029115.4314,
+                        "nonce.ciphertext",
+                        &ExperimentStage::CarryOut,
+                    ),
+                ),
+                (
+                    "experiment_document",
+                    schemas.experiment_document_event("5678", &measurements, temp_range, true),
+                ),
+                (
+                    "producer_heartbeat",
+                    schemas.producer_heartbeat_event("5678", Some(1692029115.4314)),
+                ),
+            ];
+            for (record_name, event) in events {
+                let headers = schemas.headers(record_name);
+                let headers =
+                    EventHeaders::parse((0..headers.count()).filter_map(|idx| headers.get(idx)))
+                        .unwrap();
+                assert_eq!(headers.record_name.as_deref(), Some(record_name));
+                assert_eq!(headers.schema_version, schema_version);
+                let expected_format = match record_name {
+                    "experiment_document" => Format::Protobuf,
+                    _ => Format::Avro,
+                };
+                assert_eq!(headers.format, expected_format);
+
+                // Read back with the schema the event was written with
+                let record_schemas = RecordSchemas::new(record_name, schema_version);
+                assert!(
+                    record_schemas
+                        .decode(&event.0, &headers, Encoding::SingleObject, None)
+                        .await
+                        .is_ok(),
+                    "{} v{}",
+                    record_name,
+                    schema_version
+                );
+            }
+        }
     }
 }
@@ -0,0 +1,83 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use std::time::Duration;
+use tokio::{sync::oneshot, task::JoinHandle, time::MissedTickBehavior};
+use tracing::{debug, Instrument, Span};
+
+use crate::events::{ExperimentSchemas, KafkaTopicProducer, RecordData};
+
+/// Timestamp of the latest measurement whose sensor events were all published.
+///
+/// Shared between an experiment and its heartbeat task, clones observe the same watermark.
+#[derive(Clone, Default)]
+pub struct Watermark(Arc<AtomicU64>);
+
+impl Watermark {
+    /// Moves the watermark forward to `timestamp`, the watermark never moves back.
+    pub fn advance(&self, timestamp: f64) {
+        // The bit patterns of positive floats are ordered like the floats themselves.
+        self.0.fetch_max(timestamp.to_bits(), Ordering::Relaxed);
+    }
+
+    /// `None` until the first measurement has been published.
+    pub fn get(&self) -> Option<f64> {
+        match self.0.load(Ordering::Relaxed) {
+            0 => None,
+            bits => Some(f64::from_bits(bits)),
+        }
+    }
+}
+
+/// Periodically publishes a `producer_heartbeat` event to the experiment topic.
+pub struct Heartbeat {
+    stop: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl Heartbeat {
+    /// Spawns the heartbeat task of an experiment. Heartbeats are keyed by the experiment ID,
+    /// so they land on the same partition as, and after, the measurements they cover.
+    pub fn spawn(
+        producer: KafkaTopicProducer,
+        schemas: Arc<ExperimentSchemas>,
+        topic: String,
+        experiment_id: String,
+        watermark: Watermark,
+        interval: Duration,
+    ) -> Self {
+        let (stop, mut stopped) = oneshot::channel();
+        let handle = tokio::spawn(
+            async move {
+                let mut interval = tokio::time::interval(interval);
+                // A stalled producer shows up as a gap in the heartbeats, not as a burst.
+                interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                loop {
+                    // Only wait for the tick in the select: cancelling a send could leave a
+                    // transaction open.
+                    tokio::select! {
+                        _ = interval.tick() => {}
+                        _ = &mut stopped => break,
+                    }
+                    let watermark = watermark.get();
+                    debug!(watermark, "Sending heartbeat");
+                    let record = RecordData {
+                        payload: schemas.producer_heartbeat_event(&experiment_id, watermark),
+                        key: Some(&experiment_id),
+                        headers: schemas.headers("producer_heartbeat"),
+                    };
+                    let _ = producer.send_event(record, &topic).await;
+                }
+            }
+            .instrument(Span::current()),
+        );
+        Self { stop, handle }
+    }
+
+    /// Stops the task once the heartbeat in flight, if any, has been sent.
+    pub async fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.handle.await;
+    }
+}
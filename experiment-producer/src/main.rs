@@ -1,43 +1,238 @@
 use ::time::{format_description, UtcOffset};
+use apache_avro::Schema;
 use clap::{builder::FalseyValueParser, command, value_parser, Arg, ArgAction, ArgMatches};
+use event_hash::{Keyring, Sealer};
+use event_schema::{Encoding, Format, SchemaRegistry};
 use futures::future;
-use sqlx::{
-    postgres::{PgPoolOptions, Postgres},
-    Pool,
+use sqlx::postgres::PgPoolOptions;
+use std::{collections::HashMap, env, fs::{self, create_dir_all}, path::Path, sync::Arc};
+use tokio::{
+    task::JoinHandle,
+    time::{self as tktime, Duration},
 };
-use std::{env, fs::{self, create_dir_all}, path::Path};
-use tokio::time::{self as tktime, Duration};
-use tracing::{info, span, Instrument, Level};
+use tracing::{error, info, span, warn, Instrument, Level};
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_subscriber::{filter::LevelFilter, fmt::time::OffsetTime, prelude::*, EnvFilter};
 use anyhow::{Context, Result};
 
 mod config;
 mod database;
+mod delivery;
 mod events;
+mod heartbeat;
 mod metric;
+mod scheduler;
+mod shutdown;
 mod simulator;
 mod time;
+mod writer;
 
-use config::ConfigFile;
-use events::KafkaTopicProducer;
+use config::{ConfigFile, HashKeys};
+use database::Database;
+use delivery::{DeadLetterFile, RetryPolicy};
+use events::{EventEncoding, KafkaTopicProducer};
+use kafka_client::KafkaConfig;
 use metric::{MetricServer, Metrics};
-use simulator::{Experiment, ExperimentConfiguration, TempRange};
+use scheduler::{Scheduler, SchedulerConfig};
+use shutdown::Shutdown;
+use simulator::{DocumentSnapshots, Experiment, ExperimentConfiguration, TempRange};
+use writer::{Writer, WriterConfig};
+
+/// Waits for the queued measurements to be published, once every experiment has dropped its
+/// handle of the scheduler.
+async fn publish_queued(handle: JoinHandle<()>, timeout: Duration) {
+    if tktime::timeout(timeout, handle).await.is_err() {
+        error!("Queued measurements could not be published before the shutdown deadline");
+    }
+}
+
+fn flush_producer(producer: &KafkaTopicProducer, timeout: Duration) {
+    let undelivered = producer.flush(timeout);
+    if undelivered > 0 {
+        error!(undelivered, "Messages could not be delivered before the shutdown deadline");
+    } else {
+        info!("All messages delivered");
+    }
+}
+
+async fn event_encoding(matches: &ArgMatches) -> EventEncoding {
+    let encoding: Encoding = matches
+        .get_one::<String>("encoding")
+        .expect("required")
+        .parse()
+        .expect("Validated by clap");
+    let schema_version = *matches.get_one::<u32>("schema-version").expect("required");
+    let mut schema_ids: HashMap<String, u32> = match matches.get_one::<String>("schema-ids") {
+        Some(path) => {
+            let contents = fs::read_to_string(path)
+                .unwrap_or_else(|_| panic!("Could not read file `{}`", path));
+            serde_json::from_str(&contents).expect("Could not deserialize schema IDs file")
+        }
+        None => HashMap::new(),
+    };
+    if let Some(url) = matches.get_one::<String>("schema-registry") {
+        let registry = SchemaRegistry::new(url);
+        for record_name in event_schema::RECORD_NAMES {
+            let raw_schema =
+                event_schema::raw_schema(record_name, schema_version).expect("Validated by clap");
+            let schema = Schema::parse_str(raw_schema).expect("Valid embedded schema");
+            let schema_id = registry
+                .register(record_name, &schema)
+                .await
+                .unwrap_or_else(|e| panic!("Failed to register `{}`: {}", record_name, e));
+            info!(record_name, schema_version, schema_id, "Registered schema");
+            schema_ids.insert(record_name.to_string(), schema_id);
+        }
+    }
+    if encoding == Encoding::Confluent && schema_ids.is_empty() {
+        panic!("`--encoding confluent` requires `--schema-ids` or `--schema-registry`");
+    }
+    EventEncoding {
+        encoding,
+        schema_version,
+        schema_ids,
+        format: matches
+            .get_one::<String>("format")
+            .expect("required")
+            .parse()
+            .expect("Validated by clap"),
+        topic_formats: matches
+            .get_many::<(String, Format)>("topic-format")
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect(),
+    }
+}
+
+/// Parses `<topic>=<format>`.
+fn parse_topic_format(s: &str) -> Result<(String, Format), String> {
+    let (topic, format) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected `<topic>=<format>`, got `{}`", s))?;
+    Ok((topic.to_string(), format.parse()?))
+}
+
+fn open_dead_letter_file(
+    matches: &ArgMatches,
+    metrics: &Metrics,
+) -> (DeadLetterFile, JoinHandle<()>) {
+    let path = matches
+        .get_one::<String>("dead-letter-file")
+        .expect("required");
+    let buffer = *matches
+        .get_one::<u32>("dead-letter-buffer")
+        .expect("required") as usize;
+    DeadLetterFile::open(path, buffer, metrics.clone())
+        .unwrap_or_else(|e| panic!("Could not open dead-letter file `{}`: {}", path, e))
+}
+
+fn kafka_config(matches: &ArgMatches) -> KafkaConfig {
+    let mut kafka_config = KafkaConfig::from_args(matches)
+        .unwrap_or_else(|e| panic!("Invalid Kafka configuration: {}", e));
+    if *matches.get_one::<bool>("no-ssl").unwrap() {
+        kafka_config.set_default("security.protocol", "PLAINTEXT");
+    }
+    kafka_config
+}
+
+/// Parses `<topic>=<group>`.
+fn parse_topic_group(s: &str) -> Result<(String, String), String> {
+    let (topic, group) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected `<topic>=<group>`, got `{}`", s))?;
+    Ok((topic.to_string(), group.to_string()))
+}
+
+/// Keys of `--sealing-keys`, of `--keyring`, or the `default` key of `--secret-key`. Hashes are
+/// bound to the group of `--topic` if it has one: keyring keys are derived for it, sealed hashes
+/// name it.
+fn hash_keys(matches: &ArgMatches) -> Arc<HashKeys> {
+    let topic = matches.get_one::<String>("topic").expect("required");
+    let mut topic_groups = matches
+        .get_many::<(String, String)>("topic-group")
+        .into_iter()
+        .flatten()
+        .peekable();
+    let grouped = topic_groups.peek().is_some();
+    let group =
+        topic_groups.find_map(|(group_topic, group)| (group_topic == topic).then_some(group));
+    // Hashes of no group open for every group, any team could replay them
+    if group.is_none() && grouped {
+        warn!(
+            topic,
+            "No `--topic-group` for the topic, measurement hashes are not bound to a group"
+        );
+    }
+
+    if let Some(path) = matches.get_one::<String>("sealing-keys") {
+        let mut sealer =
+            Sealer::from_file(path).unwrap_or_else(|e| panic!("Invalid sealing keys: {}", e));
+        if let Some(group) = group {
+            sealer = sealer
+                .for_group(group)
+                .unwrap_or_else(|e| panic!("Invalid group `{}`: {}", group, e));
+        }
+        info!(
+            signer_id = sealer.signer_id(),
+            group, "Sealing measurement hashes"
+        );
+        return Arc::new(HashKeys::Sealed(Box::new(sealer)));
+    }
+    let mut keyring = match matches.get_one::<String>("keyring") {
+        Some(path) => Keyring::from_file(path),
+        None => Keyring::single(matches.get_one::<String>("secret-key").expect("required")),
+    }
+    .unwrap_or_else(|e| panic!("Invalid keyring: {}", e));
+    if let Some(group) = group {
+        keyring = keyring
+            .for_group(group)
+            .unwrap_or_else(|e| panic!("Invalid group `{}`: {}", group, e));
+    }
+    match keyring.key_at(time::current_epoch()) {
+        Some(key) => info!(key_id = key.id(), group, "Encrypting measurement hashes"),
+        None => panic!("The keyring has no active key yet"),
+    }
+    Arc::new(HashKeys::Symmetric(keyring))
+}
+
+fn heartbeat_interval(matches: &ArgMatches) -> Option<Duration> {
+    match *matches.get_one::<u64>("heartbeat-interval").expect("required") {
+        0 => None,
+        millis => Some(Duration::from_millis(millis)),
+    }
+}
+
+fn document_snapshots(matches: &ArgMatches) -> Option<DocumentSnapshots> {
+    matches
+        .get_one::<String>("topic-document-snapshot")
+        .map(|topic| DocumentSnapshots {
+            topic: topic.clone(),
+            interval: *matches
+                .get_one::<u32>("document-snapshot-interval")
+                .expect("required") as usize,
+        })
+}
 
 async fn run_single_experiment(
     mut matches: ArgMatches,
-    pool: Option<Pool<Postgres>>,
+    dead_letter: DeadLetterFile,
+    database: Option<Database>,
     metrics: Metrics,
+    shutdown: Shutdown,
 ) {
     let topic_producer = KafkaTopicProducer::new(
         &matches
             .remove_one::<String>("broker-list")
             .expect("required"),
         metrics.clone(),
-        !matches.remove_one::<bool>("no-ssl").unwrap(),
+        &kafka_config(&matches),
+        RetryPolicy::from(&matches),
+        dead_letter,
+        matches.get_one::<String>("transactional-id").map(String::as_str),
     );
 
-    let experiment_config = ExperimentConfiguration::new(
+    let mut experiment_config = ExperimentConfiguration::new(
         "d.landau@uu.nl".into(),
         matches.remove_one::<u32>("num-sensors").expect("required") as usize,
         matches.remove_one::<u64>("sample-rate").expect("required"),
@@ -56,12 +251,12 @@ async fn run_single_experiment(
         matches
             .remove_one::<u16>("carry-out-samples")
             .expect("required"),
-        matches
-            .remove_one::<String>("secret-key")
-            .expect("required"),
+        hash_keys(&matches),
         matches.remove_one::<String>("topic").expect("required"),
         matches.remove_one::<String>("topic-document"),
     );
+    experiment_config.set_heartbeat_interval(heartbeat_interval(&matches));
+    experiment_config.set_document_snapshots(document_snapshots(&matches));
 
     let start_temperature = matches
         .remove_one::<f32>("start-temperature")
@@ -72,58 +267,99 @@ async fn run_single_experiment(
         "experiment",
         experiment_id = experiment_config.experiment_id
     );
+    let shutdown_timeout = Duration::from_secs(
+        matches
+            .remove_one::<u64>("shutdown-timeout")
+            .expect("required"),
+    );
+    let (scheduler, scheduler_handle) = Scheduler::spawn(
+        topic_producer.clone(),
+        metrics.clone(),
+        SchedulerConfig::from(&matches),
+    );
     let mut experiment = Experiment::new(
         start_temperature,
         experiment_config,
-        topic_producer,
-        pool,
+        topic_producer.clone(),
+        scheduler,
+        database,
         metrics,
+        shutdown,
+        event_encoding(&matches).await,
     );
     experiment.run().instrument(span).await;
+    drop(experiment);
+    publish_queued(scheduler_handle, shutdown_timeout).await;
+    flush_producer(&topic_producer, shutdown_timeout);
 }
 
 async fn run_multiple_experiments(
     matches: ArgMatches,
     config_file: &str,
-    pool: Option<Pool<Postgres>>,
+    dead_letter: DeadLetterFile,
+    database: Option<Database>,
     metrics: Metrics,
+    shutdown: Shutdown,
 ) {
     let config = ConfigFile::from_file(config_file);
+    // A single librdkafka client is shared by every experiment, clones share the same queue.
+    let topic_producer = KafkaTopicProducer::new(
+        matches.get_one::<String>("broker-list").expect("required"),
+        metrics.clone(),
+        &kafka_config(&matches),
+        RetryPolicy::from(&matches),
+        dead_letter,
+        matches.get_one::<String>("transactional-id").map(String::as_str),
+    );
+    let (scheduler, scheduler_handle) = Scheduler::spawn(
+        topic_producer.clone(),
+        metrics.clone(),
+        SchedulerConfig::from(&matches),
+    );
+    let encoding = event_encoding(&matches).await;
+    let hash_keys = hash_keys(&matches);
     let mut handles = vec![];
     for mut entry in config.0 {
         let start_temperature = entry.start_temperature;
         let start_offset = entry.start_time;
-        entry.set_secret_key(matches.get_one::<String>("secret-key").expect("required"));
+        entry.set_hash_keys(&hash_keys);
         entry.set_topic(matches.get_one::<String>("topic").expect("required"));
         entry.set_topic_document(
             matches
                 .get_one::<String>("topic-document")
                 .map(|topic| topic.as_str()),
         );
-        let experiment_config = ExperimentConfiguration::from(entry);
-        let topic_producer = KafkaTopicProducer::new(
-            matches.get_one::<String>("broker-list").expect("required"),
-            metrics.clone(),
-            !*matches.get_one::<bool>("no-ssl").unwrap(),
-        );
+        let mut experiment_config = ExperimentConfiguration::from(entry);
+        experiment_config.set_heartbeat_interval(heartbeat_interval(&matches));
+        experiment_config.set_document_snapshots(document_snapshots(&matches));
 
         let span = span!(
             Level::INFO,
             "experiment",
             experiment_id = experiment_config.experiment_id
         );
-        let pool = pool.clone();
+        let topic_producer = topic_producer.clone();
+        let scheduler = scheduler.clone();
+        let database = database.clone();
         let metrics = metrics.clone();
+        let mut shutdown = shutdown.clone();
+        let encoding = encoding.clone();
         handles.push(tokio::spawn(
             async move {
-                tktime::sleep(Duration::from_millis(start_offset * 1000)).await;
+                tokio::select! {
+                    _ = tktime::sleep(Duration::from_millis(start_offset * 1000)) => {}
+                    _ = shutdown.triggered() => return,
+                }
 
                 let mut experiment = Experiment::new(
                     start_temperature,
                     experiment_config,
                     topic_producer,
-                    pool,
+                    scheduler,
+                    database,
                     metrics,
+                    shutdown,
+                    encoding,
                 );
                 experiment.run().await;
             }
@@ -131,6 +367,11 @@ async fn run_multiple_experiments(
         ));
     }
     future::join_all(handles).await;
+    drop(scheduler);
+    let shutdown_timeout =
+        Duration::from_secs(*matches.get_one::<u64>("shutdown-timeout").expect("required"));
+    publish_queued(scheduler_handle, shutdown_timeout).await;
+    flush_producer(&topic_producer, shutdown_timeout);
 }
 
 fn configure_tracing(file_subscriber: bool) -> Result<Option<WorkerGuard>> {
@@ -197,6 +438,20 @@ fn configure_cli() -> ArgMatches {
             .default_value("QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh")
             .help("<key> is a 32 character string that must match the key being passed to the notifications-service")
         )
+        .arg(Arg::new("keyring")
+            .required(false)
+            .long("keyring")
+            .action(ArgAction::Set)
+            .conflicts_with("secret-key")
+            .help("<keyring> is the JSON file of keys being passed to the notifications-service, instead of `--secret-key`. Measurement hashes are encrypted with the active key scheduled last, switching keys at their `active_from`")
+        )
+        .arg(Arg::new("sealing-keys")
+            .required(false)
+            .long("sealing-keys")
+            .action(ArgAction::Set)
+            .conflicts_with_all(["secret-key", "keyring"])
+            .help("<sealing-keys> is the JSON file of the signing key of the producer and the public key of the notifications-service, instead of `--keyring`. Measurement hashes are sealed to the notifications-service, the producer cannot decrypt them")
+        )
         .arg(Arg::new("config-file")
             .required(false)
             .action(ArgAction::Set)
@@ -269,6 +524,20 @@ fn configure_cli() -> ArgMatches {
             .action(ArgAction::Set)
             .long("topic-document")
         )
+        .arg(Arg::new("topic-document-snapshot")
+            .required(false)
+            .action(ArgAction::Set)
+            .long("topic-document-snapshot")
+            .help("Compacted topic receiving the experiment document, keyed by experiment, every `--document-snapshot-interval` measurements and once the experiment terminates. Snapshots share the format of `--topic-document`")
+        )
+        .arg(Arg::new("document-snapshot-interval")
+            .required(false)
+            .action(ArgAction::Set)
+            .long("document-snapshot-interval")
+            .default_value("10")
+            .value_parser(value_parser!(u32).range(1..))
+            .help("Number of carry out measurements between two document snapshots")
+        )
         .arg(
             Arg::new("file-subscriber")
                 .required(false)
@@ -284,7 +553,182 @@ fn configure_cli() -> ArgMatches {
                 .action(ArgAction::SetTrue)
                 .default_value("false")
                 .value_parser(FalseyValueParser::new())
-                .help("Producer connects with broker with SSL protocol"),
+                .help("Connect to the broker in plaintext, unless `security.protocol` is set with `--kafka-config` or `--kafka-property`"),
+        )
+        .args(KafkaConfig::args())
+        .arg(
+            Arg::new("database-buffer")
+                .required(false)
+                .long("database-buffer")
+                .alias("ground-truth-buffer")
+                .action(ArgAction::Set)
+                .default_value("10000")
+                .value_parser(value_parser!(u32).range(1..))
+                .help("Number of experiment, measurement and ground truth rows waiting to be written to the database, further rows are dropped"),
+        )
+        .arg(
+            Arg::new("database-batch-size")
+                .required(false)
+                .long("database-batch-size")
+                .alias("ground-truth-batch-size")
+                .action(ArgAction::Set)
+                .default_value("500")
+                .value_parser(value_parser!(u32).range(1..))
+                .help("Maximum number of rows written by a single database transaction"),
+        )
+        .arg(
+            Arg::new("database-flush-interval-ms")
+                .required(false)
+                .long("database-flush-interval-ms")
+                .alias("ground-truth-flush-interval-ms")
+                .action(ArgAction::Set)
+                .default_value("500")
+                .value_parser(value_parser!(u64))
+                .help("Longest a database row waits for its batch to fill up. Failed batches are retried according to `--max-retries`"),
+        )
+        .arg(
+            Arg::new("publish-tick-ms")
+                .required(false)
+                .long("publish-tick-ms")
+                .action(ArgAction::Set)
+                .default_value("20")
+                .value_parser(value_parser!(u64))
+                .help("Longest a measurement waits for the measurements of other experiments to be published along with it"),
+        )
+        .arg(
+            Arg::new("publish-buffer")
+                .required(false)
+                .long("publish-buffer")
+                .action(ArgAction::Set)
+                .default_value("10000")
+                .value_parser(value_parser!(u32).range(1..))
+                .help("Number of measurements waiting to be published, and of measurements in flight, experiments wait for room beyond it"),
+        )
+        .arg(
+            Arg::new("shutdown-timeout")
+                .required(false)
+                .long("shutdown-timeout")
+                .action(ArgAction::Set)
+                .default_value("10")
+                .value_parser(value_parser!(u64))
+                .help("Seconds to wait for queued messages to be delivered once the experiments have terminated"),
+        )
+        .arg(
+            Arg::new("encoding")
+                .required(false)
+                .long("encoding")
+                .action(ArgAction::Set)
+                .default_value("container")
+                .value_parser(Encoding::VALUES)
+                .help("Wire encoding of the Avro events: an object container file per message, Avro single-object encoding or the Confluent wire format"),
+        )
+        .arg(
+            Arg::new("format")
+                .required(false)
+                .long("format")
+                .action(ArgAction::Set)
+                .default_value("avro")
+                .value_parser(Format::VALUES)
+                .help("Format of the events: Avro laid out according to `--encoding`, JSON or Protobuf as defined in `schemas/events.proto`"),
+        )
+        .arg(
+            Arg::new("topic-group")
+                .required(false)
+                .long("topic-group")
+                .action(ArgAction::Append)
+                .value_parser(parse_topic_group)
+                .help("<topic>=<group> encrypting the measurement hashes sent to <topic> with the key of <group>, or naming <group> in hashes sealed with `--sealing-keys`, the JWT subject of the team consuming it. The notifications-service rejects them for any other group. Can be repeated. Without an entry for `--topic`, hashes are encrypted with the master key, or sealed for no group, and are not bound to a group: they decrypt for every group"),
+        )
+        .arg(
+            Arg::new("topic-format")
+                .required(false)
+                .long("topic-format")
+                .action(ArgAction::Append)
+                .value_parser(parse_topic_format)
+                .help("<topic>=<format> overriding `--format` for the events sent to <topic>, can be repeated"),
+        )
+        .arg(
+            Arg::new("schema-version")
+                .required(false)
+                .long("schema-version")
+                .action(ArgAction::Set)
+                .default_value("1")
+                .value_parser(value_parser!(u32).range(1..=event_schema::LATEST_VERSION as i64))
+                .help("Version of the event schemas, sent along in the `schema_version` header of every event"),
+        )
+        .arg(
+            Arg::new("schema-ids")
+                .required(false)
+                .long("schema-ids")
+                .action(ArgAction::Set)
+                .help("JSON file mapping each record name to its schema registry ID. Required by `--encoding confluent` unless `--schema-registry` is set"),
+        )
+        .arg(
+            Arg::new("schema-registry")
+                .required(false)
+                .long("schema-registry")
+                .action(ArgAction::Set)
+                .help("URL of a Confluent-compatible schema registry the event schemas are registered with at startup, using the record name as subject"),
+        )
+        .arg(
+            Arg::new("transactional-id")
+                .required(false)
+                .long("transactional-id")
+                .action(ArgAction::Set)
+                .help("Enables transactions: the sensor events of a measurement, or the events of a stage transition, are committed atomically. Must be unique per producer instance. Transactions run one at a time across all experiments, each waiting for its commit round-trip: throughput drops to about one measurement per commit latency. A fenced producer gives up and dead-letters every event"),
+        )
+        .arg(
+            Arg::new("heartbeat-interval")
+                .required(false)
+                .long("heartbeat-interval")
+                .action(ArgAction::Set)
+                .default_value("0")
+                .value_parser(value_parser!(u64))
+                .help("Interval in milliseconds between the `producer_heartbeat` events of an experiment, carrying the timestamp of its latest fully published measurement. 0, the default, disables them: consumers must know the `producer_heartbeat` record before they are enabled"),
+        )
+        .arg(
+            Arg::new("max-retries")
+                .required(false)
+                .long("max-retries")
+                .action(ArgAction::Set)
+                .default_value("5")
+                .value_parser(value_parser!(u32))
+                .help("Number of times the delivery of an event is retried on a retriable error"),
+        )
+        .arg(
+            Arg::new("retry-backoff-ms")
+                .required(false)
+                .long("retry-backoff-ms")
+                .action(ArgAction::Set)
+                .default_value("100")
+                .value_parser(value_parser!(u64))
+                .help("Backoff before the first retry, doubled on every subsequent retry"),
+        )
+        .arg(
+            Arg::new("retry-max-backoff-ms")
+                .required(false)
+                .long("retry-max-backoff-ms")
+                .action(ArgAction::Set)
+                .default_value("5000")
+                .value_parser(value_parser!(u64))
+                .help("Upper bound of the backoff between retries"),
+        )
+        .arg(
+            Arg::new("dead-letter-file")
+                .required(false)
+                .long("dead-letter-file")
+                .action(ArgAction::Set)
+                .default_value("./logs/dead-letter.ndjson")
+                .help("File to which events are appended once their delivery has ultimately failed"),
+        )
+        .arg(
+            Arg::new("dead-letter-buffer")
+                .required(false)
+                .long("dead-letter-buffer")
+                .action(ArgAction::Set)
+                .default_value("10000")
+                .value_parser(value_parser!(u32).range(1..))
+                .help("Number of dead letters waiting to be appended to the dead-letter file, further ones are dropped"),
         )
         .get_matches()
 }
@@ -295,29 +739,48 @@ async fn main() -> Result<()> {
     let mut matches = configure_cli();
     let _guard = configure_tracing(matches.remove_one::<bool>("file-subscriber").unwrap())?;
 
-    let pool = match env::var("DATABASE_URL") {
+    let metrics = Metrics::new();
+    let (database, database_writer) = match env::var("DATABASE_URL") {
         Ok(database_url) => {
-            let pool = Some(
-                PgPoolOptions::new()
-                    .max_connections(5)
-                    .connect(&database_url)
-                    .await
-                    .expect("Unable to connect to database provided in DATABASE_URL"),
-            );
+            let pool = PgPoolOptions::new()
+                .max_connections(5)
+                .connect(&database_url)
+                .await
+                .expect("Unable to connect to database provided in DATABASE_URL");
             info!("Created connection pool to database");
-            pool
+            let (writer, handle) =
+                Writer::spawn(pool, metrics.clone(), WriterConfig::from(&matches));
+            (Some(Database { writer }), Some(handle))
         }
-        _ => None,
+        _ => (None, None),
     };
 
-    let metrics = Metrics::new();
     let metric_server = MetricServer::new(metrics.clone());
     metric_server.start();
+    let shutdown = shutdown::listen();
+    let shutdown_timeout = Duration::from_secs(
+        *matches.get_one::<u64>("shutdown-timeout").expect("required"),
+    );
+
+    let (dead_letter, dead_letter_writer) = open_dead_letter_file(&matches, &metrics);
 
     if let Some(config_file) = matches.remove_one::<String>("config-file") {
-        run_multiple_experiments(matches, &config_file, pool, metrics).await
+        run_multiple_experiments(matches, &config_file, dead_letter, database, metrics, shutdown)
+            .await
     } else {
-        run_single_experiment(matches, pool, metrics).await
+        run_single_experiment(matches, dead_letter, database, metrics, shutdown).await
+    }
+    // The writers stop once the experiments, and their handles, are gone
+    if let Some(handle) = database_writer {
+        if tktime::timeout(shutdown_timeout, handle).await.is_err() {
+            error!("Database rows could not be written before the shutdown deadline");
+        }
+    }
+    if tktime::timeout(shutdown_timeout, dead_letter_writer)
+        .await
+        .is_err()
+    {
+        error!("Dead letters could not be written before the shutdown deadline");
     }
     Ok(())
 }
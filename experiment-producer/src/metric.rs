@@ -1,6 +1,6 @@
 use actix_web::{get, web::Data, App, HttpServer, Responder};
 use prometheus_client::{
-    encoding::{text, EncodeLabelSet},
+    encoding::{text, EncodeLabelSet, EncodeLabelValue},
     metrics::{counter::Counter, family::Family, gauge::Gauge},
     registry::Registry,
 };
@@ -13,10 +13,49 @@ pub struct EventCountLabels {
     pub topic: String,
 }
 
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct TopicLabels {
+    pub topic: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum TransactionOutcome {
+    Committed,
+    Aborted,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct TransactionLabels {
+    pub outcome: TransactionOutcome,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum RowOutcome {
+    Written,
+    /// The buffer of the writer was full.
+    Dropped,
+    /// Writing failed after every retry.
+    Failed,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct DatabaseRowLabels {
+    pub table: String,
+    pub outcome: RowOutcome,
+}
+
 #[derive(Clone)]
 pub struct Metrics {
     pub event_count: Family<EventCountLabels, Counter>,
     pub experiment_gauge: Gauge,
+    pub event_retry_count: Family<TopicLabels, Counter>,
+    pub event_failure_count: Family<TopicLabels, Counter>,
+    pub dead_letter_drop_count: Family<TopicLabels, Counter>,
+    pub transaction_count: Family<TransactionLabels, Counter>,
+    pub database_row_count: Family<DatabaseRowLabels, Counter>,
+    pub database_retry_count: Counter,
+    pub database_queue_gauge: Gauge,
+    pub publish_queue_gauge: Gauge,
 }
 
 impl Metrics {
@@ -24,6 +63,14 @@ impl Metrics {
         Self {
             event_count: Family::<EventCountLabels, Counter>::default(),
             experiment_gauge: Gauge::default(),
+            event_retry_count: Family::<TopicLabels, Counter>::default(),
+            event_failure_count: Family::<TopicLabels, Counter>::default(),
+            dead_letter_drop_count: Family::<TopicLabels, Counter>::default(),
+            transaction_count: Family::<TransactionLabels, Counter>::default(),
+            database_row_count: Family::<DatabaseRowLabels, Counter>::default(),
+            database_retry_count: Counter::default(),
+            database_queue_gauge: Gauge::default(),
+            publish_queue_gauge: Gauge::default(),
         }
     }
 }
@@ -45,6 +92,46 @@ impl MetricServer {
             "Number of experiments running",
             metrics.experiment_gauge.clone(),
         );
+        registry.register(
+            "experiment_producer_event_retry_count",
+            "Count of event delivery retries",
+            metrics.event_retry_count.clone(),
+        );
+        registry.register(
+            "experiment_producer_event_failure_count",
+            "Count of events that could not be delivered",
+            metrics.event_failure_count.clone(),
+        );
+        registry.register(
+            "experiment_producer_dead_letter_drop_count",
+            "Count of undelivered events dropped instead of written to the dead-letter file",
+            metrics.dead_letter_drop_count.clone(),
+        );
+        registry.register(
+            "experiment_producer_transaction_count",
+            "Count of transactions by outcome, in transactional mode",
+            metrics.transaction_count.clone(),
+        );
+        registry.register(
+            "experiment_producer_database_row_count",
+            "Count of rows written to the database by table and outcome",
+            metrics.database_row_count.clone(),
+        );
+        registry.register(
+            "experiment_producer_database_retry_count",
+            "Count of database batch write retries",
+            metrics.database_retry_count.clone(),
+        );
+        registry.register(
+            "experiment_producer_database_queue",
+            "Number of rows waiting to be written to the database",
+            metrics.database_queue_gauge.clone(),
+        );
+        registry.register(
+            "experiment_producer_publish_queue",
+            "Number of measurements waiting for their tick to be published",
+            metrics.publish_queue_gauge.clone(),
+        );
         Self { registry }
     }
 
@@ -0,0 +1,170 @@
+use clap::ArgMatches;
+use futures::future;
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    sync::{mpsc, oneshot, Semaphore},
+    task::JoinHandle,
+    time::{self, Instant},
+};
+use tracing::{Instrument, Span};
+
+use crate::events::{KafkaTopicProducer, SensorEvent};
+use crate::heartbeat::Watermark;
+use crate::metric::Metrics;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SchedulerConfig {
+    /// Measurements waiting to be published, further experiments wait for room. As many again
+    /// may be in flight, ticks are published without waiting for the retries of the previous ones.
+    pub buffer: usize,
+    /// Longest a measurement waits for the other measurements of its tick.
+    pub tick: Duration,
+}
+
+impl From<&ArgMatches> for SchedulerConfig {
+    fn from(args: &ArgMatches) -> Self {
+        Self {
+            buffer: *args.get_one::<u32>("publish-buffer").expect("required") as usize,
+            tick: Duration::from_millis(*args.get_one::<u64>("publish-tick-ms").expect("required")),
+        }
+    }
+}
+
+/// Sensor events of a measurement, waiting for their tick.
+struct Queued {
+    topic: String,
+    sensor_events: Vec<SensorEvent>,
+    timestamp: f64,
+    watermark: Watermark,
+    /// Previous measurement of the experiment, the watermark and `published` wait for it.
+    after: Option<Published>,
+    published: oneshot::Sender<()>,
+    span: Span,
+}
+
+/// Handle queueing measurements for the publishing task.
+///
+/// The task publishes the measurements of every experiment queued within a tick at once, each tick
+/// from a task of its own: experiments do not hold the sensor events of their measurements in
+/// flight themselves, the queue bounds those waiting and a semaphore those in flight. A tick in
+/// backoff does not hold back the next ones. The task stops once every handle has been dropped
+/// and the queue has been published.
+#[derive(Clone)]
+pub struct Scheduler {
+    tx: mpsc::Sender<Queued>,
+    metrics: Metrics,
+}
+
+/// Resolves once the sensor events of a measurement, and of the measurements of its experiment
+/// queued before, have been published or dead-lettered.
+pub struct Published(oneshot::Receiver<()>);
+
+impl Published {
+    pub async fn wait(self) {
+        let _ = self.0.await;
+    }
+}
+
+impl Scheduler {
+    pub fn spawn(
+        producer: KafkaTopicProducer,
+        metrics: Metrics,
+        config: SchedulerConfig,
+    ) -> (Self, JoinHandle<()>) {
+        let (tx, rx) = mpsc::channel(config.buffer);
+        let handle = tokio::spawn(publish_ticks(rx, producer, metrics.clone(), config));
+        (Self { tx, metrics }, handle)
+    }
+
+    /// Queues the sensor events of a measurement for the next tick, waiting for room in the
+    /// queue. `watermark` advances to `timestamp` once they have all been published, after the
+    /// previous measurement of the experiment, `after`.
+    pub async fn publish(
+        &self,
+        topic: &str,
+        sensor_events: Vec<SensorEvent>,
+        timestamp: f64,
+        watermark: &Watermark,
+        after: Option<Published>,
+    ) -> Published {
+        let (published, rx) = oneshot::channel();
+        let queued = Queued {
+            topic: topic.to_string(),
+            sensor_events,
+            timestamp,
+            watermark: watermark.clone(),
+            after,
+            published,
+            span: Span::current(),
+        };
+        // Once the task is gone the sender is dropped along with the measurement, which resolves
+        // `Published` right away.
+        if self.tx.send(queued).await.is_ok() {
+            self.metrics.publish_queue_gauge.inc();
+        }
+        Published(rx)
+    }
+}
+
+async fn publish_ticks(
+    mut rx: mpsc::Receiver<Queued>,
+    producer: KafkaTopicProducer,
+    metrics: Metrics,
+    config: SchedulerConfig,
+) {
+    let in_flight = Arc::new(Semaphore::new(config.buffer));
+    let mut tick = Vec::new();
+    while let Some(queued) = rx.recv().await {
+        tick.push(queued);
+        let deadline = Instant::now() + config.tick;
+        while tick.len() < config.buffer {
+            match time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(queued)) => tick.push(queued),
+                Ok(None) | Err(_) => break,
+            }
+        }
+        metrics.publish_queue_gauge.dec_by(tick.len() as i64);
+        let permits = in_flight
+            .clone()
+            .acquire_many_owned(tick.len() as u32)
+            .await
+            .expect("never closed");
+        let tick = std::mem::take(&mut tick);
+        let producer = producer.clone();
+        tokio::spawn(async move {
+            future::join_all(tick.into_iter().map(|queued| publish(&producer, queued))).await;
+            drop(permits);
+        });
+    }
+    // Waits for the ticks still in flight
+    let _ = in_flight.acquire_many(config.buffer as u32).await;
+}
+
+async fn publish(producer: &KafkaTopicProducer, queued: Queued) {
+    let Queued {
+        topic,
+        sensor_events,
+        timestamp,
+        watermark,
+        after,
+        published,
+        span,
+    } = queued;
+    // In transactional mode consumers see either the whole measurement or none of it.
+    let sent = producer
+        .send_events(
+            sensor_events
+                .into_iter()
+                .map(|record| (topic.as_str(), record))
+                .collect(),
+        )
+        .instrument(span)
+        .await;
+    if let Some(after) = after {
+        after.wait().await;
+    }
+    if sent.is_ok() {
+        watermark.advance(timestamp);
+    }
+    let _ = published.send(());
+}
@@ -0,0 +1,37 @@
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
+use tracing::info;
+
+/// Handle observed by experiments to find out whether the producer is shutting down.
+///
+/// Cloning the handle is cheap, every experiment holds its own copy.
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    pub fn is_triggered(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolves once SIGTERM or SIGINT has been received.
+    pub async fn triggered(&mut self) {
+        // An error means the sender was dropped, which only happens after the signal.
+        let _ = self.0.wait_for(|triggered| *triggered).await;
+    }
+}
+
+/// Spawns the task listening for SIGTERM and SIGINT.
+pub fn listen() -> Shutdown {
+    let (tx, rx) = watch::channel(false);
+    let mut sigterm = signal(SignalKind::terminate()).expect("Failed to register SIGTERM handler");
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = sigterm.recv() => info!(signal = "SIGTERM", "Shutting down"),
+            _ = tokio::signal::ctrl_c() => info!(signal = "SIGINT", "Shutting down"),
+        }
+        tx.send_replace(true);
+    });
+    Shutdown(rx)
+}
@@ -1,21 +1,26 @@
-use futures::future;
 use rand::Rng;
-use rdkafka::message::OwnedHeaders;
 use serde::Deserialize;
-use sqlx::{Pool, Postgres};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::{task::JoinHandle, time};
-use tracing::{debug, info, Instrument, Span};
+use tokio::time;
+use tracing::{debug, info, warn, Instrument};
 use uuid::Uuid;
 
 use event_hash::NotificationType;
 
-use crate::config::{ConfigEntry, UncheckedTempRange};
-use crate::database;
-use crate::events::{self, EventWrapper, ExperimentSchemas, KafkaTopicProducer, RecordData};
+use crate::config::{ConfigEntry, HashKeys, UncheckedTempRange};
+use crate::database::Database;
+use crate::events::{
+    self, EventEncoding, EventWrapper, ExperimentSchemas, KafkaTopicProducer, RecordData,
+    SensorEvent,
+};
+use crate::heartbeat::{Heartbeat, Watermark};
 use crate::metric::Metrics;
+use crate::scheduler::{Published, Scheduler};
+use crate::shutdown::Shutdown;
+use crate::writer::{GroundTruth, Row};
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub enum ExperimentStage {
     Uninitialized,
     Configuration,
@@ -24,6 +29,19 @@ pub enum ExperimentStage {
     Terminated,
 }
 
+impl ExperimentStage {
+    /// Name of the stage in the `sensor_temperature_measured` events and the database.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExperimentStage::Uninitialized => "uninitialized",
+            ExperimentStage::Configuration => "configuration",
+            ExperimentStage::Stabilization => "stabilization",
+            ExperimentStage::CarryOut => "carry_out",
+            ExperimentStage::Terminated => "terminated",
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Deserialize)]
 #[serde(try_from = "UncheckedTempRange")]
 pub struct TempRange {
@@ -111,9 +129,21 @@ pub struct ExperimentConfiguration {
     temp_range: TempRange,
     stabilization_samples: u16,
     carry_out_samples: u16,
-    secret_key: String,
+    hash_keys: Arc<HashKeys>,
     topic: String,
     topic_document: Option<String>,
+    /// Interval between `producer_heartbeat` events, `None` disables them.
+    heartbeat_interval: Option<Duration>,
+    document_snapshots: Option<DocumentSnapshots>,
+}
+
+/// Publishes the experiment document, as measured so far, every `interval` carry out
+/// measurements. Snapshots are keyed by experiment, on a compacted topic the latest one is the
+/// current state of the experiment.
+#[derive(Clone, Debug)]
+pub struct DocumentSnapshots {
+    pub topic: String,
+    pub interval: usize,
 }
 
 impl ExperimentConfiguration {
@@ -124,7 +154,7 @@ impl ExperimentConfiguration {
         temp_range: TempRange,
         stabilization_samples: u16,
         carry_out_samples: u16,
-        secret_key: String,
+        hash_keys: Arc<HashKeys>,
         topic: String,
         topic_document: Option<String>,
     ) -> Self {
@@ -139,11 +169,21 @@ impl ExperimentConfiguration {
             temp_range,
             stabilization_samples,
             carry_out_samples,
-            secret_key,
+            hash_keys,
             topic,
             topic_document,
+            heartbeat_interval: None,
+            document_snapshots: None,
         }
     }
+
+    pub fn set_heartbeat_interval(&mut self, heartbeat_interval: Option<Duration>) {
+        self.heartbeat_interval = heartbeat_interval;
+    }
+
+    pub fn set_document_snapshots(&mut self, document_snapshots: Option<DocumentSnapshots>) {
+        self.document_snapshots = document_snapshots;
+    }
 }
 
 impl From<ConfigEntry> for ExperimentConfiguration {
@@ -156,7 +196,7 @@ impl From<ConfigEntry> for ExperimentConfiguration {
             stabilization_samples,
             carry_out_samples,
             start_time: _,
-            secret_key,
+            hash_keys,
             start_temperature: _,
             topic,
             topic_document,
@@ -168,7 +208,7 @@ impl From<ConfigEntry> for ExperimentConfiguration {
             temp_range,
             stabilization_samples,
             carry_out_samples,
-            secret_key,
+            hash_keys,
             topic,
             topic_document,
         )
@@ -176,23 +216,34 @@ impl From<ConfigEntry> for ExperimentConfiguration {
 }
 
 pub struct Experiment {
-    experiment_schemas: ExperimentSchemas,
+    experiment_schemas: Arc<ExperimentSchemas>,
+    watermark: Watermark,
     sample: TemperatureSample,
     measurements: Vec<Measurement>,
+    /// Transitions expecting a notification so far, numbering them across stages.
+    transitions: u32,
     stage: ExperimentStage,
     config: ExperimentConfiguration,
     producer: KafkaTopicProducer,
-    pool: Option<Pool<Postgres>>,
+    scheduler: Scheduler,
+    /// Latest measurement queued for publishing.
+    queued: Option<Published>,
+    database: Option<Database>,
     metrics: Metrics,
+    shutdown: Shutdown,
 }
 
 impl Experiment {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         start: f32,
         config: ExperimentConfiguration,
         producer: KafkaTopicProducer,
-        pool: Option<Pool<Postgres>>,
+        scheduler: Scheduler,
+        database: Option<Database>,
         metrics: Metrics,
+        shutdown: Shutdown,
+        encoding: EventEncoding,
     ) -> Self {
         metrics.experiment_gauge.inc();
         let sample = TemperatureSample {
@@ -200,19 +251,51 @@ impl Experiment {
             temp_range: config.temp_range,
         };
         Experiment {
-            experiment_schemas: ExperimentSchemas::new(),
+            experiment_schemas: Arc::new(ExperimentSchemas::new(
+                encoding,
+                &config.topic,
+                config.topic_document.as_deref().or(config
+                    .document_snapshots
+                    .as_ref()
+                    .map(|snapshots| snapshots.topic.as_str())),
+            )),
+            watermark: Watermark::default(),
             stage: ExperimentStage::Uninitialized,
             measurements: Vec::new(),
+            transitions: 0,
             sample,
             producer,
+            scheduler,
+            queued: None,
             config,
-            pool,
+            database,
             metrics,
+            shutdown,
+        }
+    }
+
+    /// Records the start of `stage` in the database, if any.
+    fn enter_stage(&mut self, stage: ExperimentStage) {
+        self.stage = stage;
+        if let Some(database) = &self.database {
+            database.writer.write(Row::Stage {
+                experiment_id: self.config.experiment_id.clone(),
+                stage,
+                timestamp: crate::time::current_epoch(),
+            });
         }
     }
 
     async fn stage_configuration(&mut self) {
-        self.stage = ExperimentStage::Configuration;
+        if let Some(database) = &self.database {
+            database.writer.write(Row::Experiment {
+                experiment_id: self.config.experiment_id.clone(),
+                researcher: self.config.researcher.clone(),
+                sensors: self.config.sensors.clone(),
+                temp_range: self.config.temp_range,
+            });
+        }
+        self.enter_stage(ExperimentStage::Configuration);
         let record = RecordData {
             payload: self.experiment_schemas.experiment_configured_event(
                 &self.config.experiment_id,
@@ -221,140 +304,234 @@ impl Experiment {
                 self.config.temp_range,
             ),
             key: Some(&self.config.experiment_id),
-            headers: OwnedHeaders::new().add("record_name", "experiment_configured"),
+            headers: self.experiment_schemas.headers("experiment_configured"),
         };
-        self.producer
-            .send_event(record, &self.config.topic)
-            .await
-            .expect("Failed to produce message");
+        // Undelivered events end up in the dead-letter file, the experiment carries on
+        let _ = self.producer.send_event(record, &self.config.topic).await;
     }
 
     async fn stage_stabilization(&mut self) {
-        self.stage = ExperimentStage::Stabilization;
+        self.enter_stage(ExperimentStage::Stabilization);
         let record = RecordData {
             payload: self
                 .experiment_schemas
                 .stabilization_started_event(&self.config.experiment_id),
             key: Some(&self.config.experiment_id),
-            headers: OwnedHeaders::new().add("record_name", "stabilization_started"),
+            headers: self.experiment_schemas.headers("stabilization_started"),
         };
-        self.producer
-            .send_event(record, &self.config.topic)
-            .await
-            .expect("Failed to produce message");
+        let _ = self.producer.send_event(record, &self.config.topic).await;
 
         // Stabilization Temperature Samples
         let stabilization_samples = self
             .sample
             .stabilization_samples(self.config.stabilization_samples.into());
         let stabilization_events = events::temperature_events(
-            &mut self.experiment_schemas,
+            &self.experiment_schemas,
             stabilization_samples,
             &self.config.experiment_id,
             &self.config.researcher,
             &self.config.sensors,
             &self.stage,
-            &self.config.secret_key,
+            self.transitions,
+            &self.config.hash_keys,
         );
 
-        for (sensor_events, _span, measurement) in stabilization_events {
-            let enter = _span.enter();
-            measurement
-                .persist_sensor_events(
-                    &self.producer,
-                    self.pool.clone(),
-                    &self.config.topic,
-                    &self.config.experiment_id,
-                    sensor_events,
-                    self.config.sample_rate,
-                )
-                .await;
-            drop(enter);
+        for (sensor_events, span, measurement) in stabilization_events {
+            if self.shutdown.is_triggered() {
+                break;
+            }
+            if measurement.notification_type.is_some() {
+                self.transitions += 1;
+            }
+            self.queued = Some(
+                measurement
+                    .persist_sensor_events(
+                        &self.scheduler,
+                        self.database.as_ref(),
+                        &self.config,
+                        sensor_events,
+                        &self.watermark,
+                        self.queued.take(),
+                    )
+                    .instrument(span)
+                    .await,
+            );
+        }
+    }
+
+    /// Waits for the measurements queued so far to be published, so that the events sent next
+    /// follow them.
+    async fn wait_published(&mut self) {
+        if let Some(queued) = self.queued.take() {
+            queued.wait().await;
         }
     }
 
     async fn stage_carry_out(&mut self) {
-        self.stage = ExperimentStage::CarryOut;
+        self.wait_published().await;
+        self.enter_stage(ExperimentStage::CarryOut);
         let record = RecordData {
             payload: self
                 .experiment_schemas
                 .experiment_started_event(&self.config.experiment_id),
             key: Some(&self.config.experiment_id),
-            headers: OwnedHeaders::new().add("record_name", "experiment_started"),
+            headers: self.experiment_schemas.headers("experiment_started"),
         };
-        self.producer
-            .send_event(record, &self.config.topic)
-            .await
-            .expect("Failed to produce message");
+        let _ = self.producer.send_event(record, &self.config.topic).await;
 
-        let carry_out_samples = self
-            .sample
-            .carry_out_samples(self.config.carry_out_samples.into());
+        // Sampled from a copy, snapshots borrow the whole experiment while the samples are drawn
+        let mut sample = self.sample;
+        let carry_out_samples = sample.carry_out_samples(self.config.carry_out_samples.into());
         let carry_out_events = events::temperature_events(
-            &mut self.experiment_schemas,
+            &self.experiment_schemas,
             carry_out_samples,
             &self.config.experiment_id,
             &self.config.researcher,
             &self.config.sensors,
             &self.stage,
-            &self.config.secret_key,
+            self.transitions,
+            &self.config.hash_keys,
         );
-        for (sensor_events, _span, measurement) in carry_out_events {
-            measurement
-                .persist_sensor_events(
-                    &self.producer,
-                    self.pool.clone(),
-                    &self.config.topic,
-                    &self.config.experiment_id,
-                    sensor_events,
-                    self.config.sample_rate,
-                )
-                .await;
+        for (sensor_events, span, measurement) in carry_out_events {
+            if self.shutdown.is_triggered() {
+                break;
+            }
+            if measurement.notification_type.is_some() {
+                self.transitions += 1;
+            }
+            self.queued = Some(
+                measurement
+                    .persist_sensor_events(
+                        &self.scheduler,
+                        self.database.as_ref(),
+                        &self.config,
+                        sensor_events,
+                        &self.watermark,
+                        self.queued.take(),
+                    )
+                    .instrument(span)
+                    .await,
+            );
             self.measurements.push(measurement);
+            // No snapshots in between for an interval of 0
+            let snapshot_topic = self
+                .config
+                .document_snapshots
+                .as_ref()
+                .filter(|snapshots| {
+                    self.measurements.len().checked_rem(snapshots.interval) == Some(0)
+                })
+                .map(|snapshots| snapshots.topic.clone());
+            if let Some(topic) = snapshot_topic {
+                // The snapshot follows the sensor events of the measurements it includes. Waits
+                // on the field itself, the samples still borrow the experiment.
+                if let Some(queued) = self.queued.take() {
+                    queued.wait().await;
+                }
+                let record = self.document_record(&topic, false);
+                // Dead-lettered by the producer, the next snapshot includes its measurements
+                if let Err(error) = self.producer.send_event(record, &topic).await {
+                    warn!(
+                        topic,
+                        error = error.to_string(),
+                        measurements = self.measurements.len(),
+                        "Failed to publish document snapshot"
+                    );
+                }
+            }
+        }
+        self.sample = sample;
+    }
+
+    fn document_record(
+        &self,
+        topic: &str,
+        interrupted: bool,
+    ) -> RecordData<&String, EventWrapper> {
+        debug!(
+            topic,
+            measurements = self.measurements.len(),
+            "Publishing experiment document"
+        );
+        RecordData {
+            payload: self.experiment_schemas.experiment_document_event(
+                &self.config.experiment_id,
+                &self.measurements,
+                self.config.temp_range,
+                interrupted,
+            ),
+            headers: self.experiment_schemas.headers("experiment_document"),
+            key: Some(&self.config.experiment_id),
         }
+    }
 
-        self.stage = ExperimentStage::Terminated;
+    async fn stage_termination(&mut self) {
+        self.wait_published().await;
+        self.enter_stage(ExperimentStage::Terminated);
+        if let Some(database) = &self.database {
+            database.writer.write(Row::Interrupted {
+                experiment_id: self.config.experiment_id.clone(),
+                interrupted: self.shutdown.is_triggered(),
+            });
+        }
         let record = RecordData {
-            payload: self
-                .experiment_schemas
-                .experiment_terminated_event(&self.config.experiment_id),
+            payload: self.experiment_schemas.experiment_terminated_event(
+                &self.config.experiment_id,
+                self.shutdown.is_triggered(),
+            ),
             key: Some(&self.config.experiment_id),
-            headers: OwnedHeaders::new().add("record_name", "experiment_terminated"),
+            headers: self.experiment_schemas.headers("experiment_terminated"),
         };
-        self.producer
-            .send_event(record, &self.config.topic)
-            .await
-            .expect("Failed to produce message");
-
-        if let Some(topic_document) = &self.config.topic_document {
-            let record = RecordData {
-                payload: self.experiment_schemas.experiment_document_event(
-                    &self.config.experiment_id,
-                    &self.measurements,
-                    self.config.temp_range,
-                ),
-                headers: OwnedHeaders::new(),
-                key: Some(&self.config.experiment_id),
-            };
-            self.producer
-                .send_event(record, topic_document)
-                .await
-                .expect("Failed to produce message");
+        let mut records = vec![(self.config.topic.as_str(), record)];
+
+        // The document is committed along with the termination event in transactional mode. The
+        // last snapshot is the complete document.
+        let document_topics = [
+            self.config.topic_document.as_ref(),
+            self.config
+                .document_snapshots
+                .as_ref()
+                .map(|snapshots| &snapshots.topic),
+        ];
+        for topic in document_topics.into_iter().flatten() {
+            let record = self.document_record(topic, self.shutdown.is_triggered());
+            records.push((topic.as_str(), record));
         }
+        let _ = self.producer.send_events(records).await;
     }
 
     pub async fn run(&mut self) {
         let start = Instant::now();
+        let heartbeat = self.config.heartbeat_interval.map(|interval| {
+            Heartbeat::spawn(
+                self.producer.clone(),
+                self.experiment_schemas.clone(),
+                self.config.topic.clone(),
+                self.config.experiment_id.clone(),
+                self.watermark.clone(),
+                interval,
+            )
+        });
         info!(stage = "configuration");
         self.stage_configuration().await;
-        info!(stage = "stabilization");
         let stabilization = Instant::now();
-        self.stage_stabilization().await;
-        info!(stage = "carry out");
+        if !self.shutdown.is_triggered() {
+            info!(stage = "stabilization");
+            self.stage_stabilization().await;
+        }
         let carry_out = Instant::now();
-        self.stage_carry_out().await;
+        if !self.shutdown.is_triggered() {
+            info!(stage = "carry out");
+            self.stage_carry_out().await;
+        }
+        // No heartbeats follow the termination event
+        if let Some(heartbeat) = heartbeat {
+            heartbeat.stop().await;
+        }
+        self.stage_termination().await;
         info!(
             stage = "terminated",
+            interrupted = self.shutdown.is_triggered(),
             elapsed = start.elapsed().as_millis(),
             stabilization = (carry_out - stabilization).as_millis(),
             carry_out = carry_out.elapsed().as_millis()
@@ -368,65 +545,57 @@ impl Drop for Experiment {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Measurement {
     pub measurement_id: String,
     pub timestamp: f64,
     pub temperature: f32,
     pub notification_type: Option<NotificationType>,
+    pub stage: ExperimentStage,
 }
 
 impl Measurement {
     pub async fn persist_sensor_events(
         &self,
-        producer: &KafkaTopicProducer,
-        pool: Option<Pool<Postgres>>,
-        topic: &str,
-        experiment_id: &str,
-        sensor_events: Vec<EventWrapper>,
-        period_millis: u64,
-    ) {
-        let sleep_handle = tokio::spawn(async move {
-            time::sleep(Duration::from_millis(period_millis)).await;
-        });
-        if let (Some(pool), Some(_)) = (pool, &self.notification_type) {
-            let experiment_id = experiment_id.to_string();
-            let measurement_id = self.measurement_id.clone();
-
-            tokio::spawn(async move {
-                database::insert_ground_truth(
-                    &pool,
-                    experiment_id.as_str(),
-                    measurement_id.as_str(),
-                )
-                .await
-                .expect("Insert should not fail");
+        scheduler: &Scheduler,
+        database: Option<&Database>,
+        config: &ExperimentConfiguration,
+        sensor_events: Vec<SensorEvent>,
+        watermark: &Watermark,
+        after: Option<Published>,
+    ) -> Published {
+        // The next measurement is due one period after this one started, no matter how long
+        // publishing takes.
+        let next_measurement = time::Instant::now() + Duration::from_millis(config.sample_rate);
+        if let Some(database) = database {
+            database.writer.write(Row::Measurement {
+                experiment_id: config.experiment_id.clone(),
+                measurement: self.clone(),
             });
+            if let Some(notification_type) = &self.notification_type {
+                database.writer.write(Row::GroundTruth(GroundTruth {
+                    experiment_id: config.experiment_id.clone(),
+                    measurement_id: self.measurement_id.clone(),
+                    notification_type: notification_type.clone(),
+                    measurement_timestamp: self.timestamp,
+                    researcher: config.researcher.clone(),
+                    topic: config.topic.clone(),
+                }));
+            }
         }
-        let span = Span::current();
-        let mut handles: Vec<JoinHandle<_>> = sensor_events
-            .into_iter()
-            .map(|event| {
-                let record = RecordData {
-                    payload: event,
-                    key: Some(experiment_id.to_string()),
-                    headers: OwnedHeaders::new().add("record_name", "sensor_temperature_measured"),
-                };
-                let producer = producer.clone();
-                let topic = topic.to_string().clone();
-                tokio::spawn(
-                    async move {
-                        producer
-                            .send_event(record, &topic)
-                            .await
-                            .expect("Failed to produce message");
-                    }
-                    .instrument(span.clone()),
-                )
-            })
-            .collect();
-        handles.push(sleep_handle);
-        future::join_all(handles).await;
+        // The sensor events are published along with those of the other experiments due in the
+        // same tick, the experiment moves on to its next measurement meanwhile.
+        let published = scheduler
+            .publish(
+                &config.topic,
+                sensor_events,
+                self.timestamp,
+                watermark,
+                after,
+            )
+            .await;
+        time::sleep_until(next_measurement).await;
+        published
     }
 }
 
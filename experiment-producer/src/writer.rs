@@ -0,0 +1,274 @@
+use clap::ArgMatches;
+use sqlx::{Pool, Postgres};
+use std::{collections::HashMap, time::Duration};
+use tokio::{
+    sync::mpsc::{self, error::TrySendError},
+    task::JoinHandle,
+    time::{self, Instant},
+};
+use tracing::{error, warn};
+
+use event_hash::NotificationType;
+
+use crate::database;
+use crate::delivery::RetryPolicy;
+use crate::metric::{DatabaseRowLabels, Metrics, RowOutcome};
+use crate::simulator::{ExperimentStage, Measurement, TempRange};
+
+/// Row of `demo.notification_ground_truth`: a measurement the researcher should be notified
+/// about by the team consuming `topic`.
+#[derive(Clone, Debug)]
+pub struct GroundTruth {
+    pub experiment_id: String,
+    pub measurement_id: String,
+    pub notification_type: NotificationType,
+    pub measurement_timestamp: f64,
+    pub researcher: String,
+    pub topic: String,
+}
+
+/// Row written by the background writer.
+#[derive(Clone, Debug)]
+pub enum Row {
+    Experiment {
+        experiment_id: String,
+        researcher: String,
+        sensors: Vec<String>,
+        temp_range: TempRange,
+    },
+    Stage {
+        experiment_id: String,
+        stage: ExperimentStage,
+        timestamp: f64,
+    },
+    Measurement {
+        experiment_id: String,
+        measurement: Measurement,
+    },
+    /// Sets `interrupted` of an experiment once it terminates.
+    Interrupted {
+        experiment_id: String,
+        interrupted: bool,
+    },
+    GroundTruth(GroundTruth),
+}
+
+impl Row {
+    /// Table the row is written to, labelling its metrics.
+    pub fn table(&self) -> &'static str {
+        match self {
+            Row::Experiment { .. } | Row::Interrupted { .. } => "experiment",
+            Row::Stage { .. } => "experiment_stage",
+            Row::Measurement { .. } => "measurement",
+            Row::GroundTruth(_) => "notification_ground_truth",
+        }
+    }
+
+    pub fn experiment_id(&self) -> &str {
+        match self {
+            Row::Experiment { experiment_id, .. }
+            | Row::Stage { experiment_id, .. }
+            | Row::Measurement { experiment_id, .. }
+            | Row::Interrupted { experiment_id, .. } => experiment_id,
+            Row::GroundTruth(ground_truth) => &ground_truth.experiment_id,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct WriterConfig {
+    /// Rows waiting to be written, further rows are dropped.
+    pub buffer: usize,
+    pub batch_size: usize,
+    /// Longest a row waits for its batch to fill up.
+    pub flush_interval: Duration,
+    pub retry_policy: RetryPolicy,
+}
+
+impl From<&ArgMatches> for WriterConfig {
+    fn from(args: &ArgMatches) -> Self {
+        Self {
+            buffer: *args.get_one::<u32>("database-buffer").expect("required") as usize,
+            batch_size: *args
+                .get_one::<u32>("database-batch-size")
+                .expect("required") as usize,
+            flush_interval: Duration::from_millis(
+                *args
+                    .get_one::<u64>("database-flush-interval-ms")
+                    .expect("required"),
+            ),
+            retry_policy: RetryPolicy::from(args),
+        }
+    }
+}
+
+/// Handle queueing rows for the background writer.
+///
+/// Queueing never waits: experiments are not held up by a slow or unavailable database, rows
+/// that do not fit in the buffer are dropped and counted instead. The writer stops once every
+/// handle has been dropped and the buffer has been written.
+#[derive(Clone)]
+pub struct Writer {
+    tx: mpsc::Sender<Row>,
+    metrics: Metrics,
+}
+
+impl Writer {
+    pub fn spawn(
+        pool: Pool<Postgres>,
+        metrics: Metrics,
+        config: WriterConfig,
+    ) -> (Self, JoinHandle<()>) {
+        let (tx, rx) = mpsc::channel(config.buffer);
+        let handle = tokio::spawn(write_batches(rx, pool, metrics.clone(), config));
+        (Self { tx, metrics }, handle)
+    }
+
+    pub fn write(&self, row: Row) {
+        match self.tx.try_send(row) {
+            Ok(()) => {
+                self.metrics.database_queue_gauge.inc();
+            }
+            Err(TrySendError::Full(row)) | Err(TrySendError::Closed(row)) => {
+                warn!(
+                    table = row.table(),
+                    experiment_id = row.experiment_id(),
+                    "Database buffer full, dropping row"
+                );
+                count(&self.metrics, &[row], RowOutcome::Dropped);
+            }
+        }
+    }
+}
+
+fn count(metrics: &Metrics, rows: &[Row], outcome: RowOutcome) {
+    let mut tables: HashMap<&str, u64> = HashMap::new();
+    for row in rows {
+        *tables.entry(row.table()).or_default() += 1;
+    }
+    for (table, rows) in tables {
+        metrics
+            .database_row_count
+            .get_or_create(&DatabaseRowLabels {
+                table: table.to_string(),
+                outcome: outcome.clone(),
+            })
+            .inc_by(rows);
+    }
+}
+
+async fn write_batches(
+    mut rx: mpsc::Receiver<Row>,
+    pool: Pool<Postgres>,
+    metrics: Metrics,
+    config: WriterConfig,
+) {
+    let mut batch = Vec::with_capacity(config.batch_size);
+    while let Some(row) = rx.recv().await {
+        batch.push(row);
+        let deadline = Instant::now() + config.flush_interval;
+        while batch.len() < config.batch_size {
+            match time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(row)) => batch.push(row),
+                Ok(None) | Err(_) => break,
+            }
+        }
+        metrics.database_queue_gauge.dec_by(batch.len() as i64);
+        write_batch(&pool, &metrics, &config.retry_policy, &batch).await;
+        batch.clear();
+    }
+}
+
+async fn write_batch(
+    pool: &Pool<Postgres>,
+    metrics: &Metrics,
+    retry_policy: &RetryPolicy,
+    batch: &[Row],
+) {
+    let error = match write_rows(pool, metrics, retry_policy, batch).await {
+        Ok(()) => {
+            count(metrics, batch, RowOutcome::Written);
+            return;
+        }
+        Err(error) => error,
+    };
+    if batch.len() == 1 || is_transient(&error) {
+        drop_rows(metrics, batch, &error);
+        return;
+    }
+
+    // A row the database rejects fails its whole batch, write the rows one at a time so that
+    // only the bad ones are lost. Rows are queued in order, each row after those it refers to.
+    warn!(
+        error = error.to_string(),
+        rows = batch.len(),
+        "Failed to write a batch to the database, writing its rows one at a time"
+    );
+    for row in batch {
+        let row = std::slice::from_ref(row);
+        match write_rows(pool, metrics, retry_policy, row).await {
+            Ok(()) => count(metrics, row, RowOutcome::Written),
+            Err(error) => drop_rows(metrics, row, &error),
+        }
+    }
+}
+
+/// Writes `rows` in one transaction, retrying on transient errors.
+async fn write_rows(
+    pool: &Pool<Postgres>,
+    metrics: &Metrics,
+    retry_policy: &RetryPolicy,
+    rows: &[Row],
+) -> Result<(), sqlx::Error> {
+    let mut retry = 0;
+    loop {
+        // Every write of a batch can be repeated, a failed batch is retried as a whole
+        let error = match database::insert_rows(pool, rows).await {
+            Ok(()) => return Ok(()),
+            Err(error) => error,
+        };
+        if retry >= retry_policy.max_retries || !is_transient(&error) {
+            return Err(error);
+        }
+        let backoff = retry_policy.backoff(retry);
+        warn!(
+            error = error.to_string(),
+            rows = rows.len(),
+            retry,
+            backoff = backoff.as_millis(),
+            "Failed to write to the database, retrying"
+        );
+        metrics.database_retry_count.inc();
+        time::sleep(backoff).await;
+        retry += 1;
+    }
+}
+
+/// Whether writing again may succeed: the database could not be reached or the transaction
+/// conflicted with another one.
+fn is_transient(error: &sqlx::Error) -> bool {
+    match error {
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::WorkerCrashed => true,
+        // serialization_failure, deadlock_detected and connection exceptions
+        sqlx::Error::Database(error) => error
+            .code()
+            .is_some_and(|code| code == "40001" || code == "40P01" || code.starts_with("08")),
+        _ => false,
+    }
+}
+
+fn drop_rows(metrics: &Metrics, rows: &[Row], error: &sqlx::Error) {
+    error!(
+        error = error.to_string(),
+        rows = rows.len(),
+        "Failed to write to the database, dropping rows"
+    );
+    for row in rows {
+        error!(
+            table = row.table(),
+            experiment_id = row.experiment_id(),
+            "Database row lost"
+        );
+    }
+    count(metrics, rows, RowOutcome::Failed);
+}
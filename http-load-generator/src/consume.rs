@@ -1,34 +1,25 @@
-use apache_avro::{from_value, Reader};
 use clap::ArgMatches;
-use rdkafka::{
-    client::ClientContext,
-    config::ClientConfig,
-    consumer::stream_consumer::StreamConsumer,
-    consumer::{CommitMode, Consumer, ConsumerContext, Rebalance},
-    message::Message,
+use event_schema::Encoding;
+use event_types::ExperimentDocument as ExperimentDocumentEvent;
+use experiment_model::ExperimentDocument;
+use kafka_client::{
+    ConsumerConfig, Dispatcher, EventConsumer, KafkaConfig, LogRebalance, PoisonPolicy,
 };
 use tokio::{
     sync::mpsc::Sender,
     time::{self, Duration},
 };
 
-use crate::experiment::{ExperimentDocument, ExperimentDocumentData};
-
-struct CustomContext;
-
-impl ClientContext for CustomContext {}
-
-impl ConsumerContext for CustomContext {
-    fn post_rebalance(&self, rebalance: &Rebalance) {
-        println!("Post rebalance {:?}", rebalance);
-    }
-}
 
 pub struct ConsumeConfiguration {
     wait_before_tx: u8,
     group_id: String,
     brokers: String,
     topic: String,
+    encoding: Encoding,
+    schema_registry: Option<String>,
+    kafka_config: KafkaConfig,
+    poison_policy: PoisonPolicy,
 }
 
 impl From<&mut ArgMatches> for ConsumeConfiguration {
@@ -39,74 +30,69 @@ impl From<&mut ArgMatches> for ConsumeConfiguration {
         let wait_before_tx = args
             .remove_one::<u8>("consumer-wait-before-send")
             .expect("Required");
+        let encoding = args
+            .remove_one::<String>("encoding")
+            .expect("Required")
+            .parse()
+            .expect("Validated by clap");
+        let schema_registry = args.remove_one::<String>("schema-registry");
+        let kafka_config = KafkaConfig::from_args(args)
+            .unwrap_or_else(|e| panic!("Invalid Kafka configuration: {}", e));
+        let poison_policy = PoisonPolicy::from_args(args);
 
         ConsumeConfiguration {
             group_id,
             brokers,
             topic,
             wait_before_tx,
+            encoding,
+            schema_registry,
+            kafka_config,
+            poison_policy,
         }
     }
 }
 
 pub struct Consume {
-    config: ConsumeConfiguration,
-    consumer: StreamConsumer<CustomContext>,
+    wait_before_tx: u8,
+    consumer: EventConsumer,
 }
 
 impl Consume {
     pub fn new(config: ConsumeConfiguration) -> Self {
-        let context = CustomContext;
-        let consumer: StreamConsumer<CustomContext> = ClientConfig::new()
-            .set("group.id", config.group_id.as_str())
-            .set("bootstrap.servers", config.brokers.as_str())
-            .set("enable.partition.eof", "false")
-            .set("session.timeout.ms", "6000")
-            .set("enable.auto.commit", "true")
-            .set("security.protocol", "SSL")
-            .set("ssl.ca.location", "auth/ca.crt")
-            .set("ssl.keystore.location", "auth/kafka.keystore.pkcs12")
-            .set("ssl.keystore.password", "cc2023")
-            .create_with_context(context)
-            .expect("Consumer creation failed");
-        Self { config, consumer }
+        let consumer = EventConsumer::new(
+            ConsumerConfig {
+                brokers: config.brokers,
+                group_id: config.group_id,
+                topics: vec![config.topic],
+                encoding: config.encoding,
+                schema_registry: config.schema_registry,
+                kafka_config: config.kafka_config,
+                poison_policy: config.poison_policy,
+            },
+            LogRebalance,
+        )
+        .expect("Consumer creation failed");
+        Self {
+            wait_before_tx: config.wait_before_tx,
+            consumer,
+        }
     }
 
     pub async fn start(&self, tx: Sender<ExperimentDocument>) {
-        self.consumer
-            .subscribe(&[self.config.topic.as_str()])
-            .expect("Can't subscribe to specified topics");
-        self.read_loop(tx).await;
-    }
-
-    async fn read_loop(&self, tx: Sender<ExperimentDocument>) {
-        loop {
-            match self.consumer.recv().await {
-                Err(e) => println!("Kafka error: {}", e),
-                Ok(b) => {
-                    let m = b.detach();
-                    let reader = Reader::new(m.payload().unwrap()).unwrap();
-                    for value in reader {
-                        let mut experiment_document: ExperimentDocument =
-                            from_value::<ExperimentDocumentData>(&value.unwrap())
-                                .expect("Received invalid event")
-                                .into();
-                        experiment_document
-                            .measurements
-                            .sort_by(|a, b| a.partial_cmp(b).unwrap());
-                        let tx = tx.clone();
-                        let wait_before_tx = self.config.wait_before_tx as u64;
-                        tokio::spawn(async move {
-                            time::sleep(Duration::from_millis(wait_before_tx * 1000)).await;
-                            println!("experiment: {}", experiment_document.experiment);
-                            tx.send(experiment_document)
-                                .await
-                                .expect("Receiver available");
-                        });
-                    }
-                    self.consumer.commit_message(&b, CommitMode::Async).unwrap();
-                }
-            };
-        }
+        let wait_before_tx = self.wait_before_tx as u64;
+        let dispatcher = Dispatcher::new().on(move |event: ExperimentDocumentEvent| {
+            let experiment_document = ExperimentDocument::from(event);
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                time::sleep(Duration::from_millis(wait_before_tx * 1000)).await;
+                println!("experiment: {}", experiment_document.experiment);
+                tx.send(experiment_document)
+                    .await
+                    .expect("Receiver available");
+            });
+            async { Ok(()) }
+        });
+        self.consumer.run(dispatcher).await;
     }
 }
@@ -1,10 +1,9 @@
 use async_broadcast::Sender;
+use experiment_model::ExperimentDocument;
 use rand::Rng;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
-use crate::experiment::ExperimentDocument;
-
 #[derive(Debug, Clone)]
 pub enum APIQuery {
     OutOfBounds {
@@ -18,7 +17,7 @@ pub enum APIQuery {
 }
 
 async fn generate_temperature_query(experiment: Arc<RwLock<ExperimentDocument>>) -> APIQuery {
-    let num_experiments = experiment.read().await.measurements.len();
+    let num_experiments = experiment.read().await.measurements().len();
     let mut idxs = {
         let mut rng = rand::thread_rng();
         vec![
@@ -28,9 +27,9 @@ async fn generate_temperature_query(experiment: Arc<RwLock<ExperimentDocument>>)
     };
     idxs.sort();
     let start_time =
-        (experiment.read().await.measurements[idxs[0]].timestamp * 1000.0).floor() / 1000.0;
+        (experiment.read().await.measurements()[idxs[0]].timestamp * 1000.0).floor() / 1000.0;
     let end_time =
-        (experiment.read().await.measurements[idxs[1]].timestamp * 1000.0).ceil() / 1000.0;
+        (experiment.read().await.measurements()[idxs[1]].timestamp * 1000.0).ceil() / 1000.0;
 
     APIQuery::Temperature {
         experiment,
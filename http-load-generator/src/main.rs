@@ -1,10 +1,11 @@
 use clap::{command, value_parser, Arg, ArgAction};
+use event_schema::Encoding;
+use kafka_client::{KafkaConfig, PoisonPolicy};
 use futures::future;
 use std::process;
 use tokio::sync::mpsc;
 
 mod consume;
-mod experiment;
 mod generator;
 mod metric;
 mod receiver;
@@ -42,6 +43,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .long("topic")
             .default_value("experiment")
             .action(ArgAction::Set)
+            .help("Topic of the experiment documents, either `--topic-document` or `--topic-document-snapshot` of the experiment-producer. Snapshots of live experiments replace the previous ones")
         )
         .arg(Arg::new("group-id")
             .required(true)
@@ -54,8 +56,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .action(ArgAction::Set)
             .default_value("60")
             .value_parser(value_parser!(u8))
-            .help("Time the consumer should wait before forwarding the experiment to the receiver")
+            .help("Time the consumer should wait before forwarding the experiment to the receiver, the window within which the hosts should have caught up with a document snapshot")
         )
+        .arg(Arg::new("encoding")
+            .required(false)
+            .long("encoding")
+            .action(ArgAction::Set)
+            .default_value("container")
+            .value_parser(Encoding::VALUES)
+            .help("Wire encoding of the Avro events, must match the `--encoding` of the experiment-producer")
+        )
+        .arg(Arg::new("schema-registry")
+            .required(false)
+            .long("schema-registry")
+            .action(ArgAction::Set)
+            .help("URL of the schema registry resolving the writer schema of `--encoding confluent` events")
+        )
+        .args(KafkaConfig::args())
+        .args(PoisonPolicy::args())
         .arg(Arg::new("hosts-file")
             .required(true)
             .long("hosts-file")
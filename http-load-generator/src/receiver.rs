@@ -1,14 +1,14 @@
 use async_broadcast::broadcast;
 use clap::ArgMatches;
+use experiment_model::ExperimentDocument;
 use futures::future;
 use rand::Rng;
-use std::{fs, sync::Arc};
+use std::{collections::HashMap, fs, sync::Arc};
 use tokio::{
     sync::{mpsc::Receiver, RwLock},
     time::{self, Duration},
 };
 
-use crate::experiment::ExperimentDocument;
 use crate::generator::{self, APIQuery};
 use crate::metric::{MetricServer, Metrics};
 use crate::request::{Host, Requestor, RequestorConfiguration};
@@ -67,6 +67,8 @@ pub struct ExperimentReceiver {
     config: ExperimentReceiverConfig,
     experiment_rx: Receiver<ExperimentDocument>,
     experiments: Arc<RwLock<Vec<Arc<RwLock<ExperimentDocument>>>>>,
+    /// Experiments by ID, to replace the snapshot of a live experiment by a newer one.
+    index: HashMap<String, Arc<RwLock<ExperimentDocument>>>,
 }
 
 impl ExperimentReceiver {
@@ -78,15 +80,37 @@ impl ExperimentReceiver {
             config,
             experiment_rx,
             experiments: Arc::new(RwLock::new(vec![])),
+            index: HashMap::new(),
         }
     }
 
     async fn add_first_experiment(&mut self) {
         let experiment = self.experiment_rx.recv().await.expect("Sender available");
-        self.experiments
-            .write()
-            .await
-            .push(Arc::new(RwLock::new(experiment)));
+        let mut experiments = self.experiments.write().await;
+        Self::upsert_experiment(&mut self.index, &mut experiments, experiment).await;
+    }
+
+    /// Adds a new experiment, or replaces the previous snapshot of a live experiment.
+    async fn upsert_experiment(
+        index: &mut HashMap<String, Arc<RwLock<ExperimentDocument>>>,
+        experiments: &mut Vec<Arc<RwLock<ExperimentDocument>>>,
+        experiment: ExperimentDocument,
+    ) {
+        match index.get(&experiment.experiment) {
+            Some(existing) => {
+                let mut existing = existing.write().await;
+                // Snapshots are forwarded concurrently, an older one may arrive last
+                if experiment.measurements().len() >= existing.measurements().len() {
+                    *existing = experiment;
+                }
+            }
+            None => {
+                let experiment_id = experiment.experiment.clone();
+                let experiment = Arc::new(RwLock::new(experiment));
+                index.insert(experiment_id, experiment.clone());
+                experiments.push(experiment);
+            }
+        }
     }
 
     /// Spawn 1 thread per group
@@ -154,7 +178,7 @@ impl ExperimentReceiver {
     async fn receive_experiments(&mut self) {
         let mut experiments = self.experiments.write().await;
         while let Ok(experiment) = self.experiment_rx.try_recv() {
-            experiments.push(Arc::new(RwLock::new(experiment)));
+            Self::upsert_experiment(&mut self.index, &mut experiments, experiment).await;
         }
     }
 }
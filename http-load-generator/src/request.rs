@@ -1,5 +1,6 @@
 use async_broadcast::Receiver;
 use clap::ArgMatches;
+use experiment_model::{ExperimentDocument, Measurement};
 use futures::{stream, StreamExt};
 use reqwest::{Client, Error, RequestBuilder, Response};
 use serde::Deserialize;
@@ -13,10 +14,7 @@ use tokio::{
 };
 
 use crate::metric::Metrics;
-use crate::{
-    experiment::{ExperimentDocument, Measurement},
-    metric::{ResponseCountLabels, ResponseType},
-};
+use crate::metric::{ResponseCountLabels, ResponseType};
 use crate::{generator::APIQuery, metric::RequestRateLabels};
 
 #[derive(Debug)]
@@ -131,14 +129,12 @@ impl Requestor {
                 return Err(ResponseError::DeserializationError);
             }
         };
-        measurements.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        measurements.sort_by(|a, b| a.timestamp.total_cmp(&b.timestamp));
 
         let experiment_read = experiment.read().await;
-        let measurements_cmp = experiment_read
-            .get_measurements_slice(start_time, end_time)
-            .expect("Valid start and end times");
+        let measurements_cmp = experiment_read.range(start_time..=end_time);
 
-        if measurements_cmp != measurements {
+        if !experiment_model::approx_eq(measurements_cmp, &measurements) {
             println!(
                 "Group: {}\nQuery: {:?}\nGround truth: {}\nReceived: {}",
                 self.host.host_name,
@@ -209,19 +205,13 @@ impl Requestor {
                 return Err(ResponseError::DeserializationError);
             }
         };
-        measurements.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        measurements.sort_by(|a, b| a.timestamp.total_cmp(&b.timestamp));
 
-        let is_none = experiment.read().await.get_cached_out_of_bounds().is_none();
-        if is_none {
-            let mut experiment = experiment.write().await;
-            if experiment.get_cached_out_of_bounds().is_none() {
-                let measurements = experiment.compute_out_of_bounds();
-                experiment.set_out_of_bounds(measurements);
-            }
-        }
         let experiment_read = experiment.read().await;
-        let measurements_cmp = experiment_read.get_cached_out_of_bounds().unwrap();
-        if measurements_cmp != measurements {
+        // Live experiments keep measuring after the snapshot was taken
+        measurements.retain(|measurement| experiment_read.covers(measurement));
+        let measurements_cmp = experiment_read.out_of_range();
+        if !experiment_model::approx_eq(measurements_cmp, &measurements) {
             println!(
                 "Group: {}\nQuery: {:?}\nGround truth: {}\nReceived: {}",
                 self.host.host_name,
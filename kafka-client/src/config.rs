@@ -0,0 +1,189 @@
+use clap::{Arg, ArgAction, ArgMatches};
+use rdkafka::config::ClientConfig;
+use std::{collections::BTreeMap, env, fs, io};
+
+/// Prefix of the environment variables setting a property: `KAFKA_SASL_USERNAME` sets
+/// `sasl.username`.
+pub const ENV_PREFIX: &str = "KAFKA_";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    /// Line of the properties file, or `--kafka-property`, that is not a `key=value` pair.
+    Malformed(String),
+}
+
+impl std::error::Error for ConfigError {}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// librdkafka properties set by the operator, from a properties file, environment variables and
+/// the command line, in increasing order of precedence. They override the settings of the
+/// clients themselves.
+///
+/// Unless `security.protocol` is among them, clients connect over SSL with the CA and keystore
+/// of their `auth` directory.
+#[derive(Clone, Debug, Default)]
+pub struct KafkaConfig {
+    properties: BTreeMap<String, String>,
+}
+
+impl KafkaConfig {
+    /// `--kafka-config` and `--kafka-property`, read by [`KafkaConfig::from_args`].
+    pub fn args() -> [Arg; 2] {
+        [
+            Arg::new("kafka-config")
+                .required(false)
+                .long("kafka-config")
+                .action(ArgAction::Set)
+                .help("File of librdkafka properties, one `key=value` per line, e.g. `security.protocol=SASL_SSL`. Environment variables prefixed with `KAFKA_` override them: `KAFKA_SASL_PASSWORD` sets `sasl.password`"),
+            Arg::new("kafka-property")
+                .required(false)
+                .short('X')
+                .long("kafka-property")
+                .action(ArgAction::Append)
+                .help("librdkafka property `key=value`, overriding `--kafka-config` and the environment. Can be repeated"),
+        ]
+    }
+
+    pub fn from_args(args: &ArgMatches) -> Result<Self, ConfigError> {
+        let mut config = Self::default();
+        if let Some(path) = args.get_one::<String>("kafka-config") {
+            let content = fs::read_to_string(path).map_err(ConfigError::Io)?;
+            config.properties.extend(parse_properties(&content)?);
+        }
+        config.properties.extend(from_env(env::vars()));
+        for property in args
+            .get_many::<String>("kafka-property")
+            .into_iter()
+            .flatten()
+        {
+            let (key, value) = parse_property(property)?;
+            config.properties.insert(key, value);
+        }
+        Ok(config)
+    }
+
+    /// Sets `key` unless it has been configured.
+    pub fn set_default(&mut self, key: &str, value: &str) {
+        self.properties
+            .entry(key.to_string())
+            .or_insert_with(|| value.to_string());
+    }
+
+    /// Sets the properties on `client_config`, falling back to SSL with the certificates of
+    /// `auth_dir`.
+    pub fn apply(&self, client_config: &mut ClientConfig, auth_dir: &str) {
+        for (key, value) in self.properties(auth_dir) {
+            client_config.set(key, value);
+        }
+    }
+
+    fn properties(&self, auth_dir: &str) -> BTreeMap<String, String> {
+        let mut properties = BTreeMap::new();
+        if !self.properties.contains_key("security.protocol") {
+            properties.insert("security.protocol".into(), "SSL".into());
+            properties.insert("ssl.ca.location".into(), format!("{}/ca.crt", auth_dir));
+            properties.insert(
+                "ssl.keystore.location".into(),
+                format!("{}/kafka.keystore.pkcs12", auth_dir),
+            );
+            properties.insert("ssl.keystore.password".into(), "cc2023".into());
+        }
+        properties.extend(self.properties.clone());
+        properties
+    }
+}
+
+fn parse_property(property: &str) -> Result<(String, String), ConfigError> {
+    match property.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err(ConfigError::Malformed(property.to_string())),
+    }
+}
+
+/// Parses a Java style properties file, as read by `kcat -F`. Blank lines and lines starting
+/// with `#` are skipped.
+fn parse_properties(content: &str) -> Result<Vec<(String, String)>, ConfigError> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(parse_property)
+        .collect()
+}
+
+fn from_env(vars: impl IntoIterator<Item = (String, String)>) -> Vec<(String, String)> {
+    vars.into_iter()
+        .filter_map(|(name, value)| {
+            let key = name.strip_prefix(ENV_PREFIX)?;
+            Some((key.to_lowercase().replace('_', "."), value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_properties_file() {
+        let content = "
+            # Local broker
+            security.protocol=SASL_PLAINTEXT
+            sasl.mechanisms = SCRAM-SHA-256
+
+            sasl.password=p=ss
+        ";
+        assert_eq!(
+            parse_properties(content).unwrap(),
+            [
+                ("security.protocol".into(), "SASL_PLAINTEXT".into()),
+                ("sasl.mechanisms".into(), "SCRAM-SHA-256".into()),
+                ("sasl.password".into(), "p=ss".into()),
+            ]
+        );
+        assert!(matches!(
+            parse_properties("security.protocol"),
+            Err(ConfigError::Malformed(_))
+        ));
+        assert!(matches!(
+            parse_property("=SSL"),
+            Err(ConfigError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn read_prefixed_environment_variables() {
+        let vars = [
+            ("KAFKA_SASL_USERNAME".into(), "group0".into()),
+            ("BROKERS".into(), "localhost:9092".into()),
+        ];
+        assert_eq!(
+            from_env(vars),
+            [("sasl.username".into(), "group0".into())]
+        );
+    }
+
+    #[test]
+    fn fall_back_to_ssl_unless_configured() {
+        let mut config = KafkaConfig::default();
+        config.set_default("ssl.keystore.password", "secret");
+        let properties = config.properties("auth");
+        assert_eq!(properties["security.protocol"], "SSL");
+        assert_eq!(properties["ssl.ca.location"], "auth/ca.crt");
+        assert_eq!(properties["ssl.keystore.password"], "secret");
+
+        config.set_default("security.protocol", "PLAINTEXT");
+        config.set_default("security.protocol", "SSL");
+        let properties = config.properties("auth");
+        assert_eq!(properties["security.protocol"], "PLAINTEXT");
+        assert!(!properties.contains_key("ssl.ca.location"));
+    }
+}
@@ -0,0 +1,425 @@
+use apache_avro::types::Value;
+use clap::{Arg, ArgAction, ArgMatches};
+use event_schema::{
+    DecodeError, Encoding, EventHeaders, RecordSchemas, SchemaRegistry, LATEST_VERSION,
+};
+use event_types::Event;
+use rdkafka::{
+    client::ClientContext,
+    config::ClientConfig,
+    consumer::{stream_consumer::StreamConsumer, Consumer, ConsumerContext, Rebalance},
+    error::KafkaError,
+    message::{BorrowedMessage, Headers, Message, OwnedHeaders},
+    producer::{FutureProducer, FutureRecord},
+};
+use std::{collections::HashMap, future::Future, pin::Pin, time::Duration};
+
+use crate::KafkaConfig;
+
+/// Header of a dead-lettered message, describing why it could not be handled.
+pub const DEAD_LETTER_ERROR_HEADER: &str = "dead_letter_error";
+
+/// Upper bound on dead-lettering a message.
+const DEAD_LETTER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Error of a handler rejecting an event, the message is then handled by the [`PoisonPolicy`].
+pub type HandlerError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Why a message could not be handled.
+#[derive(Debug)]
+pub enum PoisonError {
+    MissingPayload,
+    Decode(DecodeError),
+    Deserialize(apache_avro::Error),
+    Handler(HandlerError),
+}
+
+impl std::error::Error for PoisonError {}
+
+impl std::fmt::Display for PoisonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// What the consumer does with a message it cannot handle. The consumer moves past the message
+/// in every case, a poison message never blocks its partition.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PoisonPolicy {
+    Skip,
+    Log,
+    /// Forwards the message, with its key and headers, to the topic.
+    DeadLetter(String),
+}
+
+impl PoisonPolicy {
+    /// Accepted values of the `--poison-policy` command line argument.
+    pub const VALUES: [&'static str; 3] = ["skip", "log", "dead-letter"];
+
+    /// `--poison-policy` and `--dead-letter-topic`, read by [`PoisonPolicy::from_args`].
+    pub fn args() -> [Arg; 2] {
+        [
+            Arg::new("poison-policy")
+                .required(false)
+                .long("poison-policy")
+                .action(ArgAction::Set)
+                .default_value("log")
+                .value_parser(Self::VALUES)
+                .help("What to do with a message that cannot be handled: a missing payload, an event that does not decode or is rejected"),
+            Arg::new("dead-letter-topic")
+                .required_if_eq("poison-policy", "dead-letter")
+                .long("dead-letter-topic")
+                .action(ArgAction::Set)
+                .help("Topic the messages that cannot be handled are forwarded to with `--poison-policy dead-letter`"),
+        ]
+    }
+
+    pub fn from_args(args: &ArgMatches) -> Self {
+        match args
+            .get_one::<String>("poison-policy")
+            .expect("Required")
+            .as_str()
+        {
+            "skip" => PoisonPolicy::Skip,
+            "log" => PoisonPolicy::Log,
+            "dead-letter" => PoisonPolicy::DeadLetter(
+                args.get_one::<String>("dead-letter-topic")
+                    .expect("Required with `--poison-policy dead-letter`")
+                    .clone(),
+            ),
+            _ => unreachable!("Validated by clap"),
+        }
+    }
+}
+
+/// Callbacks on the partitions assigned to the consumer. They run on the consumer thread and
+/// should return quickly.
+pub trait RebalanceListener: Send + Sync + 'static {
+    /// Called once `(topic, partition)`s have been assigned.
+    fn assigned(&self, _partitions: &[(String, i32)]) {}
+
+    /// Called before every partition is revoked. The offsets of the messages handled so far are
+    /// committed with the revocation.
+    fn revoked(&self) {}
+}
+
+/// Prints the rebalances.
+pub struct LogRebalance;
+
+impl RebalanceListener for LogRebalance {
+    fn assigned(&self, partitions: &[(String, i32)]) {
+        println!("Assigned {:?}", partitions);
+    }
+
+    fn revoked(&self) {
+        println!("Partitions revoked");
+    }
+}
+
+struct EventContext<L> {
+    listener: L,
+}
+
+impl<L: RebalanceListener> ClientContext for EventContext<L> {}
+
+impl<L: RebalanceListener> ConsumerContext for EventContext<L> {
+    fn pre_rebalance(&self, rebalance: &Rebalance) {
+        if let Rebalance::Revoke = rebalance {
+            self.listener.revoked();
+        }
+    }
+
+    fn post_rebalance(&self, rebalance: &Rebalance) {
+        match rebalance {
+            Rebalance::Assign(assignment) => {
+                let partitions: Vec<_> = assignment
+                    .elements()
+                    .iter()
+                    .map(|element| (element.topic().to_string(), element.partition()))
+                    .collect();
+                self.listener.assigned(&partitions);
+            }
+            Rebalance::Revoke => {}
+            Rebalance::Error(e) => println!("Rebalance error: {}", e),
+        }
+    }
+}
+
+type HandlerFuture = Pin<Box<dyn Future<Output = Result<(), HandlerError>> + Send>>;
+
+type Handle = dyn FnMut(Value, &str, u32) -> Result<HandlerFuture, apache_avro::Error> + Send;
+
+struct Route {
+    schemas: RecordSchemas,
+    handle: Box<Handle>,
+}
+
+/// Event along with the message carrying it.
+#[derive(Clone, Debug)]
+pub struct Record<E> {
+    pub event: E,
+    pub topic: String,
+    /// Schema version the event was encoded with, from the `schema_version` header.
+    pub schema_version: u32,
+}
+
+/// Handlers of the events of a consumer, by the record name header of the messages.
+#[derive(Default)]
+pub struct Dispatcher {
+    routes: HashMap<&'static str, Route>,
+}
+
+impl Dispatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handles the `E` events with `handler`. Events of every schema version are resolved into
+    /// the latest one.
+    pub fn on<E, F, Fut>(self, mut handler: F) -> Self
+    where
+        E: Event + Send + 'static,
+        F: FnMut(E) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), HandlerError>> + Send + 'static,
+    {
+        self.on_record(move |record: Record<E>| handler(record.event))
+    }
+
+    /// Handles the `E` events with `handler`, along with the topic and schema version of their
+    /// message.
+    pub fn on_record<E, F, Fut>(mut self, mut handler: F) -> Self
+    where
+        E: Event + Send + 'static,
+        F: FnMut(Record<E>) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), HandlerError>> + Send + 'static,
+    {
+        let route = Route {
+            schemas: RecordSchemas::new(E::RECORD_NAME, LATEST_VERSION),
+            handle: Box::new(move |value, topic, schema_version| {
+                let record = Record {
+                    event: E::from_value(&value)?,
+                    topic: topic.to_string(),
+                    schema_version,
+                };
+                Ok(Box::pin(handler(record)) as HandlerFuture)
+            }),
+        };
+        self.routes.insert(E::RECORD_NAME, route);
+        self
+    }
+
+    /// Messages of records without a handler are skipped. Messages without a record name,
+    /// published before the header existed, go to the only handler if there is one.
+    fn route(&mut self, headers: &EventHeaders) -> Option<&mut Route> {
+        match headers.record_name.as_deref() {
+            Some(record_name) => self.routes.get_mut(record_name),
+            None if self.routes.len() == 1 => self.routes.values_mut().next(),
+            None => None,
+        }
+    }
+}
+
+pub struct ConsumerConfig {
+    pub brokers: String,
+    pub group_id: String,
+    pub topics: Vec<String>,
+    pub encoding: Encoding,
+    pub schema_registry: Option<String>,
+    pub kafka_config: KafkaConfig,
+    pub poison_policy: PoisonPolicy,
+}
+
+/// Consumer of experiment-producer events.
+///
+/// The offset of a message is committed once it has been handled, a message is redelivered
+/// after a crash or rebalance rather than lost.
+pub struct EventConsumer<L: RebalanceListener = LogRebalance> {
+    consumer: StreamConsumer<EventContext<L>>,
+    encoding: Encoding,
+    registry: Option<SchemaRegistry>,
+    poison_policy: PoisonPolicy,
+    dead_letter: Option<FutureProducer>,
+}
+
+impl<L: RebalanceListener> EventConsumer<L> {
+    /// Creates the consumer and subscribes to the topics.
+    pub fn new(config: ConsumerConfig, listener: L) -> Result<Self, KafkaError> {
+        let mut client_config = ClientConfig::new();
+        client_config
+            .set("group.id", config.group_id.as_str())
+            .set("bootstrap.servers", config.brokers.as_str())
+            .set("enable.partition.eof", "false")
+            // Skip the events of aborted transactions of the experiment-producer
+            .set("isolation.level", "read_committed")
+            .set("session.timeout.ms", "6000")
+            // Offsets are stored once their message has been handled, and committed in the
+            // background
+            .set("enable.auto.commit", "true")
+            .set("enable.auto.offset.store", "false");
+        config.kafka_config.apply(&mut client_config, "auth");
+        let consumer: StreamConsumer<EventContext<L>> =
+            client_config.create_with_context(EventContext { listener })?;
+        let topics: Vec<&str> = config.topics.iter().map(String::as_str).collect();
+        consumer.subscribe(&topics)?;
+
+        let dead_letter = match config.poison_policy {
+            PoisonPolicy::DeadLetter(_) => {
+                let mut client_config = ClientConfig::new();
+                client_config.set("bootstrap.servers", config.brokers.as_str());
+                config.kafka_config.apply(&mut client_config, "auth");
+                Some(client_config.create()?)
+            }
+            PoisonPolicy::Skip | PoisonPolicy::Log => None,
+        };
+
+        Ok(Self {
+            consumer,
+            encoding: config.encoding,
+            registry: config.schema_registry.as_deref().map(SchemaRegistry::new),
+            poison_policy: config.poison_policy,
+            dead_letter,
+        })
+    }
+
+    /// Handles the messages one at a time with `dispatcher`, forever.
+    pub async fn run(&self, mut dispatcher: Dispatcher) {
+        loop {
+            let message = match self.consumer.recv().await {
+                Ok(message) => message,
+                Err(e) => {
+                    println!("Kafka error: {}", e);
+                    continue;
+                }
+            };
+            if let Err(e) = self.handle(&message, &mut dispatcher).await {
+                self.poison(&message, e).await;
+            }
+            if let Err(e) = self.consumer.store_offset(&message) {
+                println!("Failed to store offset of {}: {}", location(&message), e);
+            }
+        }
+    }
+
+    async fn handle(
+        &self,
+        message: &BorrowedMessage<'_>,
+        dispatcher: &mut Dispatcher,
+    ) -> Result<(), PoisonError> {
+        let headers = EventHeaders::parse(message.headers().into_iter().flat_map(|headers| {
+            (0..headers.count()).filter_map(|idx| headers.get(idx))
+        }))
+        .map_err(PoisonError::Decode)?;
+        let route = match dispatcher.route(&headers) {
+            Some(route) => route,
+            None => return Ok(()),
+        };
+        let payload = message.payload().ok_or(PoisonError::MissingPayload)?;
+        let values = route
+            .schemas
+            .decode(payload, &headers, self.encoding, self.registry.as_ref())
+            .await
+            .map_err(PoisonError::Decode)?;
+        // Every record of the message is deserialized before any is handled, a message that
+        // does not deserialize is not partially handled.
+        let handlers = values
+            .into_iter()
+            .map(|value| (route.handle)(value, message.topic(), headers.schema_version))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(PoisonError::Deserialize)?;
+        for handler in handlers {
+            handler.await.map_err(PoisonError::Handler)?;
+        }
+        Ok(())
+    }
+
+    async fn poison(&self, message: &BorrowedMessage<'_>, error: PoisonError) {
+        let topic = match &self.poison_policy {
+            PoisonPolicy::Skip => return,
+            PoisonPolicy::Log => {
+                println!("Skipping {}: {}", location(message), error);
+                return;
+            }
+            PoisonPolicy::DeadLetter(topic) => topic,
+        };
+        println!("Dead-lettering {} to `{}`: {}", location(message), topic, error);
+
+        let mut headers = OwnedHeaders::new();
+        if let Some(original) = message.headers() {
+            for (name, value) in (0..original.count()).filter_map(|idx| original.get(idx)) {
+                headers = headers.add(name, value);
+            }
+        }
+        let headers = headers.add(DEAD_LETTER_ERROR_HEADER, error.to_string().as_str());
+        let mut record = FutureRecord::<[u8], [u8]>::to(topic).headers(headers);
+        if let Some(key) = message.key() {
+            record = record.key(key);
+        }
+        if let Some(payload) = message.payload() {
+            record = record.payload(payload);
+        }
+        let producer = self.dead_letter.as_ref().expect("Created with the policy");
+        if let Err((e, _)) = producer.send(record, DEAD_LETTER_TIMEOUT).await {
+            println!("Failed to dead-letter {}: {}", location(message), e);
+        }
+    }
+}
+
+fn location(message: &BorrowedMessage) -> String {
+    format!(
+        "{}[{}]@{}",
+        message.topic(),
+        message.partition(),
+        message.offset()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Command;
+    use event_schema::RECORD_NAME_HEADER;
+    use event_types::{ExperimentStarted, ExperimentTerminated};
+
+    async fn ignore<E>(_: E) -> Result<(), HandlerError> {
+        Ok(())
+    }
+
+    fn headers(record_name: Option<&str>) -> EventHeaders {
+        EventHeaders::parse(record_name.map(|name| (RECORD_NAME_HEADER, name.as_bytes())))
+            .unwrap()
+    }
+
+    fn poison_policy(args: &[&str]) -> Result<PoisonPolicy, clap::Error> {
+        let matches = Command::new("consumer")
+            .args(PoisonPolicy::args())
+            .try_get_matches_from(std::iter::once("consumer").chain(args.iter().copied()))?;
+        Ok(PoisonPolicy::from_args(&matches))
+    }
+
+    #[test]
+    fn parse_poison_policy() {
+        assert_eq!(poison_policy(&[]).unwrap(), PoisonPolicy::Log);
+        assert_eq!(
+            poison_policy(&["--poison-policy", "skip"]).unwrap(),
+            PoisonPolicy::Skip
+        );
+        assert_eq!(
+            poison_policy(&["--poison-policy", "dead-letter", "--dead-letter-topic", "dlq"])
+                .unwrap(),
+            PoisonPolicy::DeadLetter("dlq".into())
+        );
+        assert!(poison_policy(&["--poison-policy", "dead-letter"]).is_err());
+    }
+
+    #[test]
+    fn route_on_record_name() {
+        let mut dispatcher = Dispatcher::new().on(ignore::<ExperimentStarted>);
+        assert!(dispatcher.route(&headers(Some("experiment_started"))).is_some());
+        assert!(dispatcher.route(&headers(Some("producer_heartbeat"))).is_none());
+        assert!(dispatcher.route(&headers(None)).is_some());
+
+        let mut dispatcher = dispatcher.on(ignore::<ExperimentTerminated>);
+        assert!(dispatcher.route(&headers(Some("experiment_terminated"))).is_some());
+        assert!(dispatcher.route(&headers(None)).is_none());
+    }
+}
@@ -0,0 +1,8 @@
+mod config;
+mod consumer;
+
+pub use config::{ConfigError, KafkaConfig, ENV_PREFIX};
+pub use consumer::{
+    ConsumerConfig, Dispatcher, EventConsumer, HandlerError, LogRebalance, PoisonError,
+    PoisonPolicy, RebalanceListener, Record, DEAD_LETTER_ERROR_HEADER,
+};
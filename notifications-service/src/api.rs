@@ -1,4 +1,4 @@
-use event_hash::{DecryptError, HashData};
+use event_hash::{AssociatedData, DecryptError, HashData, Keyring, Opener};
 use poem::web::Data;
 use poem_openapi::{
     param::Query,
@@ -14,13 +14,11 @@ use std::{
 };
 use tracing::info;
 
-use crate::metric::{Metrics, ResponseCountLabels};
+use crate::metric::{KeyUseLabels, Metrics, ResponseCountLabels};
+use crate::sequence::Sequences;
 use crate::store;
 use crate::{jwt, metric::ResponseType};
 
-#[derive(Debug, Clone)]
-pub struct SecretKey(pub String);
-
 #[derive(Debug, PartialEq, Enum, Serialize, Deserialize)]
 enum BodyNotificationType {
     OutOfRange,
@@ -34,6 +32,11 @@ struct NotifyBody {
     measurement_id: String,
     experiment_id: String,
     cipher_data: String,
+    /// Topic of the sensor event carrying `cipher_data`
+    topic: String,
+    /// Schema version of the sensor event carrying `cipher_data`, as in its `schema_version`
+    /// header
+    schema_version: u32,
 }
 
 impl NotifyBody {
@@ -48,11 +51,13 @@ impl NotifyBody {
                 "Unexpected experiment_id `{}`. Expected `{}`",
                 self.experiment_id, hash_data.experiment_id
             ))));
-        } else if hash_data.researcher != self.researcher {
-            return Err(NotifyErrorResponse::BadRequest(PlainText(format!(
-                "Unexpected researcher `{}`. Expected `{}`",
-                self.researcher, hash_data.researcher
-            ))));
+        } else if let Some(researcher) = &hash_data.researcher {
+            if *researcher != self.researcher {
+                return Err(NotifyErrorResponse::BadRequest(PlainText(format!(
+                    "Unexpected researcher `{}`. Expected `{}`",
+                    self.researcher, researcher
+                ))));
+            }
         }
         if let None = hash_data.notification_type {
             return Err(NotifyErrorResponse::BadRequest(PlainText(format!(
@@ -100,24 +105,52 @@ pub enum NotifyErrorResponse {
 impl From<DecryptError> for NotifyErrorResponse {
     fn from(e: DecryptError) -> Self {
         match e {
+            DecryptError::OversizedHashData => {
+                NotifyErrorResponse::BadRequest(PlainText("Cipher text too long".into()))
+            }
             DecryptError::MalformedHashDataString => {
                 NotifyErrorResponse::BadRequest(PlainText("Invalid cipher".into()))
             }
             DecryptError::MalformedB64Nonce => {
                 NotifyErrorResponse::BadRequest(PlainText("Malformed b64 encoded nonce".into()))
             }
+            DecryptError::InvalidNonceLength => NotifyErrorResponse::BadRequest(PlainText(
+                "Nonce is not 96 bits long".into(),
+            )),
             DecryptError::MalformedB64Ciphertext => NotifyErrorResponse::BadRequest(PlainText(
                 "Malformed b64 encoded ciphertext".into(),
             )),
+            DecryptError::TruncatedCiphertext => NotifyErrorResponse::BadRequest(PlainText(
+                "Ciphertext too short to hold an authentication tag".into(),
+            )),
+            DecryptError::UnknownKeyId => NotifyErrorResponse::BadRequest(PlainText(
+                "Cipher text encrypted with a key unknown to the server".into(),
+            )),
+            DecryptError::UntrustedSigner => NotifyErrorResponse::BadRequest(PlainText(
+                "Cipher text sealed by a producer unknown to the server".into(),
+            )),
+            DecryptError::InvalidSignature => NotifyErrorResponse::BadRequest(PlainText(
+                "Cipher text not signed by the producer it names".into(),
+            )),
             DecryptError::DecryptionError => NotifyErrorResponse::BadRequest(PlainText(
                 "Cipher text not encrypted with provided nonce and server key".into(),
             )),
+            DecryptError::AssociatedDataMismatch => NotifyErrorResponse::BadRequest(PlainText(
+                "Cipher text not issued for the topic, experiment and schema version of the request"
+                    .into(),
+            )),
+            DecryptError::GroupMismatch => NotifyErrorResponse::BadRequest(PlainText(
+                "Cipher text issued for another group".into(),
+            )),
             DecryptError::Utf8DecodingError => NotifyErrorResponse::InternalServerError(PlainText(
                 "Could not decode into utf8 string".into(),
             )),
             DecryptError::JsonDeserializationError => NotifyErrorResponse::InternalServerError(
                 PlainText("Could not deserialize json string into HashData.".into()),
             ),
+            DecryptError::CompactDecodingError => NotifyErrorResponse::InternalServerError(
+                PlainText("Could not decode compact HashData.".into()),
+            ),
         }
     }
 }
@@ -153,17 +186,18 @@ pub struct Api;
 #[OpenApi]
 impl Api {
     #[oai(path = "/notify", method = "post")]
+    #[allow(clippy::too_many_arguments)]
     async fn notify_post(
         &self,
-        data_secret_key: Data<&SecretKey>,
+        keyring: Data<&Arc<Keyring>>,
+        opener: Data<&Option<Arc<Opener>>>,
         pool: Data<&Option<Pool<Postgres>>>,
         body: Json<NotifyBody>,
         metrics: Data<&Metrics>,
+        sequences: Data<&Sequences>,
         token: Query<Option<String>>,
     ) -> Result<NotifyResponse, NotifyErrorResponse> {
         let pool = pool.0;
-        let secret_key = data_secret_key.0;
-        let key: &[u8] = secret_key.0.as_bytes();
         let body = body.0;
         let metrics = metrics.0;
         let mut subject: Option<String> = None;
@@ -182,7 +216,26 @@ impl Api {
             subject = Some(claims.sub);
         }
 
-        let hash_data = HashData::decrypt(key, &body.cipher_data).map_err(|e| {
+        let associated_data = AssociatedData {
+            topic: &body.topic,
+            experiment_id: &body.experiment_id,
+            schema_version: body.schema_version,
+        };
+        let hash_data = match opener.0 {
+            Some(opener) if event_hash::is_sealed(&body.cipher_data) => HashData::open(
+                opener,
+                subject.as_deref(),
+                associated_data,
+                &body.cipher_data,
+            ),
+            _ => HashData::decrypt(
+                keyring.0,
+                subject.as_deref(),
+                associated_data,
+                &body.cipher_data,
+            ),
+        }
+        .map_err(|e| {
             self.update_counters(
                 metrics,
                 subject.as_ref().map(|subject| subject.as_str()),
@@ -190,6 +243,14 @@ impl Api {
             );
             e
         })?;
+        metrics
+            .key_use_count
+            .get_or_create(&KeyUseLabels {
+                key_id: event_hash::key_id(&body.cipher_data)
+                    .expect("Decrypted")
+                    .into(),
+            })
+            .inc();
         body.validate_body(&hash_data).map_err(|e| {
             self.update_counters(
                 metrics,
@@ -215,11 +276,16 @@ impl Api {
             })?;
         }
 
-        self.update_counters(
-            metrics,
-            subject.as_ref().map(|x| x.as_str()),
-            ResponseType::Ok,
-        );
+        // Hashes from before sequence numbers are not ordered
+        let response_type = match hash_data.sequence {
+            Some(sequence) => ResponseType::from(sequences.accept(
+                subject.as_deref(),
+                &body.experiment_id,
+                sequence,
+            )),
+            None => ResponseType::Ok,
+        };
+        self.update_counters(metrics, subject.as_deref(), response_type);
 
         info!(
             "group: {:?}\tmeasurement_id: {}\tlatency: {}s",
@@ -267,6 +333,15 @@ mod test {
     use serde_json::json;
 
     const SECRET_KEY: &str = "QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh";
+    const ASSOCIATED_DATA: AssociatedData = AssociatedData {
+        topic: "experiment",
+        experiment_id: "5678",
+        schema_version: 2,
+    };
+    const KEYRING: &str = r#"[
+        { "id": "2023-fall", "key": "QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh", "retired": true },
+        { "id": "2024-spring", "key": "hEDYf0ikoGyFwu8mNFPl3VrqsZpHrBXi" }
+    ]"#;
 
     fn message_for_comparison() -> String {
         json!({
@@ -282,17 +357,16 @@ mod test {
     fn create_hash_data() -> HashData {
         HashData {
             notification_type: Some(event_hash::NotificationType::OutOfRange),
-            researcher: "d.landau@uu.nl".into(),
+            researcher: Some("d.landau@uu.nl".into()),
             experiment_id: "5678".into(),
             measurement_id: "1234".into(),
             timestamp: 1692029115.4314,
+            sequence: Some(1),
         }
     }
 
     fn create_cipher_data(message: String) -> String {
-        let secret_key = SecretKey(SECRET_KEY.into());
-        let key: &[u8] = secret_key.0.as_bytes();
-        let key = Key::<Aes256Gcm>::from_slice(key);
+        let key = Key::<Aes256Gcm>::from_slice(SECRET_KEY.as_bytes());
         let cipher = Aes256Gcm::new(&key);
         let nonce = Aes256Gcm::generate_nonce(&mut OsRng); // 96-bits; unique per message
         let ciphertext = cipher.encrypt(&nonce, message.as_bytes().as_ref()).unwrap();
@@ -302,38 +376,141 @@ mod test {
         b64_nonce + "." + &b64_cipher
     }
 
-    fn get_client(
-    ) -> TestClient<AddDataEndpoint<AddDataEndpoint<Route, SecretKey>, Option<Pool<Postgres>>>>
-    {
-        let secret_key = SecretKey(SECRET_KEY.into());
+    type Client = TestClient<
+        AddDataEndpoint<
+            AddDataEndpoint<
+                AddDataEndpoint<
+                    AddDataEndpoint<
+                        AddDataEndpoint<Route, Arc<Keyring>>,
+                        Option<Arc<Opener>>,
+                    >,
+                    Option<Pool<Postgres>>,
+                >,
+                Metrics,
+            >,
+            Sequences,
+        >,
+    >;
+
+    fn get_client_with_keys(keyring: Keyring, opener: Option<Opener>) -> Client {
         let api_service =
             OpenApiService::new(Api, "Hello World", "1.0").server("http://localhost:3000/api");
         let app = Route::new()
             .nest("/api", api_service)
-            .data(secret_key)
-            .data(None);
+            .data(Arc::new(keyring))
+            .data(opener.map(Arc::new))
+            .data(None::<Pool<Postgres>>)
+            .data(Metrics::new())
+            .data(Sequences::default());
         TestClient::new(app)
     }
 
-    #[tokio::test]
-    async fn post_notify_valid_request() {
-        let client = get_client();
-        let hash_data = create_hash_data();
-        let secret_key = SecretKey(SECRET_KEY.into());
-        let key: &[u8] = secret_key.0.as_bytes();
-        let cipher_data = hash_data.encrypt(key);
-        let body = json!({
+    fn get_client_with_keyring(keyring: Keyring) -> Client {
+        get_client_with_keys(keyring, None)
+    }
+
+    fn get_client() -> Client {
+        get_client_with_keyring(Keyring::single(SECRET_KEY).unwrap())
+    }
+
+    fn valid_body(cipher_data: String) -> serde_json::Value {
+        json!({
             "notification_type": "OutOfRange",
             "researcher": "d.landau@uu.nl",
             "measurement_id": "1234",
             "experiment_id": "5678",
-            "cipher_data": cipher_data
-        });
+            "cipher_data": cipher_data,
+            "topic": "experiment",
+            "schema_version": 2
+        })
+    }
+
+    #[tokio::test]
+    async fn post_notify_valid_request() {
+        let client = get_client();
+        let hash_data = create_hash_data();
+        let keyring = Keyring::single(SECRET_KEY).unwrap();
+        let (key, _) = keyring.get(event_hash::DEFAULT_KEY_ID).unwrap();
+        let body = valid_body(hash_data.encrypt(key, ASSOCIATED_DATA));
         let mut res = client.post("/api/notify").body_json(&body).send().await;
         println!("{:?}", res.0.take_body());
         assert_eq!(res.0.status(), 200);
     }
 
+    #[tokio::test]
+    async fn post_notify_with_active_and_retired_keys() {
+        let keyring = Keyring::from_json(KEYRING).unwrap();
+        let client = get_client_with_keyring(keyring.clone());
+        for key_id in ["2023-fall", "2024-spring"] {
+            let (key, _) = keyring.get(key_id).unwrap();
+            let body = valid_body(create_hash_data().encrypt(key, ASSOCIATED_DATA));
+            let res = client.post("/api/notify").body_json(&body).send().await;
+            assert_eq!(res.0.status(), 200);
+        }
+
+        // From before keys had IDs
+        let body = valid_body(create_cipher_data(message_for_comparison()));
+        let mut res = client.post("/api/notify").body_json(&body).send().await;
+        assert_eq!(res.0.status(), 400);
+        assert_eq!(
+            res.0.take_body().into_string().await.unwrap(),
+            "Cipher text encrypted with a key unknown to the server"
+        );
+    }
+
+    #[tokio::test]
+    async fn post_notify_hash_of_another_context() {
+        let client = get_client();
+        let keyring = Keyring::single(SECRET_KEY).unwrap();
+        let (key, _) = keyring.get(event_hash::DEFAULT_KEY_ID).unwrap();
+        let mut body = valid_body(create_hash_data().encrypt(key, ASSOCIATED_DATA));
+        body["schema_version"] = json!(1);
+        let mut res = client.post("/api/notify").body_json(&body).send().await;
+        assert_eq!(res.0.status(), 400);
+        assert_eq!(
+            res.0.take_body().into_string().await.unwrap(),
+            "Cipher text not issued for the topic, experiment and schema version of the request"
+        );
+    }
+
+    #[tokio::test]
+    async fn post_notify_without_context() {
+        let client = get_client();
+        let keyring = Keyring::single(SECRET_KEY).unwrap();
+        let (key, _) = keyring.get(event_hash::DEFAULT_KEY_ID).unwrap();
+        let valid = valid_body(create_hash_data().encrypt(key, ASSOCIATED_DATA));
+        for field in ["topic", "schema_version"] {
+            let mut body = valid.clone();
+            body.as_object_mut().unwrap().remove(field);
+            let res = client.post("/api/notify").body_json(&body).send().await;
+            assert_eq!(res.0.status(), 400);
+        }
+    }
+
+    #[tokio::test]
+    async fn post_notify_sealed_hash() {
+        let (sealer, opener) = event_hash::generate_keys("producer-2024").unwrap();
+        let sealer = event_hash::Sealer::from_json(&sealer).unwrap();
+        let client = get_client_with_keys(
+            Keyring::default(),
+            Some(Opener::from_json(&opener).unwrap()),
+        );
+        let body = valid_body(create_hash_data().seal(&sealer, ASSOCIATED_DATA));
+        let res = client.post("/api/notify").body_json(&body).send().await;
+        assert_eq!(res.0.status(), 200);
+
+        // Sealed to the server, but by a producer it does not trust
+        let (untrusted, _) = event_hash::generate_keys("producer-2025").unwrap();
+        let untrusted = event_hash::Sealer::from_json(&untrusted).unwrap();
+        let body = valid_body(create_hash_data().seal(&untrusted, ASSOCIATED_DATA));
+        let mut res = client.post("/api/notify").body_json(&body).send().await;
+        assert_eq!(res.0.status(), 400);
+        assert_eq!(
+            res.0.take_body().into_string().await.unwrap(),
+            "Cipher text sealed by a producer unknown to the server"
+        );
+    }
+
     #[tokio::test]
     async fn post_notify_invalid_cipher_composition() {
         let client = get_client();
@@ -344,7 +521,9 @@ mod test {
                 "researcher": "d.landau@uu.nl",
                 "measurement_id": "1234",
                 "experiment_id": "5678",
-                "cipher_data": "R8n76xYE4v/AUk1X5hM/+kkLHH5KYdoDpKiz7dUxybXaq++DcjXcuqM4GxNFg/jbvjmTnS/rh7FKoXvjJu1sg4Gc/cELVkDJ+ZWl0HTS81AfyQQmFH/CID53T3ynTtFmYATtWCnGxWiHffo/RFVSNXdQQvb2x5YBFA4DX7mznPpaC3qzwtzGEGgYtkDkzS0cVC4Kd5gWgJwInx7SHBIoflHZvfzUi329vIU"
+                "cipher_data": "R8n76xYE4v/AUk1X5hM/+kkLHH5KYdoDpKiz7dUxybXaq++DcjXcuqM4GxNFg/jbvjmTnS/rh7FKoXvjJu1sg4Gc/cELVkDJ+ZWl0HTS81AfyQQmFH/CID53T3ynTtFmYATtWCnGxWiHffo/RFVSNXdQQvb2x5YBFA4DX7mznPpaC3qzwtzGEGgYtkDkzS0cVC4Kd5gWgJwInx7SHBIoflHZvfzUi329vIU",
+                "topic": "experiment",
+                "schema_version": 2
             }))
             .send().await;
         assert_eq!(res.0.status(), 400);
@@ -364,7 +543,9 @@ mod test {
                 "researcher": "d.landau@uu.nl",
                 "measurement_id": "1234",
                 "experiment_id": "5678",
-                "cipher_data": "~8n76xYE4v/AUk1X.5hM/+kkLHH5KYdoDpKiz7dUxybXaq++DcjXcuqM4GxNFg/jbvjmTnS/rh7FKoXvjJu1sg4Gc/cELVkDJ+ZWl0HTS81AfyQQmFH/CID53T3ynTtFmYATtWCnGxWiHffo/RFVSNXdQQvb2x5YBFA4DX7mznPpaC3qzwtzGEGgYtkDkzS0cVC4Kd5gWgJwInx7SHBIoflHZvfzUi329vIU"
+                "cipher_data": "~8n76xYE4v/AUk1X.5hM/+kkLHH5KYdoDpKiz7dUxybXaq++DcjXcuqM4GxNFg/jbvjmTnS/rh7FKoXvjJu1sg4Gc/cELVkDJ+ZWl0HTS81AfyQQmFH/CID53T3ynTtFmYATtWCnGxWiHffo/RFVSNXdQQvb2x5YBFA4DX7mznPpaC3qzwtzGEGgYtkDkzS0cVC4Kd5gWgJwInx7SHBIoflHZvfzUi329vIU",
+                "topic": "experiment",
+                "schema_version": 2
             }))
             .send().await;
         assert_eq!(res.0.status(), 400);
@@ -374,6 +555,18 @@ mod test {
         );
     }
 
+    #[tokio::test]
+    async fn post_notify_invalid_nonce_length() {
+        let client = get_client();
+        let body = valid_body("R8n76xYE.5hM/+kkLHH5KYdoDpKiz7dUxybXaq++DcjXcuqM4GxNFg".into());
+        let mut res = client.post("/api/notify").body_json(&body).send().await;
+        assert_eq!(res.0.status(), 400);
+        assert_eq!(
+            res.0.take_body().into_string().await.unwrap(),
+            "Nonce is not 96 bits long"
+        );
+    }
+
     #[tokio::test]
     async fn post_notify_invalid_b64_ciphertext() {
         let client = get_client();
@@ -384,7 +577,9 @@ mod test {
                 "researcher": "d.landau@uu.nl",
                 "measurement_id": "1234",
                 "experiment_id": "5678",
-                "cipher_data": "R8n76xYE4v/AUk1X.~hM/+kkLHH5KYdoDpKiz7dUxybXaq++DcjXcuqM4GxNFg/jbvjmTnS/rh7FKoXvjJu1sg4Gc/cELVkDJ+ZWl0HTS81AfyQQmFH/CID53T3ynTtFmYATtWCnGxWiHffo/RFVSNXdQQvb2x5YBFA4DX7mznPpaC3qzwtzGEGgYtkDkzS0cVC4Kd5gWgJwInx7SHBIoflHZvfzUi329vIU"
+                "cipher_data": "R8n76xYE4v/AUk1X.~hM/+kkLHH5KYdoDpKiz7dUxybXaq++DcjXcuqM4GxNFg/jbvjmTnS/rh7FKoXvjJu1sg4Gc/cELVkDJ+ZWl0HTS81AfyQQmFH/CID53T3ynTtFmYATtWCnGxWiHffo/RFVSNXdQQvb2x5YBFA4DX7mznPpaC3qzwtzGEGgYtkDkzS0cVC4Kd5gWgJwInx7SHBIoflHZvfzUi329vIU",
+                "topic": "experiment",
+                "schema_version": 2
             }))
             .send().await;
         assert_eq!(res.0.status(), 400);
@@ -404,7 +599,9 @@ mod test {
                 "researcher": "d.landau@uu.nl",
                 "measurement_id": "1234",
                 "experiment_id": "5678",
-                "cipher_data": "S8n76xYE4v/AUk1X.5hM/+kkLHH5KYdoDpKiz7dUxybXaq++DcjXcuqM4GxNFg/jbvjmTnS/rh7FKoXvjJu1sg4Gc/cELVkDJ+ZWl0HTS81AfyQQmFH/CID53T3ynTtFmYATtWCnGxWiHffo/RFVSNXdQQvb2x5YBFA4DX7mznPpaC3qzwtzGEGgYtkDkzS0cVC4Kd5gWgJwInx7SHBIoflHZvfzUi329vIU"
+                "cipher_data": "S8n76xYE4v/AUk1X.5hM/+kkLHH5KYdoDpKiz7dUxybXaq++DcjXcuqM4GxNFg/jbvjmTnS/rh7FKoXvjJu1sg4Gc/cELVkDJ+ZWl0HTS81AfyQQmFH/CID53T3ynTtFmYATtWCnGxWiHffo/RFVSNXdQQvb2x5YBFA4DX7mznPpaC3qzwtzGEGgYtkDkzS0cVC4Kd5gWgJwInx7SHBIoflHZvfzUi329vIU",
+                "topic": "experiment",
+                "schema_version": 2
             }))
             .send().await;
         assert_eq!(res.0.status(), 400);
@@ -423,7 +620,9 @@ mod test {
             "researcher": "d.landau@uu.nl",
             "measurement_id": "234",
             "experiment_id": "5678",
-            "cipher_data": create_cipher_data(message)
+            "cipher_data": create_cipher_data(message),
+            "topic": "experiment",
+            "schema_version": 2
         });
         println!("{:?}", json_content);
         let mut res = client
@@ -450,7 +649,9 @@ mod test {
                 "researcher": "d.landau@uu.nl",
                 "measurement_id": "1234",
                 "experiment_id": "678",
-                "cipher_data": create_cipher_data(message)
+                "cipher_data": create_cipher_data(message),
+                "topic": "experiment",
+                "schema_version": 2
             }))
             .send()
             .await;
@@ -472,7 +673,9 @@ mod test {
                 "researcher": "diogo.landau@uu.nl",
                 "measurement_id": "1234",
                 "experiment_id": "5678",
-                "cipher_data": create_cipher_data(message)
+                "cipher_data": create_cipher_data(message),
+                "topic": "experiment",
+                "schema_version": 2
             }))
             .send()
             .await;
@@ -494,7 +697,9 @@ mod test {
                 "researcher": "d.landau@uu.nl",
                 "measurement_id": "1234",
                 "experiment_id": "5678",
-                "cipher_data": create_cipher_data(message)
+                "cipher_data": create_cipher_data(message),
+                "topic": "experiment",
+                "schema_version": 2
             }))
             .send()
             .await;
@@ -516,7 +721,9 @@ mod test {
                 "researcher": "d.landau@uu.nl",
                 "measurement_id": "1234",
                 "experiment_id": "678",
-                "cipher_data": create_cipher_data(message)
+                "cipher_data": create_cipher_data(message),
+                "topic": "experiment",
+                "schema_version": 2
             }))
             .send()
             .await;
@@ -1,9 +1,11 @@
 use clap::Parser;
 use dotenv;
+use event_hash::{Keyring, Opener};
 use metric::Metrics;
 use poem::{listener::TcpListener, EndpointExt, Route};
 use poem_openapi::OpenApiService;
 use prometheus_client::registry::Registry;
+use sequence::Sequences;
 use sqlx::postgres::PgPoolOptions;
 use std::{
     env,
@@ -14,17 +16,32 @@ use tracing::{info, Level};
 mod api;
 mod jwt;
 mod metric;
+mod sequence;
 mod store;
 
-use api::{Api, SecretKey};
+use api::Api;
 
 #[derive(Parser, Debug)]
 struct CliArgs {
-    #[arg(short, long)]
-    secret_key: String,
+    /// 32 character key of the experiment-producer, with key ID `default`
+    #[arg(short, long, required_unless_present_any = ["keyring", "sealing_keys"])]
+    secret_key: Option<String>,
+
+    /// JSON file of the active and retired keys of the experiment-producer
+    #[arg(short, long, conflicts_with = "secret_key")]
+    keyring: Option<String>,
+
+    /// JSON file of the key opening sealed hashes and of the producers trusted to sign them
+    #[arg(long)]
+    sealing_keys: Option<String>,
 
     #[arg(short, long)]
     external_ip: String,
+
+    /// Experiments whose last notified transition is tracked, the least recently notified one
+    /// is forgotten beyond them
+    #[arg(long, default_value_t = sequence::DEFAULT_CAPACITY)]
+    tracked_experiments: usize,
 }
 
 #[tokio::main]
@@ -33,7 +50,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv::from_filename("notifications-service/.env")?;
 
     let args = CliArgs::parse();
-    let secret_key = SecretKey(args.secret_key);
+    let keyring = match (&args.keyring, &args.secret_key) {
+        (Some(path), _) => Keyring::from_file(path)?,
+        (None, Some(secret_key)) => Keyring::single(secret_key)?,
+        // Sealed hashes only
+        (None, None) => Keyring::default(),
+    };
+    let opener = match &args.sealing_keys {
+        Some(path) => Some(Arc::new(Opener::from_file(path)?)),
+        None => None,
+    };
 
     let metrics = Metrics::new();
     let mut registry = <Registry>::default();
@@ -42,6 +68,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         "Count of response",
         metrics.response_count.clone(),
     );
+    registry.register(
+        "notifications_service_key_use_count",
+        "Count of notifications decrypted by each key",
+        metrics.key_use_count.clone(),
+    );
     let state = Arc::new(Mutex::new(registry));
 
     let pool = match env::var("DATABASE_URL") {
@@ -67,9 +98,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let app = Route::new()
         .nest("/api", api_service)
         .nest("/", ui)
-        .data(secret_key)
+        .data(Arc::new(keyring))
+        .data(opener)
         .data(state)
         .data(metrics.clone())
+        .data(Sequences::new(args.tracked_experiments))
         .data(pool);
 
     Ok(poem::Server::new(TcpListener::bind("0.0.0.0:3000"))
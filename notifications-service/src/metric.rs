@@ -1,4 +1,5 @@
 use crate::api::NotifyErrorResponse;
+use crate::sequence::Order;
 use event_hash::DecryptError;
 use prometheus_client::{
     encoding::{EncodeLabelSet, EncodeLabelValue},
@@ -11,18 +12,45 @@ pub struct ResponseCountLabels {
     pub response_type: ResponseType,
 }
 
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct KeyUseLabels {
+    pub key_id: String,
+}
+
 #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
 pub enum ResponseType {
     Ok,
     HashError,
+    /// Hash of another group, see [`DecryptError::GroupMismatch`].
+    CrossGroupReplay,
+    /// Accepted, transitions of the experiment were skipped since the last one notified.
+    SequenceGap,
+    /// Accepted, the transition precedes the last one notified.
+    SequenceReorder,
+    /// Accepted, the transition repeats the last one notified.
+    SequenceDuplicate,
     InsertError,
     JwtError,
     InvalidData,
 }
 
 impl From<&DecryptError> for ResponseType {
-    fn from(_e: &DecryptError) -> Self {
-        ResponseType::HashError
+    fn from(e: &DecryptError) -> Self {
+        match e {
+            DecryptError::GroupMismatch => ResponseType::CrossGroupReplay,
+            _ => ResponseType::HashError,
+        }
+    }
+}
+
+impl From<Order> for ResponseType {
+    fn from(order: Order) -> Self {
+        match order {
+            Order::Next => ResponseType::Ok,
+            Order::Gap => ResponseType::SequenceGap,
+            Order::Reorder => ResponseType::SequenceReorder,
+            Order::Duplicate => ResponseType::SequenceDuplicate,
+        }
     }
 }
 
@@ -47,12 +75,15 @@ impl From<&NotifyErrorResponse> for ResponseType {
 #[derive(Clone)]
 pub struct Metrics {
     pub response_count: Family<ResponseCountLabels, Counter>,
+    /// Notifications decrypted by each key, a retired key can be removed once it stops counting.
+    pub key_use_count: Family<KeyUseLabels, Counter>,
 }
 
 impl Metrics {
     pub fn new() -> Self {
         Self {
             response_count: Family::<ResponseCountLabels, Counter>::default(),
+            key_use_count: Family::<KeyUseLabels, Counter>::default(),
         }
     }
 }
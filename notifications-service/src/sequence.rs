@@ -0,0 +1,131 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+/// Experiments tracked by default, see [`Sequences::new`].
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+/// Order of a notification among the transitions of its experiment, see
+/// [`event_hash::HashData::sequence`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    /// The transition right after the last one accepted.
+    Next,
+    /// Transitions were skipped since the last one accepted.
+    Gap,
+    /// A transition before the last one accepted, reported late.
+    Reorder,
+    /// The last transition accepted, reported again.
+    Duplicate,
+}
+
+/// Last transition accepted per group and experiment. Kept in memory: after a restart, the first
+/// notification of an experiment in progress counts as a gap.
+#[derive(Clone)]
+pub struct Sequences(Arc<Mutex<Tracked>>);
+
+/// Group, if any, and ID of an experiment.
+type Experiment = (Option<String>, String);
+
+/// Experiments by last use, evicting the least recently notified one beyond `capacity`.
+struct Tracked {
+    capacity: usize,
+    /// Last transition accepted and last use of each experiment.
+    sequences: HashMap<Experiment, (u32, u64)>,
+    /// Experiments by last use.
+    uses: BTreeMap<u64, Experiment>,
+    next_use: u64,
+}
+
+impl Default for Sequences {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl Sequences {
+    /// Tracks the last `capacity` experiments notified. The service does not see experiments
+    /// terminate: a notification for an experiment evicted since counts as a gap.
+    pub fn new(capacity: usize) -> Self {
+        Self(Arc::new(Mutex::new(Tracked {
+            capacity: capacity.max(1),
+            sequences: HashMap::new(),
+            uses: BTreeMap::new(),
+            next_use: 0,
+        })))
+    }
+
+    /// Accepts transition `sequence` of `experiment_id` notified by `group`.
+    pub fn accept(&self, group: Option<&str>, experiment_id: &str, sequence: u32) -> Order {
+        let mut tracked = self.0.lock().unwrap();
+        let Tracked {
+            capacity,
+            sequences,
+            uses,
+            next_use,
+        } = &mut *tracked;
+        let experiment = (group.map(String::from), experiment_id.to_string());
+        let used = *next_use;
+        *next_use += 1;
+        let last = match sequences.get_mut(&experiment) {
+            Some((last, last_use)) => {
+                uses.remove(last_use);
+                *last_use = used;
+                last
+            }
+            None => {
+                if sequences.len() >= *capacity {
+                    if let Some((_, evicted)) = uses.pop_first() {
+                        sequences.remove(&evicted);
+                    }
+                }
+                &mut sequences.entry(experiment.clone()).or_insert((0, used)).0
+            }
+        };
+        uses.insert(used, experiment);
+        let order = match sequence.checked_sub(*last) {
+            Some(1) => Order::Next,
+            Some(0) => Order::Duplicate,
+            None => Order::Reorder,
+            Some(_) => Order::Gap,
+        };
+        *last = (*last).max(sequence);
+        order
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_gaps_and_reorders() {
+        let sequences = Sequences::default();
+        let accept =
+            |group, experiment_id, sequence| sequences.accept(group, experiment_id, sequence);
+        assert_eq!(accept(Some("group0"), "5678", 1), Order::Next);
+        assert_eq!(accept(Some("group0"), "5678", 3), Order::Gap);
+        assert_eq!(accept(Some("group0"), "5678", 2), Order::Reorder);
+        assert_eq!(accept(Some("group0"), "5678", 3), Order::Duplicate);
+        assert_eq!(accept(Some("group0"), "5678", 4), Order::Next);
+
+        // Tracked separately per group and experiment
+        assert_eq!(accept(Some("group1"), "5678", 1), Order::Next);
+        assert_eq!(accept(Some("group0"), "1234", 2), Order::Gap);
+        assert_eq!(accept(None, "5678", 1), Order::Next);
+    }
+
+    #[test]
+    fn evict_the_least_recently_notified_experiment() {
+        let sequences = Sequences::new(2);
+        assert_eq!(sequences.accept(None, "1", 1), Order::Next);
+        assert_eq!(sequences.accept(None, "2", 1), Order::Next);
+        assert_eq!(sequences.accept(None, "1", 2), Order::Next);
+        // Evicts experiment 2
+        assert_eq!(sequences.accept(None, "3", 1), Order::Next);
+        assert_eq!(sequences.accept(None, "1", 3), Order::Next);
+        assert_eq!(sequences.accept(None, "2", 2), Order::Gap);
+        assert_eq!(sequences.0.lock().unwrap().sequences.len(), 2);
+    }
+}
@@ -1,51 +1,41 @@
-use apache_avro::{from_value, Reader};
 use clap::ArgMatches;
-use event_hash::{HashData, NotificationType};
-use rand::Rng;
-use rdkafka::{
-    client::ClientContext,
-    config::ClientConfig,
-    consumer::stream_consumer::StreamConsumer,
-    consumer::{CommitMode, Consumer, ConsumerContext, Rebalance},
-    message::{Headers, Message},
+use event_hash::{AssociatedData, HashData, Keyring, NotificationType, Opener};
+use event_schema::Encoding;
+use event_types::SensorTemperatureMeasured;
+use kafka_client::{
+    ConsumerConfig, Dispatcher, EventConsumer, HandlerError, KafkaConfig, LogRebalance,
+    PoisonPolicy, Record,
 };
+use rand::Rng;
 use reqwest::Client;
-use serde::Deserialize;
-use std::{collections::HashMap, sync::Arc};
+use serde::Serialize;
+use std::sync::Arc;
 use tokio::time::{self, Duration};
 
-#[derive(Deserialize, Debug)]
-struct SensorTemperatureMeasured {
-    experiment: String,
-    sensor: String,
-    measurement_id: String,
-    timestamp: f64,
-    temperature: f32,
-    measurement_hash: String,
-}
-
-struct CustomContext;
-
-impl ClientContext for CustomContext {}
-
-impl ConsumerContext for CustomContext {
-    fn post_rebalance(&self, rebalance: &Rebalance) {
-        println!("Post rebalance {:?}", rebalance);
-    }
-}
-
 pub struct ConsumeConfiguration {
-    secret_key: String,
+    keyring: Keyring,
+    opener: Option<Opener>,
     group_id: String,
     brokers: String,
     topic: String,
     notifications_host: String,
     token: Arc<str>,
+    encoding: Encoding,
+    schema_registry: Option<String>,
+    kafka_config: KafkaConfig,
+    poison_policy: PoisonPolicy,
 }
 
 impl From<&mut ArgMatches> for ConsumeConfiguration {
     fn from(args: &mut ArgMatches) -> Self {
-        let secret_key = args.remove_one::<String>("secret-key").expect("Required");
+        let keyring = match args.remove_one::<String>("keyring") {
+            Some(path) => Keyring::from_file(&path),
+            None => Keyring::single(&args.remove_one::<String>("secret-key").expect("Required")),
+        }
+        .unwrap_or_else(|e| panic!("Invalid keyring: {}", e));
+        let opener = args.remove_one::<String>("sealing-keys").map(|path| {
+            Opener::from_file(&path).unwrap_or_else(|e| panic!("Invalid sealing keys: {}", e))
+        });
         let brokers = args.remove_one::<String>("broker-list").expect("Required");
         let group_id = args.remove_one::<String>("group-id").expect("Required");
         let topic = args.remove_one::<String>("topic").expect("Required");
@@ -55,148 +45,159 @@ impl From<&mut ArgMatches> for ConsumeConfiguration {
         let token = args
             .remove_one::<String>("token")
             .expect("Missing required arg");
+        let encoding = args
+            .remove_one::<String>("encoding")
+            .expect("Required")
+            .parse()
+            .expect("Validated by clap");
+        let schema_registry = args.remove_one::<String>("schema-registry");
+        let kafka_config = KafkaConfig::from_args(args)
+            .unwrap_or_else(|e| panic!("Invalid Kafka configuration: {}", e));
+        let poison_policy = PoisonPolicy::from_args(args);
 
         ConsumeConfiguration {
-            secret_key,
+            keyring,
+            opener,
             group_id,
             brokers,
             topic,
             notifications_host,
             token: token.into(),
+            encoding,
+            schema_registry,
+            kafka_config,
+            poison_policy,
         }
     }
 }
 
-pub struct Consume {
-    config: ConsumeConfiguration,
-    consumer: StreamConsumer<CustomContext>,
-    client: Client,
+/// Body of `/api/notify`.
+#[derive(Debug, Serialize)]
+struct NotifyBody {
+    notification_type: &'static str,
+    researcher: String,
+    measurement_id: String,
+    experiment_id: String,
+    cipher_data: String,
+    topic: String,
+    schema_version: u32,
 }
 
-impl Consume {
-    pub fn new(config: ConsumeConfiguration) -> Self {
-        let context = CustomContext;
-        let consumer: StreamConsumer<CustomContext> = ClientConfig::new()
-            .set("group.id", config.group_id.as_str())
-            .set("bootstrap.servers", config.brokers.as_str())
-            .set("enable.partition.eof", "false")
-            .set("session.timeout.ms", "6000")
-            .set("enable.auto.commit", "true")
-            .set("security.protocol", "SSL")
-            .set("ssl.ca.location", "auth/ca.crt")
-            .set("ssl.keystore.location", "auth/kafka.keystore.pkcs12")
-            .set("ssl.keystore.password", "cc2023")
-            .create_with_context(context)
-            .expect("Consumer creation failed");
+/// Posts the notification of a measurement to the notifications-service.
+#[derive(Clone)]
+struct Notify {
+    keyring: Arc<Keyring>,
+    opener: Option<Arc<Opener>>,
+    notifications_host: Arc<str>,
+    token: Arc<str>,
+    client: Client,
+}
 
-        let client = Client::new();
+impl Notify {
+    fn handle(&self, record: Record<SensorTemperatureMeasured>) -> Result<(), HandlerError> {
+        let sensor_measurement = record.event;
+        let associated_data = AssociatedData {
+            topic: &record.topic,
+            experiment_id: &sensor_measurement.experiment,
+            schema_version: record.schema_version,
+        };
+        // The notifications-service checks the group of the hash against the token
+        let hash_data = match &self.opener {
+            Some(opener) if event_hash::is_sealed(&sensor_measurement.measurement_hash) => {
+                HashData::open(
+                    opener,
+                    None,
+                    associated_data,
+                    &sensor_measurement.measurement_hash,
+                )
+            }
+            _ => HashData::decrypt(
+                &self.keyring,
+                None,
+                associated_data,
+                &sensor_measurement.measurement_hash,
+            ),
+        }?;
+        let notification_type = match hash_data.notification_type {
+            Some(NotificationType::OutOfRange) => "OutOfRange",
+            Some(NotificationType::Stabilized) => "Stabilized",
+            None => return Ok(()),
+        };
+        let body = NotifyBody {
+            notification_type,
+            // Only known from the hash, the `experiment_configured` events are not consumed
+            researcher: hash_data
+                .researcher
+                .ok_or("Measurement hash without researcher")?,
+            measurement_id: hash_data.measurement_id,
+            experiment_id: hash_data.experiment_id,
+            cipher_data: sensor_measurement.measurement_hash,
+            topic: record.topic,
+            schema_version: record.schema_version,
+        };
 
-        Self {
-            config,
-            consumer,
-            client,
-        }
+        let notify = self.clone();
+        tokio::spawn(async move {
+            let sleep_secs = {
+                let mut rng = rand::thread_rng();
+                rng.gen_range(0..5)
+            };
+            time::sleep(Duration::from_millis(sleep_secs * 1000)).await;
+            notify
+                .client
+                .post(format!(
+                    "http://{}:3000/api/notify",
+                    notify.notifications_host
+                ))
+                .query(&[("token", notify.token)])
+                .json(&body)
+                .send()
+                .await
+                .expect("Failed to notify");
+            println!("Notify {:?}", body.measurement_id);
+        });
+        Ok(())
     }
+}
 
-    pub async fn start(&self) {
-        self.consumer
-            .subscribe(&[self.config.topic.as_str()])
-            .expect("Can't subscribe to specified topics");
-        println!("Subscribed to {}", self.config.topic.as_str());
-        self.read_loop().await;
-    }
+pub struct Consume {
+    consumer: EventConsumer,
+    notify: Notify,
+}
 
-    async fn read_loop(&self) {
-        loop {
-            match self.consumer.recv().await {
-                Err(e) => println!("Kafka error: {}", e),
-                Ok(b) => {
-                    let m = b.detach();
-                    let headers = m.headers();
-                    if let None = headers {
-                        continue;
-                    }
-                    let headers = headers.unwrap();
-                    let record_name =
-                        String::from_utf8(headers.get(0).unwrap().1.to_vec()).expect("Valid utf-8");
-                    if record_name != "sensor_temperature_measured" {
-                        continue;
-                    }
-                    let reader = Reader::new(m.payload().unwrap()).unwrap();
-                    for value in reader {
-                        let sensor_measurement: SensorTemperatureMeasured =
-                            from_value::<SensorTemperatureMeasured>(&value.unwrap())
-                                .expect("Received invalid event")
-                                .into();
+impl Consume {
+    pub fn new(config: ConsumeConfiguration) -> Self {
+        println!("Subscribing to {}", config.topic);
+        let consumer = EventConsumer::new(
+            ConsumerConfig {
+                brokers: config.brokers,
+                group_id: config.group_id,
+                topics: vec![config.topic],
+                encoding: config.encoding,
+                schema_registry: config.schema_registry,
+                kafka_config: config.kafka_config,
+                poison_policy: config.poison_policy,
+            },
+            LogRebalance,
+        )
+        .expect("Consumer creation failed");
+        let notify = Notify {
+            keyring: Arc::new(config.keyring),
+            opener: config.opener.map(Arc::new),
+            notifications_host: config.notifications_host.into(),
+            token: config.token,
+            client: Client::new(),
+        };
 
-                        let hash_data = HashData::decrypt(
-                            self.config.secret_key.as_bytes(),
-                            &sensor_measurement.measurement_hash,
-                        )
-                        .expect("Valid measurement_hash");
-                        if hash_data.notification_type.is_none() {
-                            break;
-                        }
-                        let mut map = HashMap::new();
-                        map.insert("researcher", hash_data.researcher);
-                        map.insert("measurement_id", hash_data.measurement_id);
-                        map.insert("experiment_id", hash_data.experiment_id);
-                        map.insert("cipher_data", sensor_measurement.measurement_hash);
+        Self { consumer, notify }
+    }
 
-                        let token = self.config.token.clone();
-                        match hash_data.notification_type {
-                            Some(NotificationType::OutOfRange) => {
-                                let client = self.client.clone();
-                                let notifications_host = self.config.notifications_host.clone();
-                                tokio::spawn(async move {
-                                    let sleep_secs = {
-                                        let mut rng = rand::thread_rng();
-                                        rng.gen_range(0..5)
-                                    };
-                                    time::sleep(Duration::from_millis(sleep_secs * 1000)).await;
-                                    map.insert("notification_type", "OutOfRange".into());
-                                    client
-                                        .post(format!(
-                                            "http://{}:3000/api/notify",
-                                            notifications_host
-                                        ))
-                                        .query(&[("token", token)])
-                                        .json(&map)
-                                        .send()
-                                        .await
-                                        .expect("Failed to notify");
-                                    println!("Notify {:?}", map.get("measurement_id"));
-                                });
-                            }
-                            Some(NotificationType::Stabilized) => {
-                                let client = self.client.clone();
-                                let notifications_host = self.config.notifications_host.clone();
-                                tokio::spawn(async move {
-                                    let sleep_secs = {
-                                        let mut rng = rand::thread_rng();
-                                        rng.gen_range(0..5)
-                                    };
-                                    time::sleep(Duration::from_millis(sleep_secs * 1000)).await;
-                                    map.insert("notification_type", "Stabilized".into());
-                                    client
-                                        .post(format!(
-                                            "http://{}:3000/api/notify",
-                                            notifications_host
-                                        ))
-                                        .query(&[("token", token)])
-                                        .json(&map)
-                                        .send()
-                                        .await
-                                        .expect("Failed to notify");
-                                    println!("Notify {:?}", map.get("measurement_id"));
-                                });
-                            }
-                            _ => {}
-                        }
-                    }
-                    self.consumer.commit_message(&b, CommitMode::Async).unwrap();
-                }
-            };
-        }
+    pub async fn start(&self) {
+        let notify = self.notify.clone();
+        let dispatcher = Dispatcher::new().on_record(move |record| {
+            let result = notify.handle(record);
+            async move { result }
+        });
+        self.consumer.run(dispatcher).await;
     }
 }
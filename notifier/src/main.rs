@@ -1,5 +1,7 @@
 use crate::consume::{Consume, ConsumeConfiguration};
 use clap::{command, Arg, ArgAction};
+use event_schema::Encoding;
+use kafka_client::{KafkaConfig, PoisonPolicy};
 
 mod consume;
 
@@ -14,6 +16,19 @@ async fn main() {
             .default_value("QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh")
             .help("<key> is a 32 character string that must match the key being passed to the notifications-service")
         )
+        .arg(Arg::new("keyring")
+            .required(false)
+            .long("keyring")
+            .action(ArgAction::Set)
+            .conflicts_with("secret-key")
+            .help("<keyring> is the JSON file of keys being passed to the notifications-service, instead of `--secret-key`")
+        )
+        .arg(Arg::new("sealing-keys")
+            .required(false)
+            .long("sealing-keys")
+            .action(ArgAction::Set)
+            .help("<sealing-keys> is the JSON file of keys being passed to the notifications-service opening sealed hashes, along with `--keyring` for the other hashes")
+        )
         .arg(Arg::new("broker-list")
             .required(true)
             .action(ArgAction::Set)
@@ -43,6 +58,22 @@ async fn main() {
             .action(ArgAction::Set)
             .help("A string identifying the client making the request to the notifications-service")
         )
+        .arg(Arg::new("encoding")
+            .required(false)
+            .long("encoding")
+            .action(ArgAction::Set)
+            .default_value("container")
+            .value_parser(Encoding::VALUES)
+            .help("Wire encoding of the Avro events, must match the `--encoding` of the experiment-producer")
+        )
+        .arg(Arg::new("schema-registry")
+            .required(false)
+            .long("schema-registry")
+            .action(ArgAction::Set)
+            .help("URL of the schema registry resolving the writer schema of `--encoding confluent` events")
+        )
+        .args(KafkaConfig::args())
+        .args(PoisonPolicy::args())
         .get_matches();
 
     let consume_config = ConsumeConfiguration::from(&mut matches);
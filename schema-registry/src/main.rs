@@ -0,0 +1,271 @@
+use actix_web::{
+    get, post,
+    web::{Data, Json, Path},
+    App, HttpResponse, HttpServer, Responder,
+};
+use apache_avro::Schema;
+use clap::{command, value_parser, Arg, ArgAction};
+use event_schema::Compatibility;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{collections::HashMap, sync::Mutex};
+
+/// In-memory stand-in for the subset of the Confluent schema registry API used by the
+/// experiment-producer and the consumers. Nothing is persisted across restarts.
+#[derive(Default)]
+struct Registry {
+    /// Canonical form of every schema, the ID of a schema is its index + 1.
+    schemas: Vec<String>,
+    /// Schema IDs of every version of a subject, the version is the index + 1.
+    subjects: HashMap<String, Vec<u32>>,
+}
+
+impl Registry {
+    /// Registers `schema` as the latest version of `subject`, unless it is not fully compatible
+    /// with the current latest version.
+    fn register(&mut self, subject: &str, schema: &Schema) -> Result<u32, Compatibility> {
+        let canonical_form = schema.canonical_form();
+        if let Some(&latest) = self.subjects.get(subject).and_then(|ids| ids.last()) {
+            let latest = Schema::parse_str(self.schema(latest).expect("Registered schema"))
+                .expect("Registered schemas are valid");
+            match event_schema::compatibility(&latest, schema) {
+                Compatibility::Full => {}
+                compatibility => return Err(compatibility),
+            }
+        }
+        let id = match self.schemas.iter().position(|s| *s == canonical_form) {
+            Some(idx) => idx as u32 + 1,
+            None => {
+                self.schemas.push(canonical_form);
+                self.schemas.len() as u32
+            }
+        };
+        let subject_ids = self.subjects.entry(subject.to_string()).or_default();
+        if !subject_ids.contains(&id) {
+            subject_ids.push(id);
+        }
+        Ok(id)
+    }
+
+    fn schema(&self, id: u32) -> Option<&str> {
+        self.schemas
+            .get((id as usize).checked_sub(1)?)
+            .map(|s| s.as_str())
+    }
+}
+
+#[derive(Deserialize)]
+struct RegisterBody {
+    schema: String,
+}
+
+#[derive(Serialize)]
+struct SubjectVersion<'a> {
+    subject: &'a str,
+    version: usize,
+    id: u32,
+    schema: &'a str,
+}
+
+fn error(status: u16, error_code: u32, message: &str) -> HttpResponse {
+    HttpResponse::build(actix_web::http::StatusCode::from_u16(status).expect("Valid status"))
+        .json(json!({ "error_code": error_code, "message": message }))
+}
+
+#[post("/subjects/{subject}/versions")]
+async fn register(
+    registry: Data<Mutex<Registry>>,
+    subject: Path<String>,
+    body: Json<RegisterBody>,
+) -> impl Responder {
+    let schema = match Schema::parse_str(&body.schema) {
+        Ok(schema) => schema,
+        Err(e) => return error(422, 42201, &format!("Invalid schema: {}", e)),
+    };
+    match registry.lock().unwrap().register(&subject, &schema) {
+        Ok(id) => HttpResponse::Ok().json(json!({ "id": id })),
+        Err(compatibility) => error(
+            409,
+            409,
+            &format!(
+                "Schema is not fully compatible with the latest version of `{}`, only {:?}",
+                subject.as_str(),
+                compatibility
+            ),
+        ),
+    }
+}
+
+#[get("/schemas/ids/{id}")]
+async fn schema_by_id(registry: Data<Mutex<Registry>>, id: Path<u32>) -> impl Responder {
+    match registry.lock().unwrap().schema(*id) {
+        Some(schema) => HttpResponse::Ok().json(json!({ "schema": schema })),
+        None => error(404, 40403, "Schema not found"),
+    }
+}
+
+#[get("/subjects")]
+async fn subjects(registry: Data<Mutex<Registry>>) -> impl Responder {
+    let registry = registry.lock().unwrap();
+    let mut subjects: Vec<_> = registry.subjects.keys().collect();
+    subjects.sort();
+    HttpResponse::Ok().json(subjects)
+}
+
+#[get("/subjects/{subject}/versions")]
+async fn versions(registry: Data<Mutex<Registry>>, subject: Path<String>) -> impl Responder {
+    match registry.lock().unwrap().subjects.get(subject.as_str()) {
+        Some(ids) => HttpResponse::Ok().json((1..=ids.len()).collect::<Vec<_>>()),
+        None => error(404, 40401, "Subject not found"),
+    }
+}
+
+#[get("/subjects/{subject}/versions/{version}")]
+async fn version(registry: Data<Mutex<Registry>>, path: Path<(String, String)>) -> impl Responder {
+    let (subject, version) = path.into_inner();
+    let registry = registry.lock().unwrap();
+    let ids = match registry.subjects.get(&subject) {
+        Some(ids) => ids,
+        None => return error(404, 40401, "Subject not found"),
+    };
+    let version = match version.as_str() {
+        "latest" => ids.len(),
+        version => match version.parse::<usize>() {
+            Ok(version) if (1..=ids.len()).contains(&version) => version,
+            _ => return error(404, 40402, "Version not found"),
+        },
+    };
+    let id = ids[version - 1];
+    HttpResponse::Ok().json(SubjectVersion {
+        subject: &subject,
+        version,
+        id,
+        schema: registry.schema(id).expect("Registered schema"),
+    })
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let mut matches = command!() // requires `cargo` feature
+        .next_line_help(true)
+        .arg(
+            Arg::new("port")
+                .required(false)
+                .long("port")
+                .action(ArgAction::Set)
+                .default_value("8081")
+                .value_parser(value_parser!(u16)),
+        )
+        .get_matches();
+    let port = matches.remove_one::<u16>("port").expect("required");
+
+    let registry = Data::new(Mutex::new(Registry::default()));
+    println!("Schema registry listening on port {}", port);
+    HttpServer::new(move || {
+        App::new()
+            .app_data(registry.clone())
+            .service(register)
+            .service(schema_by_id)
+            .service(subjects)
+            .service(versions)
+            .service(version)
+    })
+    .bind(("0.0.0.0", port))?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test;
+
+    const SCHEMA: &str = r#"{"type": "record", "name": "experiment_started", "fields": [{"name": "experiment", "type": "string"}, {"name": "timestamp", "type": "double"}]}"#;
+
+    #[test]
+    async fn register_and_resolve_by_id() {
+        let registry = Data::new(Mutex::new(Registry::default()));
+        let app = test::init_service(
+            App::new()
+                .app_data(registry.clone())
+                .service(register)
+                .service(schema_by_id)
+                .service(version),
+        )
+        .await;
+
+        for subject in ["experiment_started", "experiment_terminated"] {
+            let req = test::TestRequest::post()
+                .uri(&format!("/subjects/{}/versions", subject))
+                .set_json(json!({ "schema": SCHEMA }))
+                .to_request();
+            let res: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            // Identical schemas share the same ID across subjects
+            assert_eq!(res, json!({ "id": 1 }));
+        }
+
+        let req = test::TestRequest::get().uri("/schemas/ids/1").to_request();
+        let res: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let schema = Schema::parse_str(res["schema"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            schema.canonical_form(),
+            Schema::parse_str(SCHEMA).unwrap().canonical_form()
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/subjects/experiment_terminated/versions/latest")
+            .to_request();
+        let res: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(res["version"], 1);
+        assert_eq!(res["id"], 1);
+    }
+
+    #[test]
+    async fn only_accept_fully_compatible_versions() {
+        let registry = Data::new(Mutex::new(Registry::default()));
+        let app = test::init_service(App::new().app_data(registry).service(register)).await;
+
+        for (id, raw_schema) in [
+            (1, event_schema::v1::EXPERIMENT_TERMINATED),
+            (2, event_schema::v2::EXPERIMENT_TERMINATED),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/subjects/experiment_terminated/versions")
+                .set_json(json!({ "schema": raw_schema }))
+                .to_request();
+            let res: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(res, json!({ "id": id }));
+        }
+
+        // Dropping the timestamp breaks consumers expecting it
+        let req = test::TestRequest::post()
+            .uri("/subjects/experiment_terminated/versions")
+            .set_json(json!({ "schema": r#"{"type": "record", "name": "experiment_terminated", "fields": [{"name": "experiment", "type": "string"}]}"# }))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 409);
+    }
+
+    #[test]
+    async fn reject_invalid_schema_and_unknown_id() {
+        let registry = Data::new(Mutex::new(Registry::default()));
+        let app = test::init_service(
+            App::new()
+                .app_data(registry)
+                .service(register)
+                .service(schema_by_id),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/subjects/experiment_started/versions")
+            .set_json(json!({ "schema": "{\"type\": \"unknown\"}" }))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 422);
+
+        let req = test::TestRequest::get().uri("/schemas/ids/0").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 404);
+    }
+}
@@ -1,5 +1,8 @@
 use clap::{command, Arg, ArgAction};
 use dashmap::DashMap;
+use event_schema::Encoding;
+use experiment_model::ExperimentDocument;
+use kafka_client::{ConsumerConfig, KafkaConfig, PoisonPolicy};
 use futures::future;
 use poem::{
     listener::TcpListener,
@@ -13,8 +16,6 @@ use std::sync::Arc;
 
 mod consumer;
 
-use crate::consumer::{ExperimentDocument, Measurement};
-
 #[derive(Deserialize, Debug)]
 struct TemperatureQueryParams {
     #[serde(rename = "experiment-id")]
@@ -53,9 +54,7 @@ impl Api {
         let experiment = map
             .get(&experiment_id)
             .expect(&format!("Experiment `{:?}` does not exist", experiment_id));
-        let measurements = experiment
-            .get_measurements_slice(start_time, end_time)
-            .unwrap();
+        let measurements = experiment.range(start_time..=end_time);
         
         if !produce_error {
             return PlainText(serde_json::to_string(&measurements).unwrap());
@@ -66,7 +65,7 @@ impl Api {
             rng.gen_range(0.0..1.0)
         };
         if random_value < 0.1 {
-            PlainText(serde_json::to_string(&experiment.measurements).unwrap())
+            PlainText(serde_json::to_string(experiment.measurements()).unwrap())
         } else if random_value < 0.2 {
             PlainText(String::from("invalid serialized data"))
         } else {
@@ -86,17 +85,7 @@ impl Api {
             .get(&experiment_id)
             .expect(&format!("Experiment `{:?}` does not exist", experiment_id));
         let produce_error = produce_error.0;
-        let measurements = &experiment.measurements;
-        let upper_threshold = experiment.temperature_range.upper_threshold;
-        let lower_threshold = experiment.temperature_range.lower_threshold;
-        let measurements: Vec<Measurement> = measurements
-            .iter()
-            .filter(|measurement| {
-                let temperature = measurement.temperature;
-                (temperature > upper_threshold) || (temperature < lower_threshold)
-            })
-            .map(|measurement| measurement.clone())
-            .collect();
+        let measurements = experiment.out_of_range();
 
         if !produce_error {
             return PlainText(serde_json::to_string(&measurements).unwrap());
@@ -107,7 +96,7 @@ impl Api {
             rng.gen_range(0.0..1.0)
         };
         if random_value < 0.1 {
-            PlainText(serde_json::to_string(&experiment.measurements).unwrap())
+            PlainText(serde_json::to_string(experiment.measurements()).unwrap())
         } else if random_value < 0.2 {
             PlainText(String::from("invalid serialized data"))
         } else {
@@ -149,7 +138,42 @@ async fn main() {
             .long("produce-errors")
             .action(ArgAction::SetTrue)
         )
+        .arg(Arg::new("encoding")
+            .required(false)
+            .long("encoding")
+            .action(ArgAction::Set)
+            .default_value("container")
+            .value_parser(Encoding::VALUES)
+            .help("Wire encoding of the Avro events, must match the `--encoding` of the experiment-producer")
+        )
+        .arg(Arg::new("schema-registry")
+            .required(false)
+            .long("schema-registry")
+            .action(ArgAction::Set)
+            .help("URL of the schema registry resolving the writer schema of `--encoding confluent` events")
+        )
+        .args(KafkaConfig::args())
+        .args(PoisonPolicy::args())
         .get_matches();
+    let mut kafka_config = KafkaConfig::from_args(&matches)
+        .unwrap_or_else(|e| panic!("Invalid Kafka configuration: {}", e));
+    // Documents are only published once, every one of them is needed
+    kafka_config.set_default("auto.offset.reset", "earliest");
+    let consumer_config = ConsumerConfig {
+        brokers: matches
+            .remove_one::<String>("broker-list")
+            .expect("required"),
+        group_id: matches.remove_one::<String>("group-id").expect("required"),
+        topics: vec![matches.remove_one::<String>("topic").expect("required")],
+        encoding: matches
+            .remove_one::<String>("encoding")
+            .expect("required")
+            .parse()
+            .expect("Validated by clap"),
+        schema_registry: matches.remove_one::<String>("schema-registry"),
+        kafka_config,
+        poison_policy: PoisonPolicy::from_args(&matches),
+    };
 
     let api_service =
         OpenApiService::new(Api, "Hello World", "1.0").server("http://localhost:3000");
@@ -165,15 +189,7 @@ async fn main() {
     let mut handles = vec![];
 
     handles.push(tokio::spawn(async move {
-        consumer::start(
-            &matches
-                .remove_one::<String>("broker-list")
-                .expect("required"),
-            &matches.remove_one::<String>("group-id").expect("required"),
-            &[&matches.remove_one::<String>("topic").expect("required")],
-            experiments,
-        )
-        .await
+        consumer::start(consumer_config, experiments).await
     }));
     handles.push(tokio::spawn(async move {
         Server::new(TcpListener::bind("0.0.0.0:3003"))